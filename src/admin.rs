@@ -0,0 +1,289 @@
+//! Server administration, the same commands the `mysqladmin` tool sends.
+//!
+//! Get an `Admin` with `Connection::admin()`.  Most of these need privileges such as PROCESS,
+//! SUPER, SHUTDOWN or RELOAD, and fail with the server's message without them.  They all need
+//! the client library backend.
+
+use ::connection::Connection;
+use ::cstr::{from_cstr, to_cstr};
+use ::ffi::mysql::{MYSQL, mysql_list_processes, mysql_list_dbs, mysql_kill, mysql_shutdown, mysql_refresh, mysql_stat, mysql_dump_debug_info,
+                   SHUTDOWN_DEFAULT, SHUTDOWN_WAIT_CONNECTIONS, SHUTDOWN_WAIT_TRANSACTIONS, SHUTDOWN_WAIT_UPDATES, SHUTDOWN_WAIT_ALL_BUFFERS, SHUTDOWN_WAIT_CRITICAL_BUFFERS};
+use ::libc::{c_uint, c_ulong};
+use ::result::Rows;
+use ::std::ptr;
+use ::std::str::FromStr;
+
+/// One connection to the server, as listed by `SHOW PROCESSLIST`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Process {
+    /// The connection id, which `kill()` takes.
+    pub id: u64,
+    /// The user it logged in as.
+    pub user: String,
+    /// The client host and port.
+    pub host: String,
+    /// The current database, if there is one.
+    pub db: Option<String>,
+    /// What it is doing, such as "Query" or "Sleep".
+    pub command: String,
+    /// Seconds it has been in its current state.
+    pub time: u64,
+    /// What the current command is waiting on, if anything.
+    pub state: Option<String>,
+    /// The statement being run, if any.  Cut short by the server past 100 characters.
+    pub info: Option<String>,
+}
+
+/// How long `shutdown()` lets the server wait before stopping.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShutdownLevel {
+    /// The server's default.
+    Default,
+    /// Wait for existing connections to finish.
+    WaitConnections,
+    /// Wait for existing transactions to finish.
+    WaitTransactions,
+    /// Wait for existing updates to finish.
+    WaitUpdates,
+    /// Flush all buffers first.
+    WaitAllBuffers,
+    /// Flush the critical buffers first.
+    WaitCriticalBuffers,
+}
+
+impl ShutdownLevel {
+    fn to_raw(self) -> c_uint {
+        match self {
+            ShutdownLevel::Default             => SHUTDOWN_DEFAULT,
+            ShutdownLevel::WaitConnections     => SHUTDOWN_WAIT_CONNECTIONS,
+            ShutdownLevel::WaitTransactions    => SHUTDOWN_WAIT_TRANSACTIONS,
+            ShutdownLevel::WaitUpdates         => SHUTDOWN_WAIT_UPDATES,
+            ShutdownLevel::WaitAllBuffers      => SHUTDOWN_WAIT_ALL_BUFFERS,
+            ShutdownLevel::WaitCriticalBuffers => SHUTDOWN_WAIT_CRITICAL_BUFFERS,
+        }
+    }
+}
+
+/// What `refresh()` should flush or reset.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Refresh {
+    /// Reload the grant tables. (REFRESH_GRANT)
+    Grant,
+    /// Flush the logs. (REFRESH_LOG)
+    Log,
+    /// Close all open tables. (REFRESH_TABLES)
+    Tables,
+    /// Empty the host cache. (REFRESH_HOSTS)
+    Hosts,
+    /// Reset the status variables. (REFRESH_STATUS)
+    Status,
+    /// Empty the thread cache. (REFRESH_THREADS)
+    Threads,
+    /// Reset the replica's position. (REFRESH_SLAVE)
+    Replica,
+    /// Remove the binary logs and start a new one. (REFRESH_MASTER)
+    Master,
+}
+
+impl Refresh {
+    fn to_raw(self) -> c_uint {
+        match self {
+            Refresh::Grant   => 1,
+            Refresh::Log     => 2,
+            Refresh::Tables  => 4,
+            Refresh::Hosts   => 8,
+            Refresh::Status  => 16,
+            Refresh::Threads => 32,
+            Refresh::Replica => 64,
+            Refresh::Master  => 128,
+        }
+    }
+}
+
+/// The counters from `mysql_stat`, the same line `mysqladmin status` prints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerStatus {
+    /// Seconds since the server started.
+    pub uptime: u64,
+    /// Open connections.
+    pub threads: u64,
+    /// Statements run since the server started.
+    pub questions: u64,
+    /// Statements that took longer than `long_query_time`.
+    pub slow_queries: u64,
+    /// Tables opened since the server started.
+    pub opens: u64,
+    /// Flush, refresh and reload commands run.
+    pub flush_tables: u64,
+    /// Tables open now.
+    pub open_tables: u64,
+    /// Average statements per second since the server started.
+    pub queries_per_second: f64,
+    /// The line as the server sent it.
+    pub raw: String,
+}
+
+impl ServerStatus {
+    /// Parses "Uptime: 5  Threads: 1  Questions: 3 ...".  Counters missing from the line are 0.
+    fn parse(raw: &str) -> Result<Self, String> {
+        let mut status = ServerStatus {
+            uptime: 0, threads: 0, questions: 0, slow_queries: 0, opens: 0, flush_tables: 0,
+            open_tables: 0, queries_per_second: 0.0, raw: raw.to_string(),
+        };
+        for part in raw.split("  ").map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let mut kv = part.splitn(2, ':');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => (k.trim(), v.trim()),
+                _                  => return Err(format!("Invalid status counter: {}", part)),
+            };
+            let count = || u64::from_str(value).map_err(|_| format!("Invalid value for {}: {}", key, value));
+            match key {
+                "Uptime"       => status.uptime = try!(count()),
+                "Threads"      => status.threads = try!(count()),
+                "Questions"    => status.questions = try!(count()),
+                "Slow queries" => status.slow_queries = try!(count()),
+                "Opens"        => status.opens = try!(count()),
+                "Flush tables" => status.flush_tables = try!(count()),
+                "Open tables"  => status.open_tables = try!(count()),
+                "Queries per second avg" => {
+                    status.queries_per_second = try!(f64::from_str(value).map_err(|_| format!("Invalid value for {}: {}", key, value)));
+                },
+                //Debug builds of the server add memory counters, which aren't needed here.
+                _ => {},
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Administration commands for a connection.
+pub struct Admin<'a> {
+    conn: &'a Connection,
+}
+
+impl<'a> Admin<'a> {
+    fn handle(&self) -> Result<*mut MYSQL, String> {
+        self.conn.client_handle("Administration")
+    }
+
+    fn failed(&self, what: &str) -> String {
+        format!("{} failed. Reason: {}", what, self.conn.last_error())
+    }
+
+    /// Every connection to the server.  Without the PROCESS privilege, only your own.
+    pub fn processlist(&self) -> Result<Vec<Process>, String> {
+        let res = unsafe { mysql_list_processes(try!(self.handle())) };
+        if res.is_null() {
+            return Err(self.failed("Listing processes"));
+        }
+        let rows = Rows::new(self.conn, res);
+        let names: Vec<String> = rows.fields().iter().map(|f| f.name.to_lowercase()).collect();
+        let column = |name: &str| names.iter().position(|n| n == name);
+        let columns = [column("id"), column("user"), column("host"), column("db"), column("command"),
+                       column("time"), column("state"), column("info")];
+        let mut processes = Vec::new();
+        for row in rows {
+            let row = try!(row);
+            let cell = |i: usize| columns[i].and_then(|c| row.get(c).cloned()).and_then(|c| c);
+            let number = |i: usize| cell(i).and_then(|v| u64::from_str(&v).ok()).unwrap_or(0);
+            processes.push(Process {
+                id: number(0),
+                user: cell(1).unwrap_or_default(),
+                host: cell(2).unwrap_or_default(),
+                db: cell(3),
+                command: cell(4).unwrap_or_default(),
+                time: number(5),
+                state: cell(6).filter(|s| !s.is_empty()),
+                info: cell(7),
+            });
+        }
+        Ok(processes)
+    }
+
+    /// Kills another connection.
+    /// Refuses to kill this connection, which would leave it unusable.
+    pub fn kill(&self, id: u64) -> Result<(), String> {
+        if id == self.conn.thread_id() {
+            return Err("Refusing to kill the connection the command was sent on.".to_string());
+        }
+        if unsafe { mysql_kill(try!(self.handle()), id as c_ulong) } != 0 {
+            return Err(self.failed(&format!("Killing connection {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Asks the server to shut down.
+    pub fn shutdown(&self, level: ShutdownLevel) -> Result<(), String> {
+        if unsafe { mysql_shutdown(try!(self.handle()), level.to_raw()) } != 0 {
+            return Err(self.failed("Shutdown"));
+        }
+        Ok(())
+    }
+
+    /// Flushes or resets everything listed, in one command.
+    pub fn refresh(&self, what: &[Refresh]) -> Result<(), String> {
+        let flags = what.iter().fold(0, |flags, r| flags | r.to_raw());
+        if flags == 0 {
+            return Ok(());
+        }
+        if unsafe { mysql_refresh(try!(self.handle()), flags) } != 0 {
+            return Err(self.failed("Refresh"));
+        }
+        Ok(())
+    }
+
+    /// The server's status counters.
+    pub fn stat(&self) -> Result<ServerStatus, String> {
+        let raw = unsafe { mysql_stat(try!(self.handle())) };
+        if raw.is_null() {
+            return Err(self.failed("Reading server status"));
+        }
+        ServerStatus::parse(&from_cstr(raw))
+    }
+
+    /// Makes the server write debugging information to its error log.
+    pub fn dump_debug_info(&self) -> Result<(), String> {
+        if unsafe { mysql_dump_debug_info(try!(self.handle())) } != 0 {
+            return Err(self.failed("Dumping debug info"));
+        }
+        Ok(())
+    }
+
+    /// The databases on the server, optionally only those matching a `LIKE` pattern.
+    pub fn list_dbs(&self, pattern: Option<&str>) -> Result<Vec<String>, String> {
+        let pattern = match pattern {
+            Some(p) => Some(try!(to_cstr(p))),
+            None    => None,
+        };
+        let res = unsafe { mysql_list_dbs(try!(self.handle()), pattern.as_ref().map_or(ptr::null(), |p| p.as_ptr())) };
+        if res.is_null() {
+            return Err(self.failed("Listing databases"));
+        }
+        let mut dbs = Vec::new();
+        for row in Rows::new(self.conn, res) {
+            if let Some(Some(name)) = try!(row).into_iter().next() {
+                dbs.push(name);
+            }
+        }
+        Ok(dbs)
+    }
+}
+
+impl Connection {
+    /// Server administration commands.
+    pub fn admin<'a>(&'a self) -> Admin<'a> {
+        Admin { conn: self }
+    }
+}
+
+#[test]
+fn test_parse_status() {
+    let status = ServerStatus::parse("Uptime: 3600  Threads: 2  Questions: 150  Slow queries: 1  Opens: 33  \
+                                      Flush tables: 1  Open tables: 26  Queries per second avg: 0.041").unwrap();
+    assert_eq!(status.uptime, 3600);
+    assert_eq!(status.threads, 2);
+    assert_eq!(status.slow_queries, 1);
+    assert_eq!(status.open_tables, 26);
+    assert_eq!(status.queries_per_second, 0.041);
+    assert!(ServerStatus::parse("Uptime: soon").is_err());
+}
@@ -0,0 +1,489 @@
+//! What a `Connection` sends its statements through.
+//!
+//! `FfiBackend`, over the client library, is what `Connection::new()` and
+//! `ConnectOptions::connect()` use, unless the crate is built without the `ffi` feature, in
+//! which case they use `NativeBackend`.  `MockBackend` answers from a script instead, so code
+//! built on `Connection` can be tested without a server.  Pass any backend to
+//! `ConnectOptions::connect_with()`.
+//!
+//! Only the core of `Connection` goes through the trait: queries and their rows, prepared
+//! statements, escaping, transactions, the current database and the character set.  Stored
+//! results, `LOAD DATA LOCAL INFILE`, `change_user()` and `Admin` need the client library, and
+//! fail with an error on any other backend.
+
+#[cfg(feature = "ffi")]
+use ::connection::open_handle;
+#[cfg(feature = "ffi")]
+use ::cstr::{Charset, from_cstr};
+#[cfg(feature = "ffi")]
+use ::ffi::mysql::{MYSQL, MYSQL_RES, mysql_close, mysql_query, mysql_error, mysql_errno, mysql_field_count, mysql_use_result,
+                   mysql_free_result, mysql_real_escape_string, mysql_select_db, mysql_character_set_name, mysql_set_character_set,
+                   mysql_get_server_info, mysql_thread_id, mysql_sqlstate};
+#[cfg(feature = "ffi")]
+use ::init::thread_init;
+#[cfg(feature = "ffi")]
+use ::libc::{c_char, c_ulong};
+use ::libc::c_void;
+use ::options::ConnectOptions;
+use ::query::{escape_string, quote_ident};
+use ::result::{Field, ExecResult};
+#[cfg(feature = "ffi")]
+use ::result::{read_fields, fetch_row, fetch_raw_row};
+#[cfg(feature = "ffi")]
+use ::statement::ClientStatement;
+use ::types::SQLType;
+#[cfg(feature = "ffi")]
+use ::std::collections::HashMap;
+use ::std::ptr;
+
+/// Client error code for "MySQL server has gone away". (CR_SERVER_GONE_ERROR)
+pub(crate) const CR_SERVER_GONE_ERROR: u32 = 2006;
+/// Client error code for "Lost connection to MySQL server during query". (CR_SERVER_LOST)
+pub(crate) const CR_SERVER_LOST: u32 = 2013;
+
+/// The error for a statement id the backend doesn't know, which happens once the connection has
+/// been opened again.
+pub(crate) fn unknown_statement(id: u32) -> String {
+    format!("There is no prepared statement {}, it was closed when the connection was opened again.", id)
+}
+
+/// A statement a backend has prepared, see `Backend::prepare()`.
+#[derive(Clone, Debug)]
+pub struct PreparedStatement {
+    /// What the backend calls the statement in the calls that use it.
+    pub id: u32,
+    /// How many `?` placeholders it has.
+    pub param_count: usize,
+    /// The columns it returns, empty if it doesn't return rows.
+    pub fields: Vec<Field>,
+}
+
+/// A way of talking to a server, or of pretending to.
+///
+/// A statement that returns rows leaves them to be read with `next_row()`.  The next call to
+/// `query()` throws away any that weren't read.
+pub trait Backend: Send {
+    /// Opens the connection, or opens it again after it was lost, replacing the old one.
+    /// Applies the character set and session variables of the options.
+    fn connect(&mut self, opts: &ConnectOptions) -> Result<(), String>;
+
+    /// Runs a statement.  Returns its columns if it returned rows, or None if it didn't.
+    /// Errors are the server's message, without the query.
+    fn query(&mut self, sql: &str) -> Result<Option<Vec<Field>>, String>;
+
+    /// The next row of the current result, with NULL as None.  None once there are no more.
+    fn next_row(&mut self) -> Result<Option<Vec<Option<String>>>, String>;
+
+    /// The next row of the current result with each column's bytes as the server sent them, for
+    /// binary columns that `next_row()` can't decode as text.  By default, the bytes of what
+    /// `next_row()` returns.
+    fn next_raw_row(&mut self) -> Result<Option<Vec<Option<Vec<u8>>>>, String> {
+        let row = try!(self.next_row());
+        Ok(row.map(|cells| cells.into_iter().map(|cell| cell.map(String::into_bytes)).collect()))
+    }
+
+    /// Reads and throws away the rest of the current result.
+    fn finish(&mut self) -> Result<(), String> {
+        while try!(self.next_row()).is_some() {}
+        Ok(())
+    }
+
+    /// What the server reported about the last statement.
+    fn exec_result(&self) -> ExecResult;
+
+    /// The message of the last error.
+    fn last_error(&self) -> String;
+
+    /// The error code of the last error, or 0 if there wasn't one or the backend doesn't know.
+    fn last_errno(&self) -> u32 {
+        0
+    }
+
+    /// The SQLSTATE of the last error, such as "42S02".  "HY000", the catch-all, if the backend
+    /// doesn't know.
+    fn sqlstate(&self) -> String {
+        "HY000".to_string()
+    }
+
+    /// Returns true if the last call failed because the connection was lost.
+    fn connection_lost(&self) -> bool {
+        false
+    }
+
+    /// Escapes text for use between single quotes in a statement.
+    fn escape(&self, text: &str) -> String {
+        escape_string(text)
+    }
+
+    /// Starts a transaction.
+    fn begin_transaction(&mut self) -> Result<(), String> {
+        self.query("START TRANSACTION;").map(|_| ())
+    }
+
+    /// Commits the current transaction.
+    fn commit(&mut self) -> Result<(), String> {
+        self.query("COMMIT;").map(|_| ())
+    }
+
+    /// Rolls back the current transaction.
+    fn rollback(&mut self) -> Result<(), String> {
+        self.query("ROLLBACK;").map(|_| ())
+    }
+
+    /// Makes `db` the current database.  The name has already been checked.
+    fn select_db(&mut self, db: &str) -> Result<(), String> {
+        self.query(&format!("USE {};", quote_ident(db))).map(|_| ())
+    }
+
+    /// The name of the character set in use, such as "utf8mb4".
+    fn charset(&self) -> String {
+        "utf8mb4".to_string()
+    }
+
+    /// Changes the character set.  The name has already been checked.
+    fn set_charset(&mut self, name: &str) -> Result<(), String> {
+        self.query(&format!("SET NAMES {};", name)).map(|_| ())
+    }
+
+    /// The server's version string, such as "10.6.12-MariaDB", or empty if it isn't known.
+    fn server_version(&self) -> String {
+        String::new()
+    }
+
+    /// The connection id on the server, or 0 if there isn't one.
+    fn thread_id(&self) -> u64 {
+        0
+    }
+
+    /// Prepares a statement, with `?` where the parameters go.  Errors are the server's message.
+    /// By default there are no prepared statements.
+    fn prepare(&mut self, _sql: &str) -> Result<PreparedStatement, String> {
+        Err("This backend has no prepared statements.".to_string())
+    }
+
+    /// Executes a prepared statement with as many parameters as it takes.  Its rows, if it
+    /// returns any, are left to be read with `next_statement_row()`.  With `prefetch_rows` above
+    /// 0 they are read through a read-only server cursor that many at a time, and other
+    /// statements can run on the connection in between.
+    fn execute(&mut self, id: u32, _params: &[SQLType], _prefetch_rows: u64) -> Result<ExecResult, String> {
+        Err(unknown_statement(id))
+    }
+
+    /// The next row of the last execution of a prepared statement, with NULL as None.  None once
+    /// there are no more.
+    fn next_statement_row(&mut self, id: u32) -> Result<Option<Vec<Option<String>>>, String> {
+        Err(unknown_statement(id))
+    }
+
+    /// Throws away the rest of the rows of a prepared statement's last execution, and closes its
+    /// cursor if it has one.
+    fn free_statement_result(&mut self, _id: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Closes a prepared statement.
+    fn close_statement(&mut self, _id: u32) {}
+
+    /// The client library's handle, for the parts of `Connection` that need the client library.
+    /// Null for every backend but `FfiBackend`.
+    #[doc(hidden)]
+    fn raw_handle(&self) -> *mut c_void {
+        ptr::null_mut()
+    }
+}
+
+/// The client library, libmysqlclient or libmariadb.  Rows are streamed with `mysql_use_result`.
+/// Built with the `ffi` cargo feature, on by default.
+#[cfg(feature = "ffi")]
+pub struct FfiBackend {
+    conn: *mut MYSQL,
+    res: *mut MYSQL_RES,
+    width: usize,
+    statements: HashMap<u32, ClientStatement>,
+    next_statement: u32,
+    /// The code and SQLSTATE of the last statement error, which the client library keeps on the
+    /// statement rather than the connection.
+    statement_error: Option<(u32, String)>,
+}
+
+#[cfg(feature = "ffi")]
+impl FfiBackend {
+    /// A backend that isn't connected yet.
+    pub fn new() -> Self {
+        FfiBackend { conn: ptr::null_mut(), res: ptr::null_mut(), width: 0, statements: HashMap::new(), next_statement: 1,
+                     statement_error: None }
+    }
+
+    fn handle(&self) -> Result<*mut MYSQL, String> {
+        if self.conn.is_null() {
+            return Err("Not connected.".to_string());
+        }
+        try!(thread_init());
+        Ok(self.conn)
+    }
+
+    fn text_charset(&self) -> Charset {
+        Charset::from_name(&self.charset())
+    }
+
+    /// Called when there is no next row.
+    fn end_of_rows(&mut self) -> Result<(), String> {
+        self.free_result();
+        //A NULL row is either the end, or a dropped connection part way through.
+        if unsafe { mysql_errno(self.conn) } != 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+
+    fn free_result(&mut self) {
+        if !self.res.is_null() {
+            //Also reads and throws away any rows that weren't fetched.
+            unsafe { mysql_free_result(self.res) };
+            self.res = ptr::null_mut();
+        }
+    }
+
+    /// Gets ready to send a command: throws away the rows still being read, of a query or of
+    /// statements without a cursor, and forgets the last statement error.
+    fn start_command(&mut self) -> Result<*mut MYSQL, String> {
+        let conn = try!(self.handle());
+        self.free_result();
+        for statement in self.statements.values_mut() {
+            statement.free_streamed_result();
+        }
+        self.statement_error = None;
+        Ok(conn)
+    }
+
+    /// Runs `call` on the statement with this id, recording its error code if it fails.
+    fn with_statement<T, F>(&mut self, id: u32, call: F) -> Result<T, String>
+        where F: FnOnce(&mut ClientStatement, *mut MYSQL) -> Result<T, String> {
+        let conn = try!(self.handle());
+        let statement = match self.statements.get_mut(&id) {
+            Some(statement) => statement,
+            None            => return Err(unknown_statement(id)),
+        };
+        let result = call(statement, conn);
+        if result.is_err() && statement.errno() != 0 {
+            self.statement_error = Some((statement.errno(), statement.sqlstate()));
+        }
+        result
+    }
+
+    /// Closes the statements and the connection.
+    fn close(&mut self) {
+        self.free_result();
+        //Statements have to go before the connection they were prepared on.
+        self.statements.clear();
+        unsafe { mysql_close(self.conn) };
+    }
+}
+
+#[cfg(feature = "ffi")]
+impl Default for FfiBackend {
+    fn default() -> Self {
+        FfiBackend::new()
+    }
+}
+
+#[cfg(feature = "ffi")]
+impl Backend for FfiBackend {
+    fn connect(&mut self, opts: &ConnectOptions) -> Result<(), String> {
+        let conn = try!(open_handle(opts));
+        if !self.conn.is_null() {
+            self.close();
+        }
+        self.conn = conn;
+        Ok(())
+    }
+
+    fn query(&mut self, sql: &str) -> Result<Option<Vec<Field>>, String> {
+        let conn = try!(self.start_command());
+        let charset = self.text_charset();
+        let sql_c = try!(charset.to_cstr(sql));
+        if unsafe { mysql_query(conn, sql_c.as_ptr()) } != 0 {
+            return Err(self.last_error());
+        }
+        if unsafe { mysql_field_count(conn) } == 0 {
+            return Ok(None);
+        }
+        self.res = unsafe { mysql_use_result(conn) };
+        if self.res.is_null() {
+            return Err(self.last_error());
+        }
+        let fields = read_fields(self.res, charset);
+        self.width = fields.len();
+        Ok(Some(fields))
+    }
+
+    fn next_row(&mut self) -> Result<Option<Vec<Option<String>>>, String> {
+        if self.res.is_null() {
+            return Ok(None);
+        }
+        if let Some(row) = fetch_row(self.res, self.width, self.text_charset()) {
+            return Ok(Some(row));
+        }
+        self.end_of_rows().map(|_| None)
+    }
+
+    fn next_raw_row(&mut self) -> Result<Option<Vec<Option<Vec<u8>>>>, String> {
+        if self.res.is_null() {
+            return Ok(None);
+        }
+        if let Some(row) = fetch_raw_row(self.res, self.width) {
+            return Ok(Some(row));
+        }
+        self.end_of_rows().map(|_| None)
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.free_result();
+        Ok(())
+    }
+
+    fn exec_result(&self) -> ExecResult {
+        ExecResult::from_handle(self.conn)
+    }
+
+    fn last_error(&self) -> String {
+        if self.conn.is_null() {
+            return "Not connected.".to_string();
+        }
+        from_cstr(unsafe { mysql_error(self.conn) })
+    }
+
+    fn last_errno(&self) -> u32 {
+        if self.conn.is_null() {
+            return 0;
+        }
+        if let Some((errno, _)) = self.statement_error {
+            return errno;
+        }
+        unsafe { mysql_errno(self.conn) }
+    }
+
+    fn sqlstate(&self) -> String {
+        if self.conn.is_null() {
+            return "HY000".to_string();
+        }
+        if let Some((_, ref sqlstate)) = self.statement_error {
+            return sqlstate.clone();
+        }
+        from_cstr(unsafe { mysql_sqlstate(self.conn) })
+    }
+
+    fn connection_lost(&self) -> bool {
+        let errno = self.last_errno();
+        errno == CR_SERVER_GONE_ERROR || errno == CR_SERVER_LOST
+    }
+
+    /// Escapes in the connection's character set, which matters for multi-byte sets such as sjis.
+    fn escape(&self, text: &str) -> String {
+        let conn = match self.handle() {
+            Ok(conn) => conn,
+            Err(_)   => return escape_string(text),
+        };
+        let charset = self.text_charset();
+        let bytes = match charset.encode(text) {
+            Ok(bytes) => bytes,
+            Err(_)    => return escape_string(text),
+        };
+        let mut out = vec![0u8; bytes.len() * 2 + 1];
+        let length = unsafe { mysql_real_escape_string(conn, out.as_mut_ptr() as *mut c_char, bytes.as_ptr() as *const c_char,
+                                                       bytes.len() as c_ulong) };
+        out.truncate(length as usize);
+        charset.decode(&out)
+    }
+
+    fn select_db(&mut self, db: &str) -> Result<(), String> {
+        let conn = try!(self.handle());
+        let db_c = try!(self.text_charset().to_cstr(db));
+        if unsafe { mysql_select_db(conn, db_c.as_ptr()) } != 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+
+    fn charset(&self) -> String {
+        if self.conn.is_null() {
+            return "utf8mb4".to_string();
+        }
+        from_cstr(unsafe { mysql_character_set_name(self.conn) })
+    }
+
+    /// Uses `mysql_set_character_set`, so the client library knows about the change too.
+    fn set_charset(&mut self, name: &str) -> Result<(), String> {
+        let conn = try!(self.handle());
+        let name_c = try!(::cstr::to_cstr(name));
+        if unsafe { mysql_set_character_set(conn, name_c.as_ptr()) } != 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+
+    fn server_version(&self) -> String {
+        if self.conn.is_null() {
+            return String::new();
+        }
+        from_cstr(unsafe { mysql_get_server_info(self.conn) })
+    }
+
+    fn thread_id(&self) -> u64 {
+        if self.conn.is_null() {
+            return 0;
+        }
+        (unsafe { mysql_thread_id(self.conn) }) as u64
+    }
+
+    fn prepare(&mut self, sql: &str) -> Result<PreparedStatement, String> {
+        let conn = try!(self.start_command());
+        let (statement, param_count, fields) = try!(ClientStatement::prepare(conn, sql, self.text_charset()));
+        let id = self.next_statement;
+        self.next_statement = self.next_statement.wrapping_add(1);
+        self.statements.insert(id, statement);
+        Ok(PreparedStatement { id: id, param_count: param_count, fields: fields })
+    }
+
+    fn execute(&mut self, id: u32, params: &[SQLType], prefetch_rows: u64) -> Result<ExecResult, String> {
+        try!(self.start_command());
+        self.with_statement(id, |statement, conn| statement.execute(conn, params, prefetch_rows))
+    }
+
+    fn next_statement_row(&mut self, id: u32) -> Result<Option<Vec<Option<String>>>, String> {
+        self.statement_error = None;
+        self.with_statement(id, |statement, _| statement.next_row())
+    }
+
+    fn free_statement_result(&mut self, id: u32) -> Result<(), String> {
+        self.with_statement(id, |statement, _| {
+            statement.free_result();
+            Ok(())
+        })
+    }
+
+    fn close_statement(&mut self, id: u32) {
+        let _ = thread_init();
+        self.statements.remove(&id);
+    }
+
+    fn raw_handle(&self) -> *mut c_void {
+        self.conn as *mut c_void
+    }
+}
+
+//The handle is only ever used by one thread at a time, and thread_init() refuses to open a
+//connection unless the client library is thread-safe.
+#[cfg(feature = "ffi")]
+unsafe impl Send for FfiBackend {}
+
+#[cfg(feature = "ffi")]
+impl Drop for FfiBackend {
+    fn drop(&mut self) {
+        if self.conn.is_null() {
+            return;
+        }
+        let _ = thread_init();
+        self.close();
+    }
+}
@@ -0,0 +1,182 @@
+//! Stopping statements that run for too long.
+//!
+//! There are three ways, from the bluntest:
+//!
+//! * `ConnectOptions::read_timeout()` makes the client give up waiting for an answer.  The
+//!   statement keeps running on the server and the connection is lost.
+//! * `Connection::raw_query_timeout()` and friends have the server stop the statement itself,
+//!   with `SET STATEMENT max_statement_time=... FOR` on MariaDB, or the `MAX_EXECUTION_TIME`
+//!   hint for a `SELECT` on MySQL.  The statement fails and the connection can be used again.
+//! * A `CancelHandle` taken from the connection can be sent to another thread, which sends
+//!   `KILL QUERY` for the connection over a connection of its own, for instance from the
+//!   watchdog `cancel_after()` starts.
+
+use ::connection::Connection;
+use ::info::ServerInfo;
+use ::options::ConnectOptions;
+use ::reconnect::ReconnectPolicy;
+use ::result::{ExecResult, Rows};
+use ::std::sync::Arc;
+use ::std::sync::atomic::{AtomicU64, Ordering};
+use ::std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use ::std::thread::{self, JoinHandle};
+use ::std::time::Duration;
+
+/// The statement with a limit on how long the server lets it run.
+fn with_statement_timeout(info: &ServerInfo, query: &str, timeout: Duration) -> Result<String, String> {
+    if timeout == Duration::from_secs(0) {
+        //Both servers take a limit of zero to mean none.
+        return Err("A statement timeout has to be longer than zero.".to_string());
+    }
+    if info.supports_max_statement_time() {
+        return Ok(format!("SET STATEMENT max_statement_time={}.{:06} FOR {}", timeout.as_secs(), timeout.subsec_micros(), query));
+    }
+    let trimmed = query.trim_start();
+    //Once the first six bytes are known to be ASCII, slicing after them is safe.
+    let is_select = trimmed.get(..6).is_some_and(|word| word.eq_ignore_ascii_case("SELECT"))
+        && !trimmed[6..].starts_with(|c: char| c.is_alphanumeric() || c == '_');
+    if info.supports_max_execution_time() && is_select {
+        let millis = (timeout.as_secs() * 1000 + timeout.subsec_millis() as u64).max(1);
+        return Ok(format!("SELECT /*+ MAX_EXECUTION_TIME({}) */{}", millis, &trimmed[6..]));
+    }
+    Err(format!("Statement timeouts need MariaDB 10.1.2 or later, or a SELECT on MySQL 5.7.8 or later, not {}.", info.version_string))
+}
+
+impl Connection {
+    /// Like `raw_query`, but the server stops the statement if it runs longer than `timeout`.
+    /// See `raw_query_no_res_timeout()` for when this works.
+    pub fn raw_query_timeout(&self, query: &str, wide: isize, timeout: Duration) -> Result<Vec<Vec<String>>, String> {
+        let query = try!(with_statement_timeout(&self.server_info(), query, timeout));
+        self.raw_query(&query, wide)
+    }
+
+    /// Like `query_iter`, but the server stops the statement if it runs longer than `timeout`.
+    /// See `raw_query_no_res_timeout()` for when this works.
+    pub fn query_iter_timeout<'a>(&'a self, query: &str, timeout: Duration) -> Result<Rows<'a>, String> {
+        let query = try!(with_statement_timeout(&self.server_info(), query, timeout));
+        self.query_iter(&query)
+    }
+
+    /// Like `raw_query_no_res`, but the server stops the statement if it runs longer than
+    /// `timeout`, and it fails.  Works on MariaDB 10.1.2 and later, and for `SELECT` on MySQL
+    /// 5.7.8 and later; anything else is an error without sending the statement.
+    pub fn raw_query_no_res_timeout(&self, query: &str, timeout: Duration) -> Result<ExecResult, String> {
+        let query = try!(with_statement_timeout(&self.server_info(), query, timeout));
+        self.raw_query_no_res(&query)
+    }
+
+    /// A handle for stopping whatever statement this connection is running, from another
+    /// thread.  It keeps working after the connection is re-established.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        let mut opts = self.opts.clone();
+        //The side connection only sends one statement, and shouldn't depend on the database.
+        opts.database = String::new();
+        opts.reconnect = ReconnectPolicy::never();
+        CancelHandle { opts: opts, thread_id: self.cancel_id.clone() }
+    }
+}
+
+/// Stops the statement a connection is running, by sending `KILL QUERY` over a connection of
+/// its own.  Take one with `Connection::cancel_handle()`; it can be cloned and sent to other
+/// threads.
+///
+/// The side connection is opened through the client library with the same options, so the
+/// user needs to be allowed to kill its own queries, which every user is.  If the statement
+/// finished just before, the next one the connection runs may be stopped instead.
+#[derive(Clone, Debug)]
+pub struct CancelHandle {
+    opts: ConnectOptions,
+    thread_id: Arc<AtomicU64>,
+}
+
+impl CancelHandle {
+    /// The connection id the statement is stopped on.  0 if the backend doesn't report one.
+    pub fn thread_id(&self) -> u64 {
+        self.thread_id.load(Ordering::SeqCst)
+    }
+
+    /// Stops the statement the connection is running, if any.  The statement fails with
+    /// "Query execution was interrupted" and the connection can be used again.
+    pub fn cancel(&self) -> Result<(), String> {
+        let id = self.thread_id();
+        if id == 0 {
+            return Err("The connection id isn't known, so its statement can't be stopped.".to_string());
+        }
+        let side = try!(self.opts.connect().map_err(|e| format!("Failed to connect to stop the statement. Reason: {}", e)));
+        try!(side.raw_query_no_res(&format!("KILL QUERY {};", id)));
+        Ok(())
+    }
+
+    /// Starts a thread that calls `cancel()` once `timeout` has passed, unless the returned
+    /// `Watchdog` is disarmed or dropped first.
+    pub fn cancel_after(&self, timeout: Duration) -> Watchdog {
+        let (disarm, disarmed) = mpsc::channel::<()>();
+        let handle = self.clone();
+        let thread = thread::spawn(move || match disarmed.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => handle.cancel().map(|_| true),
+            _                              => Ok(false),
+        });
+        Watchdog { disarm: Some(disarm), thread: Some(thread) }
+    }
+}
+
+/// A thread waiting to stop a statement, started by `CancelHandle::cancel_after()`.  Dropping
+/// it disarms it.
+#[derive(Debug)]
+pub struct Watchdog {
+    disarm: Option<Sender<()>>,
+    thread: Option<JoinHandle<Result<bool, String>>>,
+}
+
+impl Watchdog {
+    /// Stops the watchdog.  Returns true if it had already stopped the statement, and the error
+    /// if it tried and failed.
+    pub fn disarm(mut self) -> Result<bool, String> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<bool, String> {
+        //Dropping the sender wakes the thread up.
+        self.disarm.take();
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| Err("The watchdog thread panicked.".to_string())),
+            None         => Ok(false),
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[test]
+fn test_cancel() {
+    use ::info::{Capabilities, Flavor, Version};
+    use ::mock::MockBackend;
+
+    let mysql = ServerInfo { version: Version::new(8, 0, 33), version_string: "8.0.33".to_string(), flavor: Flavor::MySQL,
+                             protocol_version: 10, host_info: String::new(), thread_id: 0, capabilities: Capabilities(0) };
+    assert_eq!(with_statement_timeout(&mysql, " select * FROM t", Duration::from_millis(1500)).unwrap(),
+               "SELECT /*+ MAX_EXECUTION_TIME(1500) */ * FROM t");
+    assert!(with_statement_timeout(&mysql, "SELECTED", Duration::from_secs(1)).is_err());
+    assert!(with_statement_timeout(&mysql, "UPDATE t SET a = 1", Duration::from_secs(1)).is_err());
+    assert!(with_statement_timeout(&mysql, "CALL émettre()", Duration::from_secs(1)).is_err());
+    assert!(with_statement_timeout(&mysql, "SELECTé", Duration::from_secs(1)).is_err());
+
+    let mock = MockBackend::new();
+    let log = mock.log();
+    let conn = ConnectOptions::new("mock", "", "", "").connect_with(mock).unwrap();
+    assert!(conn.raw_query_no_res_timeout("SELECT 1;", Duration::from_secs(0)).is_err());
+    conn.raw_query_no_res_timeout("UPDATE t SET a = 1;", Duration::from_millis(250)).unwrap();
+    assert_eq!(log.queries().last().unwrap(), "SET STATEMENT max_statement_time=0.250000 FOR UPDATE t SET a = 1;");
+
+    //The mock has no connection id, so there is nothing to kill.
+    let handle = conn.cancel_handle();
+    assert!(handle.cancel().is_err());
+    assert_eq!(handle.cancel_after(Duration::from_secs(60)).disarm(), Ok(false));
+    let watchdog = handle.cancel_after(Duration::from_millis(1));
+    thread::sleep(Duration::from_millis(50));
+    assert!(watchdog.disarm().is_err());
+}
@@ -1,20 +1,142 @@
-//! A wrapper for C strings.
-//! Probably won't work well if you use non-ASCII characters.
+//! Conversions between Rust strings and the C strings of the client library.
+//!
+//! Text sent to the server is encoded in the connection's character set, and text read back is
+//! decoded from it.  UTF-8 (utf8mb4 is the default for every connection), latin1 and ascii are
+//! converted exactly.  Other character sets only pass ASCII through, so use utf8mb4 if the data
+//! isn't Western European.
 
 use ::libc::c_char;
+use ::std::ffi::{CStr, CString};
 
-pub fn from_cstr(str_in: *const c_char) -> String {
-    let mut str_out = String::new();
-    let mut pos: isize = 0;
-    while unsafe { *str_in.offset(pos) } != 0 {
-        str_out.push(unsafe { *str_in.offset(pos) } as u8 as char);
-        pos += 1;
+/// The character sets this crate knows how to convert.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Charset {
+    /// utf8mb4, utf8 and utf8mb3.
+    Utf8,
+    /// latin1, which MySQL treats as Windows-1252.
+    Latin1,
+    /// ascii.
+    Ascii,
+    /// binary.  Decoded as UTF-8, with anything invalid replaced.
+    Binary,
+    /// Any other character set.  Only ASCII is converted.
+    Other,
+}
+
+/// What the client library reports about the character set of a connection.
+#[derive(Clone, Debug)]
+pub struct CharsetInfo {
+    /// The character set name, such as "utf8mb4".
+    pub name: String,
+    /// The name of the default collation.
+    pub collation: String,
+    /// The collation number.
+    pub number: u32,
+    /// The fewest bytes a character can take.
+    pub mbminlen: u32,
+    /// The most bytes a character can take.
+    pub mbmaxlen: u32,
+}
+
+/// Windows-1252 characters for the bytes 0x80 to 0x9F.  The five bytes it leaves undefined map
+/// to the matching control character, the same as the server does.
+const CP1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl Charset {
+    /// The character set with the given server name.
+    pub fn from_name(name: &str) -> Charset {
+        match &*name.to_lowercase() {
+            "utf8mb4" | "utf8" | "utf8mb3" => Charset::Utf8,
+            "latin1"                       => Charset::Latin1,
+            "ascii"                        => Charset::Ascii,
+            "binary"                       => Charset::Binary,
+            _                              => Charset::Other,
+        }
+    }
+
+    /// Turns text from the server into a String.  Bytes that aren't valid in the character set
+    /// become U+FFFD, so binary data should be kept as bytes, as `Rows::next_raw()` does,
+    /// rather than decoded here.
+    pub fn decode(&self, bytes_in: &[u8]) -> String {
+        match *self {
+            Charset::Utf8 | Charset::Binary => String::from_utf8_lossy(bytes_in).into_owned(),
+            Charset::Latin1 => bytes_in.iter().map(|&b| match b {
+                0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize],
+                _           => b as char,
+            }).collect(),
+            Charset::Ascii | Charset::Other => bytes_in.iter()
+                .map(|&b| if b < 0x80 { b as char } else { '\u{FFFD}' }).collect(),
+        }
+    }
+
+    /// Turns a string into bytes for the server.  Fails on a character the character set can't
+    /// hold, rather than sending something else in its place.
+    pub fn encode(&self, str_in: &str) -> Result<Vec<u8>, String> {
+        match *self {
+            Charset::Utf8 | Charset::Binary => Ok(str_in.as_bytes().to_vec()),
+            Charset::Latin1 => str_in.chars().map(|c| {
+                let code = c as u32;
+                if code < 0x80 || (0xA0..=0xFF).contains(&code) {
+                    Ok(code as u8)
+                } else {
+                    CP1252_HIGH.iter().position(|&h| h == c).map(|i| 0x80 + i as u8)
+                        .ok_or_else(|| format!("The character '{}' can't be sent in the latin1 character set.", c))
+                }
+            }).collect(),
+            Charset::Ascii | Charset::Other => {
+                match str_in.chars().find(|c| !c.is_ascii()) {
+                    Some(c) => Err(format!("The character '{}' can't be sent in the connection's character set.", c)),
+                    None    => Ok(str_in.as_bytes().to_vec()),
+                }
+            }
+        }
+    }
+
+    /// Encodes a string and adds the terminating NUL.
+    pub fn to_cstr(&self, str_in: &str) -> Result<CString, String> {
+        bytes_to_cstr(try!(self.encode(str_in)))
     }
-    str_out
 }
-pub fn to_cstr(str_in: &str) -> Vec<c_char> {
-    let mut v: Vec<c_char> = str_in.chars().map(|c| c as c_char).collect();
-    v.push(0);
-    v
+
+fn bytes_to_cstr(bytes: Vec<u8>) -> Result<CString, String> {
+    CString::new(bytes).map_err(|e| format!("Strings passed to the client library can't contain a NUL byte (found one at byte {}).", e.nul_position()))
+}
+
+/// The bytes of a C string, without the terminating NUL.  A null pointer gives no bytes.
+pub fn cstr_bytes<'a>(str_in: *const c_char) -> &'a [u8] {
+    if str_in.is_null() {
+        &[]
+    } else {
+        unsafe { CStr::from_ptr(str_in) }.to_bytes()
+    }
 }
 
+/// Copies a C string from the client library, such as an error message, into a String.
+/// It is read as UTF-8, and a null pointer gives an empty string.
+pub fn from_cstr(str_in: *const c_char) -> String {
+    String::from_utf8_lossy(cstr_bytes(str_in)).into_owned()
+}
+
+/// Makes a UTF-8 C string for the client library.  Fails if the string contains a NUL, which
+/// would otherwise cut it short.
+pub fn to_cstr(str_in: &str) -> Result<CString, String> {
+    bytes_to_cstr(str_in.as_bytes().to_vec())
+}
+
+#[test]
+fn test_charsets() {
+    assert!(to_cstr("nul\0inside").is_err());
+    assert_eq!(from_cstr(to_cstr("héllo 世界 🦀").unwrap().as_ptr()), "héllo 世界 🦀");
+    assert_eq!(from_cstr(::std::ptr::null()), "");
+
+    assert_eq!(Charset::from_name("UTF8MB4"), Charset::Utf8);
+    assert_eq!(Charset::Latin1.decode(&[0x63, 0x61, 0x66, 0xE9, 0x20, 0x80]), "café €");
+    assert_eq!(Charset::Latin1.encode("café €").unwrap(), vec![0x63, 0x61, 0x66, 0xE9, 0x20, 0x80]);
+    assert!(Charset::Latin1.encode("世界").is_err());
+    assert_eq!(Charset::Utf8.decode(&[0x61, 0xFF]), "a\u{FFFD}");
+}
@@ -0,0 +1,115 @@
+//! Exact decimal numbers, for `DECIMAL` columns.
+//!
+//! A `DECIMAL` can have 65 digits, more than any float or integer holds, so `Decimal` keeps
+//! the digits as the server sent them.  It does no arithmetic; convert to a float for that, or
+//! to an integer when there is no fraction.
+
+use ::std::fmt;
+use ::std::str::FromStr;
+
+/// A decimal number such as "-12.50", exactly as written.  Numbers with a different number of
+/// decimals, such as 1.5 and 1.50, are not equal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    /// The number without superfluous leading zeros or a sign for zero, as in "-12.50".
+    text: String,
+}
+
+impl Decimal {
+    /// How many digits there are after the point.
+    pub fn scale(&self) -> u32 {
+        self.text.find('.').map_or(0, |i| (self.text.len() - i - 1) as u32)
+    }
+
+    /// The number without its fraction, if every digit of the fraction is 0.
+    pub fn integer_part(&self) -> Option<&str> {
+        match self.text.find('.') {
+            Some(i) if self.text[i + 1..].bytes().all(|b| b == b'0') => Some(&self.text[..i]),
+            Some(_) => None,
+            None => Some(&self.text),
+        }
+    }
+
+    /// The closest float.
+    pub fn to_f64(&self) -> f64 {
+        //Any string of digits parses as a float, rounded if need be.
+        f64::from_str(&self.text).unwrap_or(0.0)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+    /// Reads an optional sign, digits, and optionally a point and more digits.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let trimmed = text.trim();
+        let (negative, unsigned) = match trimmed.as_bytes().first() {
+            Some(&b'-') => (true, &trimmed[1..]),
+            Some(&b'+') => (false, &trimmed[1..]),
+            _           => (false, trimmed),
+        };
+        let (whole, fraction) = match unsigned.find('.') {
+            Some(i) => (&unsigned[..i], Some(&unsigned[i + 1..])),
+            None    => (unsigned, None),
+        };
+        let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if !digits(whole) || !fraction.is_none_or(digits) || (whole.is_empty() && fraction.is_none_or(|f| f.is_empty())) {
+            return Err(format!("Invalid decimal: {}", text));
+        }
+        let whole = whole.trim_start_matches('0');
+        let mut out = String::with_capacity(unsigned.len() + 2);
+        let zero = whole.is_empty() && fraction.is_none_or(|f| f.bytes().all(|b| b == b'0'));
+        if negative && !zero {
+            out.push('-');
+        }
+        out.push_str(if whole.is_empty() { "0" } else { whole });
+        if let Some(fraction) = fraction.filter(|f| !f.is_empty()) {
+            out.push('.');
+            out.push_str(fraction);
+        }
+        Ok(Decimal { text: out })
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(n: i64) -> Self {
+        Decimal { text: n.to_string() }
+    }
+}
+
+impl From<u64> for Decimal {
+    fn from(n: u64) -> Self {
+        Decimal { text: n.to_string() }
+    }
+}
+
+impl From<i128> for Decimal {
+    fn from(n: i128) -> Self {
+        Decimal { text: n.to_string() }
+    }
+}
+
+impl From<u128> for Decimal {
+    fn from(n: u128) -> Self {
+        Decimal { text: n.to_string() }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[test]
+fn test_decimal() {
+    let d: Decimal = " -0012.50".parse().unwrap();
+    assert_eq!((d.to_string(), d.scale(), d.integer_part()), ("-12.50".to_string(), 2, None));
+    assert_eq!(d.to_f64(), -12.5);
+    assert_eq!("-0.00".parse::<Decimal>().unwrap().to_string(), "0.00");
+    assert_eq!("+.5".parse::<Decimal>().unwrap().to_string(), "0.5");
+    assert_eq!("42.000".parse::<Decimal>().unwrap().integer_part(), Some("42"));
+    assert_eq!("123456789012345678901234567890".parse::<Decimal>().unwrap(), Decimal::from(123456789012345678901234567890u128));
+    for bad in &["", "-", ".", "1e5", "1.2.3", "12a"] {
+        assert!(bad.parse::<Decimal>().is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,222 @@
+//! Streams the results of a query out as CSV, JSON Lines, or `INSERT` statements.
+//!
+//! Rows are written as they are read from the server, so exporting a huge table doesn't need
+//! to hold it in memory.  To export a whole table, pass what `Select::from(table).to_sql()`
+//! returns as the query.
+
+use ::connection::Connection;
+use ::cstr::Charset;
+use ::query::{quote_ident, escape_string, Select};
+use ::result::Field;
+use ::std::io::Write;
+
+/// Settings for `export_csv`.
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    /// Separates the fields of a row.  Defaults to a comma.
+    pub delimiter: char,
+    /// Written for a NULL column.  Defaults to `\N`, which `LOAD DATA` reads back as NULL.
+    /// A value that happens to equal the marker is quoted, so the two can be told apart.
+    pub null_marker: String,
+    /// Write the column names as the first line.  Defaults to true.
+    pub header: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            null_marker: "\\N".to_string(),
+            header: true,
+        }
+    }
+}
+
+/// Settings for `export_sql`.
+#[derive(Clone, Debug)]
+pub struct DumpOptions {
+    /// Start a new INSERT once a statement reaches this many bytes.  Defaults to 1MB, the same
+    /// as mysqldump's default `net_buffer_length`.
+    pub max_statement_len: usize,
+}
+
+impl Default for DumpOptions {
+    fn default() -> Self {
+        DumpOptions { max_statement_len: 1024 * 1024 }
+    }
+}
+
+fn write_str<W: Write>(out: &mut W, text: &str) -> Result<(), String> {
+    out.write_all(text.as_bytes()).map_err(|e| format!("Failed to write export. Reason: {}", e))
+}
+
+/// Quotes a CSV field as RFC 4180 asks, if it needs it.
+fn csv_field(value: &str, opts: &CsvOptions) -> String {
+    let needs_quotes = value == opts.null_marker ||
+        value.chars().any(|c| c == opts.delimiter || c == '"' || c == '\n' || c == '\r');
+    if needs_quotes {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Quotes a string for JSON.
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c    => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Renders a column value as a JSON value, as a number if the column is numeric.
+fn json_value(value: &Option<String>, field: &Field) -> String {
+    match *value {
+        None => "null".to_string(),
+        Some(ref v) => {
+            if field.field_type.is_numeric() {
+                v.clone()
+            } else {
+                json_string(v)
+            }
+        }
+    }
+}
+
+/// Renders a column value as a SQL literal, in the same way mysqldump does with `--hex-blob`:
+/// bytes as a hex literal so they survive whatever character set the dump is loaded with.
+fn sql_value(value: &Option<Vec<u8>>, field: &Field, charset: Charset) -> String {
+    match *value {
+        None => "NULL".to_string(),
+        Some(ref v) if field.holds_bytes() => {
+            let hex: String = v.iter().map(|b| format!("{:02X}", b)).collect();
+            format!("X'{}'", hex)
+        },
+        Some(ref v) => {
+            let text = charset.decode(v);
+            if field.field_type.is_numeric() {
+                text
+            } else {
+                format!("'{}'", escape_string(&text))
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Writes the result of a query as CSV.  Returns the number of rows written.
+    pub fn export_csv<W: Write>(&self, query: &str, out: &mut W, opts: &CsvOptions) -> Result<u64, String> {
+        let rows = try!(self.query_iter(query));
+        let delimiter = opts.delimiter.to_string();
+        if opts.header {
+            let names: Vec<String> = rows.fields().iter().map(|f| csv_field(&f.name, opts)).collect();
+            try!(write_str(out, &(names.join(&delimiter) + "\r\n")));
+        }
+        let mut count = 0;
+        for row in rows {
+            let row = try!(row);
+            let cells: Vec<String> = row.iter().map(|cell| match *cell {
+                Some(ref v) => csv_field(v, opts),
+                None        => opts.null_marker.clone(),
+            }).collect();
+            try!(write_str(out, &(cells.join(&delimiter) + "\r\n")));
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Writes the result of a query as JSON Lines, one object per row keyed by column name.
+    /// Numeric columns are written as JSON numbers and NULL as null, everything else as a
+    /// string.  Returns the number of rows written.
+    pub fn export_json_lines<W: Write>(&self, query: &str, out: &mut W) -> Result<u64, String> {
+        let rows = try!(self.query_iter(query));
+        let fields = rows.fields().to_vec();
+        let keys: Vec<String> = fields.iter().map(|f| json_string(&f.name)).collect();
+        let mut count = 0;
+        for row in rows {
+            let row = try!(row);
+            let members: Vec<String> = row.iter().zip(fields.iter()).zip(keys.iter())
+                .map(|((cell, field), key)| format!("{}:{}", key, json_value(cell, field)))
+                .collect();
+            try!(write_str(out, &format!("{{{}}}\n", members.join(","))));
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Writes every row of a table as extended `INSERT` statements, in the same form as
+    /// mysqldump, so the output can be fed back through the `mysql` client.  Binary columns are
+    /// written as hex.  Returns the number of rows written.
+    pub fn export_sql<W: Write>(&self, table_name: &str, out: &mut W, opts: &DumpOptions) -> Result<u64, String> {
+        let mut rows = try!(self.query_iter(&try!(Select::from(table_name).to_sql())));
+        let fields = rows.fields().to_vec();
+        let charset = self.text_charset();
+        let header = format!("INSERT INTO {} VALUES ", quote_ident(table_name));
+        let mut statement = String::new();
+        let mut count = 0;
+        while let Some(row) = rows.next_raw() {
+            let row = try!(row);
+            let values: Vec<String> = row.iter().zip(fields.iter()).map(|(cell, field)| sql_value(cell, field, charset)).collect();
+            let tuple = format!("({})", values.join(","));
+            if !statement.is_empty() && statement.len() + tuple.len() + 2 > opts.max_statement_len {
+                statement.push_str(";\n");
+                try!(write_str(out, &statement));
+                statement.clear();
+            }
+            if statement.is_empty() {
+                statement.push_str(&header);
+            } else {
+                statement.push(',');
+            }
+            statement.push_str(&tuple);
+            count += 1;
+        }
+        if !statement.is_empty() {
+            statement.push_str(";\n");
+            try!(write_str(out, &statement));
+        }
+        Ok(count)
+    }
+}
+
+#[test]
+fn test_csv_field() {
+    let opts = CsvOptions::default();
+    assert_eq!(csv_field("plain", &opts), "plain");
+    assert_eq!(csv_field("a,b", &opts), "\"a,b\"");
+    assert_eq!(csv_field("say \"hi\"", &opts), "\"say \"\"hi\"\"\"");
+    assert_eq!(csv_field("\\N", &opts), "\"\\N\"");
+    assert_eq!(json_string("tab\there \"q\""), "\"tab\\there \\\"q\\\"\"");
+}
+
+#[test]
+fn test_sql_value() {
+    use ::types::SQLFieldType;
+    let field = |field_type, charset| Field {
+        name: "c".to_string(),
+        table: String::new(),
+        field_type: field_type,
+        length: 0,
+        decimals: 0,
+        flags: 0,
+        charset: charset,
+    };
+    let blob = field(SQLFieldType::Blob, 63);
+    assert_eq!(sql_value(&Some(vec![0x00, 0xff, b'\'']), &blob, Charset::Utf8), "X'00FF27'");
+    assert_eq!(sql_value(&Some(Vec::new()), &blob, Charset::Utf8), "X''");
+    assert_eq!(sql_value(&None, &blob, Charset::Utf8), "NULL");
+    assert_eq!(sql_value(&Some(b"it's".to_vec()), &field(SQLFieldType::Blob, 45), Charset::Utf8), "'it''s'");
+    assert_eq!(sql_value(&Some(b"12".to_vec()), &field(SQLFieldType::Long, 63), Charset::Utf8), "12");
+    assert_eq!(sql_value(&Some(b"2024-01-02".to_vec()), &field(SQLFieldType::Date, 63), Charset::Utf8), "'2024-01-02'");
+}
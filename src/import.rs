@@ -0,0 +1,517 @@
+//! Reads CSV or JSON Lines and inserts the rows into a table.
+//!
+//! Fields are matched to columns by name or through an explicit mapping, converted to the
+//! type of their column, and inserted in batches that each run in their own transaction.  Rows
+//! that can't be converted, or that the server refuses, go to an `ErrorSink` along with the
+//! reason, and the rest of the import carries on.
+
+use ::connection::Connection;
+use ::export::CsvOptions;
+use ::query::Insert;
+use ::types::SQLType;
+use ::std::io::BufRead;
+use ::std::iter::Peekable;
+use ::std::str::{Chars, FromStr};
+
+/// How the fields of the input are matched to the columns of the table.
+#[derive(Clone, Debug)]
+pub enum ColumnMapping {
+    /// Every field goes to the column of the same name.  The names come from the CSV header or
+    /// the keys of each JSON object.
+    ByName,
+    /// The fields go to the table's columns in order, and every row must have one for each
+    /// column.  Any CSV header is ignored.
+    ByPosition,
+    /// Pairs of (field name, column name).  Fields that aren't listed are ignored.  For CSV
+    /// without a header, the field names are their 1-based positions, such as "1".
+    Explicit(Vec<(String, String)>),
+}
+
+/// Settings for `import_csv` and `import_json_lines`.
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    /// How fields are matched to columns.  Defaults to ByName.
+    pub mapping: ColumnMapping,
+    /// Rows per INSERT and transaction.  Defaults to 500.
+    pub batch_size: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions { mapping: ColumnMapping::ByName, batch_size: 500 }
+    }
+}
+
+/// A row that was not imported.
+#[derive(Clone, Debug)]
+pub struct RejectedRow {
+    /// Which record of the input this was, counting from 1 and not counting a CSV header.
+    pub record: u64,
+    /// The fields of the row, as (field name, value).  NULL is None.
+    pub fields: Vec<(String, Option<String>)>,
+    /// The input line, if it could not be parsed into fields at all.
+    pub raw: Option<String>,
+    /// Why the row was rejected: a conversion error, or the error from the server.
+    pub error: String,
+}
+
+/// Receives the rows an import rejects.
+pub trait ErrorSink {
+    /// Called once for every rejected row.
+    fn reject(&mut self, row: RejectedRow);
+}
+
+impl ErrorSink for Vec<RejectedRow> {
+    fn reject(&mut self, row: RejectedRow) {
+        self.push(row);
+    }
+}
+
+/// Totals for an import.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImportSummary {
+    /// Rows inserted into the table.
+    pub imported: u64,
+    /// Rows sent to the error sink.
+    pub rejected: u64,
+}
+
+/// Reads RFC 4180 records, which can span several lines when a quoted field holds a newline.
+struct CsvReader<'a, R> {
+    input: R,
+    opts: &'a CsvOptions,
+}
+
+impl<'a, R: BufRead> CsvReader<'a, R> {
+    /// Reads the next record, with unquoted fields equal to the null marker as None.
+    fn next_record(&mut self) -> Result<Option<Vec<Option<String>>>, String> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = try!(self.input.read_line(&mut line).map_err(|e| format!("Failed to read import. Reason: {}", e)));
+            if read == 0 {
+                if in_quotes {
+                    return Err("Input ended inside a quoted field.".to_string());
+                }
+                if fields.is_empty() && field.is_empty() && !quoted {
+                    return Ok(None);
+                }
+                break;
+            }
+            //Skip blank lines between records.
+            if !in_quotes && fields.is_empty() && field.is_empty() && !quoted && line.trim_end_matches(|c| c == '\r' || c == '\n').is_empty() {
+                continue;
+            }
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c == '"' {
+                        if chars.peek() == Some(&'"') {
+                            chars.next();
+                            field.push('"');
+                        } else {
+                            in_quotes = false;
+                        }
+                    } else {
+                        field.push(c);
+                    }
+                } else if c == '"' && field.is_empty() && !quoted {
+                    in_quotes = true;
+                    quoted = true;
+                } else if c == self.opts.delimiter {
+                    fields.push(self.finish_field(&mut field, quoted));
+                    quoted = false;
+                } else if c != '\r' && c != '\n' {
+                    field.push(c);
+                }
+            }
+            if !in_quotes {
+                break;
+            }
+        }
+        fields.push(self.finish_field(&mut field, quoted));
+        Ok(Some(fields))
+    }
+    fn finish_field(&self, field: &mut String, quoted: bool) -> Option<String> {
+        let value = ::std::mem::take(field);
+        if !quoted && value == self.opts.null_marker {
+            None
+        } else {
+            Some(value)
+        }
+    }
+}
+
+/// Parses one line of JSON Lines.  Only flat objects are supported, so every value has to be a
+/// string, number, boolean or null.  Booleans become "1" and "0".
+fn parse_json_object(line: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut chars = line.chars().peekable();
+    let mut fields = Vec::new();
+    skip_whitespace(&mut chars);
+    if chars.next() != Some('{') {
+        return Err("Expected a JSON object.".to_string());
+    }
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = try!(parse_json_string(&mut chars));
+            skip_whitespace(&mut chars);
+            if chars.next() != Some(':') {
+                return Err(format!("Expected ':' after key \"{}\".", key));
+            }
+            skip_whitespace(&mut chars);
+            let value = try!(parse_json_scalar(&mut chars));
+            fields.push((key, value));
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _         => return Err("Expected ',' or '}' in JSON object.".to_string()),
+            }
+        }
+    }
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return Err("Unexpected text after the JSON object.".to_string());
+    }
+    Ok(fields)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_json_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    if chars.next() != Some('"') {
+        return Err("Expected a JSON string.".to_string());
+    }
+    let mut out = String::new();
+    loop {
+        match chars.next() {
+            None       => return Err("Unterminated JSON string.".to_string()),
+            Some('"')  => return Ok(out),
+            Some('\\') => {
+                match chars.next() {
+                    Some('"')  => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/')  => out.push('/'),
+                    Some('b')  => out.push('\x08'),
+                    Some('f')  => out.push('\x0c'),
+                    Some('n')  => out.push('\n'),
+                    Some('r')  => out.push('\r'),
+                    Some('t')  => out.push('\t'),
+                    Some('u')  => {
+                        let high = try!(parse_hex4(chars));
+                        let code = if (0xD800..0xDC00).contains(&high) {
+                            //A surrogate pair, the low half has to follow as another \u escape.
+                            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                                return Err("Unpaired surrogate in JSON string.".to_string());
+                            }
+                            let low = try!(parse_hex4(chars));
+                            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+                        } else {
+                            high
+                        };
+                        match ::std::char::from_u32(code) {
+                            Some(c) => out.push(c),
+                            None    => return Err("Invalid \\u escape in JSON string.".to_string()),
+                        }
+                    },
+                    _ => return Err("Invalid escape in JSON string.".to_string()),
+                }
+            },
+            Some(c) => out.push(c),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let hex: String = chars.by_ref().take(4).collect();
+    u32::from_str_radix(&hex, 16).map_err(|_| format!("Invalid \\u escape \"{}\" in JSON string.", hex))
+}
+
+fn parse_json_scalar(chars: &mut Peekable<Chars>) -> Result<Option<String>, String> {
+    match chars.peek().cloned() {
+        Some('"') => parse_json_string(chars).map(Some),
+        Some('{') | Some('[') => Err("Nested objects and arrays can't be imported.".to_string()),
+        Some(_) => {
+            let mut word = String::new();
+            while chars.peek().map_or(false, |c| c.is_alphanumeric() || *c == '-' || *c == '+' || *c == '.') {
+                word.push(chars.next().unwrap());
+            }
+            match word.as_str() {
+                "null"  => Ok(None),
+                "true"  => Ok(Some("1".to_string())),
+                "false" => Ok(Some("0".to_string())),
+                _ if f64::from_str(&word).is_ok() => Ok(Some(word)),
+                _ => Err(format!("Invalid JSON value \"{}\".", word)),
+            }
+        },
+        None => Err("Expected a JSON value.".to_string()),
+    }
+}
+
+/// The fields of one record, as (field name, value).
+type Fields = Vec<(String, Option<String>)>;
+
+/// A column being imported into, and the field it is read from.
+struct Target {
+    field: String,
+    column: String,
+    /// The column's type, or None if values should be passed along as text for the server to
+    /// convert.
+    template: Option<SQLType>,
+}
+
+/// Converts records and sends them to the server in batches.
+struct Importer<'a, 'b, S: 'b> {
+    conn: &'a Connection,
+    table: String,
+    targets: Vec<Target>,
+    /// Reject fields that don't belong to any target.
+    strict: bool,
+    /// Converted rows waiting to be sent, as (record number, fields, values).
+    batch: Vec<(u64, Fields, Vec<SQLType>)>,
+    batch_size: usize,
+    sink: &'b mut S,
+    summary: ImportSummary,
+}
+
+impl<'a, 'b, S: ErrorSink> Importer<'a, 'b, S> {
+    /// Works out the target columns from the table's schema, the mapping, and the CSV header
+    /// if there is one.
+    fn new(conn: &'a Connection, table_name: &str, opts: &ImportOptions, header: Option<&Vec<String>>, sink: &'b mut S) -> Result<Self, String> {
+        let schema = try!(conn.describe_table(table_name));
+        let template = |column: &str| -> Result<Option<SQLType>, String> {
+            match schema.iter().find(|c| c.0 == column) {
+                None => Err(format!("Table {} has no column named {}.", table_name, column)),
+                //SQLType has no unsigned variants, so let the server check those.
+                Some(c) if c.1.contains("unsigned") => Ok(None),
+                Some(c) => Ok(SQLType::from_str(&c.1).ok()),
+            }
+        };
+        let mut targets = Vec::new();
+        let mut strict = false;
+        match opts.mapping {
+            ColumnMapping::ByName => {
+                match header {
+                    Some(names) => {
+                        for n in names {
+                            targets.push(Target { field: n.clone(), column: n.clone(), template: try!(template(n)) });
+                        }
+                    },
+                    None => {
+                        for c in &schema {
+                            targets.push(Target { field: c.0.clone(), column: c.0.clone(), template: try!(template(&c.0)) });
+                        }
+                    },
+                }
+                strict = true;
+            },
+            ColumnMapping::ByPosition => {
+                for (i, c) in schema.iter().enumerate() {
+                    targets.push(Target { field: (i + 1).to_string(), column: c.0.clone(), template: try!(template(&c.0)) });
+                }
+                strict = true;
+            },
+            ColumnMapping::Explicit(ref pairs) => {
+                for (field, column) in pairs {
+                    targets.push(Target { field: field.clone(), column: column.clone(), template: try!(template(column)) });
+                }
+            },
+        }
+        if targets.is_empty() {
+            return Err("No columns to import into.".to_string());
+        }
+        Ok(Importer {
+            conn: conn,
+            table: table_name.to_string(),
+            targets: targets,
+            strict: strict,
+            batch: Vec::new(),
+            batch_size: ::std::cmp::max(opts.batch_size, 1),
+            sink: sink,
+            summary: ImportSummary { imported: 0, rejected: 0 },
+        })
+    }
+
+    fn reject(&mut self, record: u64, fields: Fields, raw: Option<String>, error: String) {
+        self.summary.rejected += 1;
+        self.sink.reject(RejectedRow { record: record, fields: fields, raw: raw, error: error });
+    }
+
+    /// Converts a record and adds it to the batch, sending the batch once it is full.
+    fn push(&mut self, record: u64, fields: Fields) -> Result<(), String> {
+        if self.strict {
+            if let Some(f) = fields.iter().find(|f| !self.targets.iter().any(|t| t.field == f.0)) {
+                let error = format!("Field {} does not match any column.", f.0);
+                self.reject(record, fields, None, error);
+                return Ok(());
+            }
+        }
+        let mut values = Vec::with_capacity(self.targets.len());
+        for t in &self.targets {
+            let value = match fields.iter().find(|f| f.0 == t.field) {
+                //Let the column's default fill in anything the record doesn't have.
+                None                   => Ok(SQLType::column_default()),
+                Some(&(_, None))       => Ok(SQLType::null()),
+                Some(&(_, Some(ref v))) => match t.template {
+                    Some(ref template) => template.parse_value(v),
+                    None               => Ok(SQLType::VarChar(v.clone(), 0)),
+                },
+            };
+            match value {
+                Ok(v) => values.push(v),
+                Err(e) => {
+                    let error = format!("Column {}: {}", t.column, e);
+                    self.reject(record, fields, None, error);
+                    return Ok(());
+                }
+            }
+        }
+        self.batch.push((record, fields, values));
+        if self.batch.len() >= self.batch_size {
+            try!(self.flush());
+        }
+        Ok(())
+    }
+
+    fn insert_for(&self) -> Insert {
+        let columns: Vec<&str> = self.targets.iter().map(|t| t.column.as_str()).collect();
+        Insert::into(&self.table).columns(&columns)
+    }
+
+    /// Sends the batch in one transaction.  If the server refuses it, the rows are sent again
+    /// one at a time, so only the bad ones are rejected.
+    fn flush(&mut self) -> Result<(), String> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = ::std::mem::take(&mut self.batch);
+        let mut insert = self.insert_for();
+        for row in &batch {
+            insert = insert.values(row.2.clone());
+        }
+        try!(self.conn.begin_transaction());
+        if insert.to_sql().map(|sql| self.conn.raw_query_no_res(&sql).is_ok()).unwrap_or(false) {
+            try!(self.conn.commit());
+            self.summary.imported += batch.len() as u64;
+            return Ok(());
+        }
+        try!(self.conn.rollback());
+
+        try!(self.conn.begin_transaction());
+        for (record, fields, values) in batch {
+            let single = self.insert_for().values(values);
+            match single.to_sql().map(|sql| self.conn.raw_query_no_res(&sql)) {
+                Ok(Ok(_)) => self.summary.imported += 1,
+                Ok(Err(error)) | Err(error) => self.reject(record, fields, None, error),
+            }
+        }
+        self.conn.commit()
+    }
+
+    fn finish(mut self) -> Result<ImportSummary, String> {
+        try!(self.flush());
+        Ok(self.summary)
+    }
+}
+
+impl Connection {
+    /// Imports CSV into a table.  The import runs its own transactions, so it can't be called
+    /// while a transaction is in progress.
+    pub fn import_csv<R: BufRead, S: ErrorSink>(&self, table_name: &str, input: R, csv: &CsvOptions,
+                                                 opts: &ImportOptions, sink: &mut S) -> Result<ImportSummary, String> {
+        let mut reader = CsvReader { input: input, opts: csv };
+        let header: Option<Vec<String>> = if csv.header {
+            match try!(reader.next_record()) {
+                Some(names) => Some(names.into_iter().map(|n| n.unwrap_or_default()).collect()),
+                None        => return Ok(ImportSummary { imported: 0, rejected: 0 }),
+            }
+        } else {
+            if let ColumnMapping::ByName = opts.mapping {
+                return Err("Matching columns by name needs a CSV header.".to_string());
+            }
+            None
+        };
+        let header_for_mapping = match opts.mapping {
+            ColumnMapping::ByName => header.as_ref(),
+            _                     => None,
+        };
+        let mut importer = try!(Importer::new(self, table_name, opts, header_for_mapping, sink));
+        let mut record = 0;
+        while let Some(values) = try!(reader.next_record()) {
+            record += 1;
+            let names: Vec<String> = match (&opts.mapping, header.as_ref()) {
+                (&ColumnMapping::ByPosition, _) | (_, None) => (1..values.len() + 1).map(|i| i.to_string()).collect(),
+                (_, Some(h)) => h.clone(),
+            };
+            let expected = match opts.mapping {
+                ColumnMapping::ByPosition => importer.targets.len(),
+                _                         => names.len(),
+            };
+            if values.len() != expected || values.len() != names.len() {
+                let fields = names.into_iter().zip(values.iter().cloned()).collect();
+                let error = format!("Expected {} fields, found {}.", expected, values.len());
+                importer.reject(record, fields, None, error);
+                continue;
+            }
+            try!(importer.push(record, names.into_iter().zip(values.into_iter()).collect()));
+        }
+        importer.finish()
+    }
+
+    /// Imports JSON Lines into a table, one flat object per line.  The import runs its own
+    /// transactions, so it can't be called while a transaction is in progress.
+    pub fn import_json_lines<R: BufRead, S: ErrorSink>(&self, table_name: &str, input: R,
+                                                        opts: &ImportOptions, sink: &mut S) -> Result<ImportSummary, String> {
+        if let ColumnMapping::ByPosition = opts.mapping {
+            return Err("JSON objects have no order, match them by name instead.".to_string());
+        }
+        let mut importer = try!(Importer::new(self, table_name, opts, None, sink));
+        let mut record = 0;
+        for line in input.lines() {
+            let line = try!(line.map_err(|e| format!("Failed to read import. Reason: {}", e)));
+            if line.trim().is_empty() {
+                continue;
+            }
+            record += 1;
+            match parse_json_object(&line) {
+                Ok(fields) => try!(importer.push(record, fields)),
+                Err(e)     => importer.reject(record, Vec::new(), Some(line), e),
+            }
+        }
+        importer.finish()
+    }
+}
+
+#[test]
+fn test_csv_reader() {
+    let opts = CsvOptions::default();
+    let input = "a,\"b,c\",\\N\r\n\n\"multi\nline\",\"say \"\"hi\"\"\",\"\\N\"\n".as_bytes();
+    let mut reader = CsvReader { input: input, opts: &opts };
+    assert_eq!(reader.next_record().unwrap(), Some(vec![Some("a".to_string()), Some("b,c".to_string()), None]));
+    assert_eq!(reader.next_record().unwrap(), Some(vec![Some("multi\nline".to_string()), Some("say \"hi\"".to_string()), Some("\\N".to_string())]));
+    assert_eq!(reader.next_record().unwrap(), None);
+}
+
+#[test]
+fn test_parse_json_object() {
+    let fields = parse_json_object(r#"{"id": 5, "name": "caf\u00e9 \"x\"", "ok": true, "gone": null}"#).unwrap();
+    assert_eq!(fields, vec![
+        ("id".to_string(), Some("5".to_string())),
+        ("name".to_string(), Some("café \"x\"".to_string())),
+        ("ok".to_string(), Some("1".to_string())),
+        ("gone".to_string(), None),
+    ]);
+    assert!(parse_json_object(r#"{"nested": {"a": 1}}"#).is_err());
+}
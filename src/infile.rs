@@ -0,0 +1,183 @@
+//! `LOAD DATA LOCAL INFILE` fed from a Rust reader instead of a file on disk.
+//!
+//! The client library asks a set of callbacks for the file's contents whenever the server
+//! requests a local file.  Those callbacks are pointed at a `Read` for the length of one
+//! statement, and the server only gets data back for the file names the `InfilePolicy` allows.
+//! The connection must have been opened with `ConnectOptions::local_infile(true)`.
+//!
+//! At every other time the callbacks refuse whatever the server asks for, so the client
+//! library never reads a file from disk for it.
+
+use ::connection::Connection;
+use ::cstr::from_cstr;
+use ::ffi::mysql::{MYSQL, mysql_affected_rows, mysql_set_local_infile_handler};
+use ::libc::{c_char, c_int, c_uint, c_void};
+use ::query::quote_ident;
+use ::std::io::Read;
+use ::std::panic::{self, AssertUnwindSafe};
+use ::std::{ptr, slice};
+
+/// The file name used in statements built by `load_csv_local`.
+pub const STREAM_NAME: &'static str = "mariadb-rs-stream";
+
+/// Client error code reported when a callback fails. (CR_UNKNOWN_ERROR)
+const CR_UNKNOWN_ERROR: c_int = 2000;
+
+/// Which file names the server may ask for during `load_data_local`.
+///
+/// The name in a `LOAD DATA LOCAL INFILE` statement is sent back by the server, so a server
+/// that isn't trusted could ask for any name it likes.  Nothing is read from disk either way,
+/// but the request is refused unless the name is on the list.
+#[derive(Clone, Debug)]
+pub struct InfilePolicy {
+    allowed: Vec<String>,
+    allow_any: bool,
+}
+
+impl InfilePolicy {
+    /// A policy that allows nothing until names are added with `allow()`.
+    pub fn new() -> Self {
+        InfilePolicy { allowed: Vec::new(), allow_any: false }
+    }
+    /// A policy that answers any file name with the stream.
+    pub fn allow_any() -> Self {
+        InfilePolicy { allowed: Vec::new(), allow_any: true }
+    }
+    /// Allow the server to ask for this file name.
+    pub fn allow(mut self, name: &str) -> Self {
+        self.allowed.push(name.to_string());
+        self
+    }
+    /// Returns true if the server may ask for this file name.
+    pub fn permits(&self, name: &str) -> bool {
+        self.allow_any || self.allowed.iter().any(|a| a == name)
+    }
+}
+
+impl Default for InfilePolicy {
+    fn default() -> Self {
+        InfilePolicy::new()
+    }
+}
+
+/// What the callbacks work on for the length of one statement.
+struct InfileState<'a> {
+    reader: &'a mut dyn Read,
+    policy: &'a InfilePolicy,
+    error: Option<String>,
+}
+
+extern "C" fn infile_init(ptr: *mut *mut c_void, filename: *const c_char, userdata: *mut c_void) -> c_int {
+    unsafe { *ptr = userdata };
+    let state = unsafe { &mut *(userdata as *mut InfileState) };
+    let name = from_cstr(filename);
+    if state.policy.permits(&name) {
+        0
+    } else {
+        state.error = Some(format!("The server asked for local file '{}', which is not allowed.", name));
+        1
+    }
+}
+
+extern "C" fn infile_read(ptr: *mut c_void, buf: *mut c_char, buf_len: c_uint) -> c_int {
+    let state = unsafe { &mut *(ptr as *mut InfileState) };
+    let buf = unsafe { slice::from_raw_parts_mut(buf as *mut u8, buf_len as usize) };
+    //A panic must not unwind into C.
+    match panic::catch_unwind(AssertUnwindSafe(|| state.reader.read(buf))) {
+        Ok(Ok(n))  => n as c_int,
+        Ok(Err(e)) => { state.error = Some(format!("Reading the local infile stream failed. Reason: {}", e)); -1 },
+        Err(_)     => { state.error = Some("Reading the local infile stream panicked.".to_string()); -1 },
+    }
+}
+
+extern "C" fn infile_end(_ptr: *mut c_void) {
+}
+
+extern "C" fn infile_error(ptr: *mut c_void, buf: *mut c_char, buf_len: c_uint) -> c_int {
+    if ptr.is_null() || buf_len == 0 {
+        return CR_UNKNOWN_ERROR;
+    }
+    let state = unsafe { &mut *(ptr as *mut InfileState) };
+    let msg = state.error.clone().unwrap_or_else(|| "Unknown local infile error.".to_string());
+    write_error(&msg, buf, buf_len);
+    CR_UNKNOWN_ERROR
+}
+
+/// Copies a message into the client library's error buffer, cut short if need be.
+fn write_error(msg: &str, buf: *mut c_char, buf_len: c_uint) {
+    let len = ::std::cmp::min(msg.len(), buf_len as usize - 1);
+    unsafe {
+        ptr::copy_nonoverlapping(msg.as_ptr() as *const c_char, buf, len);
+        *buf.add(len) = 0;
+    }
+}
+
+extern "C" fn refuse_init(ptr: *mut *mut c_void, _filename: *const c_char, _userdata: *mut c_void) -> c_int {
+    unsafe { *ptr = ptr::null_mut() };
+    1
+}
+
+extern "C" fn refuse_read(_ptr: *mut c_void, _buf: *mut c_char, _buf_len: c_uint) -> c_int {
+    -1
+}
+
+extern "C" fn refuse_error(_ptr: *mut c_void, buf: *mut c_char, buf_len: c_uint) -> c_int {
+    if buf_len > 0 {
+        write_error("The server asked for a local file outside of load_data_local, which is not allowed.", buf, buf_len);
+    }
+    CR_UNKNOWN_ERROR
+}
+
+/// Points the callbacks at ones that refuse every request, in place of the client library's
+/// own, which would read whatever file the server names.
+pub(crate) fn refuse_local_infile(conn: *mut MYSQL) {
+    unsafe {
+        mysql_set_local_infile_handler(conn, Some(refuse_init), Some(refuse_read), Some(infile_end), Some(refuse_error),
+                                       ptr::null_mut());
+    }
+}
+
+impl Connection {
+    /// Runs a `LOAD DATA LOCAL INFILE` statement, answering the server's file request with the
+    /// contents of `reader`.  Returns the number of rows loaded.  Needs the client library backend.
+    pub fn load_data_local<R: Read>(&self, statement: &str, reader: &mut R, policy: &InfilePolicy) -> Result<u64, String> {
+        let conn = try!(self.client_handle("LOAD DATA LOCAL INFILE"));
+        let mut state = InfileState { reader: reader, policy: policy, error: None };
+        unsafe {
+            mysql_set_local_infile_handler(conn, Some(infile_init), Some(infile_read), Some(infile_end), Some(infile_error),
+                                           &mut state as *mut InfileState as *mut c_void);
+        }
+        let sent = self.send_query(statement);
+        //Don't leave the handler pointing at a stack frame that is about to go away, nor hand
+        //the server the client library's own handler, which reads any file it names.
+        refuse_local_infile(conn);
+        try!(sent);
+        Ok(unsafe { mysql_affected_rows(conn) } as u64)
+    }
+
+    /// Loads comma separated rows from `reader` into a table, with fields optionally enclosed in
+    /// double quotes and one row per line.  Returns the number of rows loaded.
+    pub fn load_csv_local<R: Read>(&self, table_name: &str, reader: &mut R) -> Result<u64, String> {
+        let statement = format!("LOAD DATA LOCAL INFILE '{}' INTO TABLE {} \
+                                 FIELDS TERMINATED BY ',' OPTIONALLY ENCLOSED BY '\"' \
+                                 LINES TERMINATED BY '\\n';", STREAM_NAME, quote_ident(table_name));
+        self.load_data_local(&statement, reader, &InfilePolicy::new().allow(STREAM_NAME))
+    }
+}
+
+#[cfg(feature = "test-server")]
+#[test]
+fn test_local_infile_refused() {
+    use ::options::ConnectOptions;
+    let server = ::testing::TestServer::start().unwrap();
+    let opts: ConnectOptions = server.options().local_infile(true);
+    let conn = opts.connect().unwrap();
+    conn.create_table("infile_test", "line TEXT").unwrap();
+    //A request the server makes for a statement that isn't load_data_local is refused.
+    assert!(conn.raw_query_no_res("LOAD DATA LOCAL INFILE '/etc/hostname' INTO TABLE infile_test;").is_err());
+    //And so is one after load_data_local put its own callbacks back.
+    assert_eq!(conn.load_csv_local("infile_test", &mut "a\nb\n".as_bytes()).unwrap(), 2);
+    assert!(conn.raw_query_no_res("LOAD DATA LOCAL INFILE '/etc/hostname' INTO TABLE infile_test;").is_err());
+    assert_eq!(conn.raw_query("SELECT COUNT(*) FROM infile_test;", 1).unwrap(), vec![vec!["2".to_string()]]);
+    conn.drop_table("infile_test").unwrap();
+}
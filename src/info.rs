@@ -0,0 +1,237 @@
+//! Versions and capabilities of the server and the client library.
+
+use ::connection::Connection;
+#[cfg(feature = "ffi")]
+use ::cstr::from_cstr;
+#[cfg(feature = "ffi")]
+use ::ffi::mysql::{mysql_get_server_info, mysql_get_server_version, mysql_get_proto_info, mysql_get_host_info, mysql_get_client_info, mysql_get_client_version};
+use ::std::fmt;
+use ::std::str::FromStr;
+
+/// A version number such as 10.6.12.  Compares the way you would expect, so
+/// `version >= Version::new(10, 5, 0)` works.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// The major version.
+    pub major: u32,
+    /// The minor version.
+    pub minor: u32,
+    /// The patch version.
+    pub patch: u32,
+}
+
+impl Version {
+    /// Makes a version from its parts.
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version { major: major, minor: minor, patch: patch }
+    }
+
+    /// Splits the number the client library reports, major * 10000 + minor * 100 + patch.
+    fn from_number(number: u64) -> Self {
+        Version::new((number / 10000) as u32, (number / 100 % 100) as u32, (number % 100) as u32)
+    }
+
+    /// Reads the leading "major.minor.patch" of a version string, ignoring anything after it.
+    fn parse(text: &str) -> Option<Self> {
+        let numbers: Vec<u32> = text.split(|c: char| !c.is_ascii_digit() && c != '.').next()
+            .unwrap_or("").split('.').take(3).filter_map(|n| u32::from_str(n).ok()).collect();
+        if numbers.len() == 3 {
+            Some(Version::new(numbers[0], numbers[1], numbers[2]))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Which server the connection is talking to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Flavor {
+    /// MariaDB.
+    MariaDB,
+    /// MySQL, or something else that doesn't say it's MariaDB.
+    MySQL,
+}
+
+/// Capability flags, as sent in the handshake.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Capabilities(pub u64);
+
+impl Capabilities {
+    /// CLIENT_FOUND_ROWS
+    pub const FOUND_ROWS: u64 = 1 << 1;
+    /// CLIENT_CONNECT_WITH_DB
+    pub const CONNECT_WITH_DB: u64 = 1 << 3;
+    /// CLIENT_COMPRESS
+    pub const COMPRESS: u64 = 1 << 5;
+    /// CLIENT_LOCAL_FILES
+    pub const LOCAL_FILES: u64 = 1 << 7;
+    /// CLIENT_PROTOCOL_41
+    pub const PROTOCOL_41: u64 = 1 << 9;
+    /// CLIENT_SSL
+    pub const SSL: u64 = 1 << 11;
+    /// CLIENT_TRANSACTIONS
+    pub const TRANSACTIONS: u64 = 1 << 13;
+    /// CLIENT_SECURE_CONNECTION
+    pub const SECURE_CONNECTION: u64 = 1 << 15;
+    /// CLIENT_MULTI_STATEMENTS
+    pub const MULTI_STATEMENTS: u64 = 1 << 16;
+    /// CLIENT_MULTI_RESULTS
+    pub const MULTI_RESULTS: u64 = 1 << 17;
+    /// CLIENT_PS_MULTI_RESULTS
+    pub const PS_MULTI_RESULTS: u64 = 1 << 18;
+    /// CLIENT_PLUGIN_AUTH
+    pub const PLUGIN_AUTH: u64 = 1 << 19;
+    /// CLIENT_CONNECT_ATTRS
+    pub const CONNECT_ATTRS: u64 = 1 << 20;
+    /// CLIENT_SESSION_TRACK
+    pub const SESSION_TRACK: u64 = 1 << 23;
+    /// CLIENT_DEPRECATE_EOF
+    pub const DEPRECATE_EOF: u64 = 1 << 24;
+
+    /// Returns true if every bit of `flags` is set.
+    pub fn contains(&self, flags: u64) -> bool {
+        self.0 & flags == flags
+    }
+}
+
+/// What is known about the server of a connection.
+#[derive(Clone, Debug)]
+pub struct ServerInfo {
+    /// The server version.
+    pub version: Version,
+    /// The full version string, such as "10.6.12-MariaDB-log".
+    pub version_string: String,
+    /// MariaDB or MySQL.
+    pub flavor: Flavor,
+    /// The protocol version, 10 for every current server.
+    pub protocol_version: u32,
+    /// How the connection was made, such as "localhost via UNIX socket".
+    pub host_info: String,
+    /// The connection id on the server, as shown in the processlist.
+    pub thread_id: u64,
+    /// The capabilities both the server and the client library agreed on.
+    pub capabilities: Capabilities,
+}
+
+impl ServerInfo {
+    /// Works out the version and flavor from the version string.  MariaDB 10 and later send
+    /// "5.5.5-" in front of the real version to keep old MySQL clients happy, and the number
+    /// the client library reports is then 5.5.5 too.
+    fn parse_version(version_string: &str, number: u64) -> (Version, Flavor) {
+        let flavor = if version_string.contains("MariaDB") { Flavor::MariaDB } else { Flavor::MySQL };
+        let text = if flavor == Flavor::MariaDB && version_string.starts_with("5.5.5-") {
+            &version_string[6..]
+        } else {
+            version_string
+        };
+        (Version::parse(text).unwrap_or_else(|| Version::from_number(number)), flavor)
+    }
+
+    /// Returns true for MariaDB at or after the given version, false for MySQL.
+    fn mariadb_since(&self, version: Version) -> bool {
+        self.flavor == Flavor::MariaDB && self.version >= version
+    }
+
+    /// Returns true for MySQL at or after the given version, false for MariaDB.
+    fn mysql_since(&self, version: Version) -> bool {
+        self.flavor == Flavor::MySQL && self.version >= version
+    }
+
+    /// `INSERT ... RETURNING` (MariaDB 10.5).
+    pub fn supports_returning(&self) -> bool {
+        self.mariadb_since(Version::new(10, 5, 0))
+    }
+
+    /// The JSON type or its alias (MySQL 5.7.8, MariaDB 10.2.7).
+    pub fn supports_json(&self) -> bool {
+        self.mysql_since(Version::new(5, 7, 8)) || self.mariadb_since(Version::new(10, 2, 7))
+    }
+
+    /// `CREATE SEQUENCE` (MariaDB 10.3).
+    pub fn supports_sequences(&self) -> bool {
+        self.mariadb_since(Version::new(10, 3, 0))
+    }
+
+    /// Common table expressions, `WITH ...` (MySQL 8.0, MariaDB 10.2.1).
+    pub fn supports_cte(&self) -> bool {
+        self.mysql_since(Version::new(8, 0, 0)) || self.mariadb_since(Version::new(10, 2, 1))
+    }
+
+    /// `SET STATEMENT max_statement_time=... FOR` (MariaDB 10.1.2).
+    pub fn supports_max_statement_time(&self) -> bool {
+        self.mariadb_since(Version::new(10, 1, 2))
+    }
+
+    /// The `MAX_EXECUTION_TIME` optimizer hint for `SELECT` (MySQL 5.7.8).
+    pub fn supports_max_execution_time(&self) -> bool {
+        self.mysql_since(Version::new(5, 7, 8))
+    }
+}
+
+/// The version of the client library this crate is linked against.
+#[cfg(feature = "ffi")]
+pub fn client_version() -> Version {
+    Version::from_number(unsafe { mysql_get_client_version() } as u64)
+}
+
+/// The version string of the client library, such as "10.6.12" or "8.0.33".
+#[cfg(feature = "ffi")]
+pub fn client_info() -> String {
+    from_cstr(unsafe { mysql_get_client_info() })
+}
+
+impl Connection {
+    /// The version, flavor and capabilities of the server.  Backends other than the client
+    /// library only report the version and thread id.
+    pub fn server_info(&self) -> ServerInfo {
+        #[cfg(feature = "ffi")]
+        {
+            let conn = self.handle();
+            if !conn.is_null() {
+                let version_string = from_cstr(unsafe { mysql_get_server_info(conn) });
+                let (version, flavor) = ServerInfo::parse_version(&version_string, unsafe { mysql_get_server_version(conn) } as u64);
+                let capabilities = unsafe { (*conn).server_capabilities & (*conn).client_flag } as u64;
+                return ServerInfo {
+                    version: version,
+                    version_string: version_string,
+                    flavor: flavor,
+                    protocol_version: unsafe { mysql_get_proto_info(conn) } as u32,
+                    host_info: from_cstr(unsafe { mysql_get_host_info(conn) }),
+                    thread_id: self.thread_id(),
+                    capabilities: Capabilities(capabilities),
+                };
+            }
+        }
+        let version_string = self.backend.borrow().server_version();
+        let (version, flavor) = ServerInfo::parse_version(&version_string, 0);
+        ServerInfo {
+            version: version,
+            version_string: version_string,
+            flavor: flavor,
+            protocol_version: 10,
+            host_info: String::new(),
+            thread_id: self.thread_id(),
+            capabilities: Capabilities(0),
+        }
+    }
+
+    /// The connection id on the server.  Changes if the connection is re-established.
+    pub fn thread_id(&self) -> u64 {
+        self.backend.borrow().thread_id()
+    }
+}
+
+#[test]
+fn test_parse_version() {
+    assert_eq!(ServerInfo::parse_version("5.5.5-10.6.12-MariaDB-1:10.6.12+maria~ubu2004", 50505),
+               (Version::new(10, 6, 12), Flavor::MariaDB));
+    assert_eq!(ServerInfo::parse_version("8.0.33", 80033), (Version::new(8, 0, 33), Flavor::MySQL));
+    assert_eq!(ServerInfo::parse_version("garbage", 100512), (Version::new(10, 5, 12), Flavor::MySQL));
+    assert!(Version::new(10, 5, 0) < Version::new(10, 11, 2));
+}
@@ -0,0 +1,70 @@
+//! Setting up the client library, once per process and once per thread.
+//!
+//! `mysql_init` would set up the library on first use by itself, but not safely if two threads
+//! get there at the same time, so it is done here behind a `Once` before the first connection.
+//! Every thread that uses a connection also needs `mysql_thread_init`, and `mysql_thread_end`
+//! when it exits or the library leaks memory.  A thread-local guard takes care of both.
+
+use ::ffi::mysql::{mysql_server_init, mysql_thread_init, mysql_thread_end, mysql_thread_safe};
+use ::std::cell::Cell;
+use ::std::ptr;
+use ::std::sync::Once;
+use ::std::sync::atomic::{AtomicUsize, Ordering};
+
+static LIBRARY_INIT: Once = Once::new();
+
+const STATE_OK: usize = 0;
+const STATE_FAILED: usize = 1;
+const STATE_NOT_THREAD_SAFE: usize = 2;
+static LIBRARY_STATE: AtomicUsize = AtomicUsize::new(STATE_OK);
+
+/// Registers the current thread with the client library until the thread exits.
+struct ThreadGuard {
+    registered: Cell<bool>,
+}
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        if self.registered.get() {
+            unsafe { mysql_thread_end() };
+        }
+    }
+}
+
+thread_local!(static THREAD: ThreadGuard = const { ThreadGuard { registered: Cell::new(false) } });
+
+/// Sets up the library if this is the first call in the process, then registers the current
+/// thread if this is its first call.  Cheap after that, so call it before any use of a handle.
+///
+/// Fails if the library couldn't be set up, or was built without thread support.  `Connection`
+/// is `Send`, which is only sound if the library is thread-safe, so connections are refused
+/// outright rather than risk being moved to another thread.
+pub(crate) fn thread_init() -> Result<(), String> {
+    LIBRARY_INIT.call_once(|| {
+        let state = if unsafe { mysql_server_init(0, ptr::null_mut(), ptr::null_mut()) } != 0 {
+            STATE_FAILED
+        } else if unsafe { mysql_thread_safe() } == 0 {
+            STATE_NOT_THREAD_SAFE
+        } else {
+            STATE_OK
+        };
+        LIBRARY_STATE.store(state, Ordering::SeqCst);
+    });
+    match LIBRARY_STATE.load(Ordering::SeqCst) {
+        STATE_FAILED          => return Err("Failed to initialise the client library.".to_string()),
+        STATE_NOT_THREAD_SAFE => return Err("The client library was built without thread support.".to_string()),
+        _                     => {},
+    }
+    //During thread shutdown the guard may already be gone, there is nothing left to do then.
+    let registered = THREAD.try_with(|guard| {
+        if guard.registered.get() {
+            return Ok(());
+        }
+        if unsafe { mysql_thread_init() } != 0 {
+            return Err("Failed to initialise the client library for this thread.".to_string());
+        }
+        guard.registered.set(true);
+        Ok(())
+    });
+    registered.unwrap_or(Ok(()))
+}
@@ -17,9 +17,75 @@ extern crate libc;
 
 mod connection;
 mod cstr;
+#[cfg(feature = "ffi")]
 mod ffi;
 mod types;
+mod value;
+mod temporal;
+mod decimal;
 mod serialize;
+mod query;
+mod options;
+mod url;
+mod optfile;
+mod observe;
+mod metrics;
+mod cancel;
+mod pool;
+#[cfg(feature = "ffi")]
+mod infile;
+mod result;
+mod export;
+mod import;
+#[cfg(feature = "ffi")]
+mod init;
+mod reconnect;
+#[cfg(feature = "ffi")]
+mod admin;
+mod info;
+#[cfg(feature = "ffi")]
+mod session;
+mod statement;
+mod backend;
+mod mock;
+#[cfg(feature = "native")]
+mod native;
+#[cfg(feature = "test-server")]
+mod testing;
 
-pub use connection::Connection;
+pub use connection::{Connection, InsertMode, InsertSummary};
+pub use backend::{Backend, PreparedStatement};
+#[cfg(feature = "ffi")]
+pub use backend::FfiBackend;
+pub use mock::{MockBackend, MockResponse, QueryLog};
+#[cfg(feature = "native")]
+pub use native::NativeBackend;
+#[cfg(feature = "test-server")]
+pub use testing::TestServer;
+pub use options::{ConnectOptions, SslMode};
+pub use optfile::{OptionFile, CLIENT_GROUPS};
+pub use observe::{QueryObserver, QueryEvent, QueryLogger, redact_sql};
+pub use metrics::{Metrics, MetricsSnapshot, Histogram};
+pub use pool::{Pool, PooledConnection};
+pub use cancel::{CancelHandle, Watchdog};
+pub use reconnect::{ReconnectMode, ReconnectPolicy};
+#[cfg(feature = "ffi")]
+pub use admin::{Admin, Process, ShutdownLevel, Refresh, ServerStatus};
+pub use info::{Version, Flavor, Capabilities, ServerInfo};
+#[cfg(feature = "ffi")]
+pub use info::{client_version, client_info};
+pub use cstr::{Charset, CharsetInfo};
+#[cfg(feature = "ffi")]
+pub use infile::InfilePolicy;
+pub use result::{Rows, Field, ExecResult, Warning, WarningLevel};
+#[cfg(feature = "ffi")]
+pub use result::{StoredResult, RowOffset};
+pub use statement::{Statement, StatementRows};
+pub use export::{CsvOptions, DumpOptions};
+pub use import::{ColumnMapping, ImportOptions, ImportSummary, RejectedRow, ErrorSink};
 pub use serialize::SerializeSQL;
+pub use types::{SQLType, SQLFieldType};
+pub use value::{Value, ToSql, FromSql};
+pub use temporal::{Date, Time, DateTime};
+pub use decimal::Decimal;
+pub use query::{Select, Insert, Update, Delete, Where, Order, quote_ident, escape_string};
@@ -0,0 +1,223 @@
+//! Counters and latency histograms for the statements of one or more connections and pools.
+//!
+//! Give the same `Metrics` to `ConnectOptions::metrics()` or `Pool::metrics()` for everything
+//! that should be counted together, then read it with `snapshot()`, or serve `prometheus()` from
+//! your own metrics endpoint.  Nothing here opens a socket.
+
+use ::observe::{QueryObserver, QueryEvent};
+use ::std::collections::BTreeMap;
+use ::std::fmt::Write;
+use ::std::sync::{Arc, Mutex};
+use ::std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// How long things took, counted into buckets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// The upper bound of each bucket in seconds, with how many took at most that long.
+    pub buckets: Vec<(f64, u64)>,
+    /// How many there were in all, including those slower than the last bucket.
+    pub count: u64,
+    /// How long they took together, in seconds.
+    pub sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram { buckets: BUCKETS.iter().map(|&le| (le, 0)).collect(), count: 0, sum: 0.0 }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for bucket in self.buckets.iter_mut().filter(|b| seconds <= b.0) {
+            bucket.1 += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// The metrics at one moment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Statement durations by kind: SELECT, INSERT, UPDATE, DELETE, REPLACE, CALL, SET, SHOW,
+    /// TRANSACTION for transaction control, DDL for schema changes, and OTHER.
+    pub queries: BTreeMap<String, Histogram>,
+    /// Failed statements by error code and SQLSTATE.
+    pub errors: BTreeMap<(u32, String), u64>,
+    /// How long `Pool::get()` waited for a connection.
+    pub pool_wait: Histogram,
+    /// How many times `Pool::get()` gave up waiting.
+    pub pool_timeouts: u64,
+    /// Connections handed out by pools and not yet returned.
+    pub pool_in_use: u64,
+    /// Connections waiting in pools to be handed out.
+    pub pool_idle: u64,
+    /// How many times a lost connection was re-established.
+    pub reconnects: u64,
+    /// Bytes of statement text sent.
+    pub bytes_sent: u64,
+    /// Bytes of column values read, see `QueryEvent::bytes_received`.
+    pub bytes_received: u64,
+}
+
+/// Escapes a Prometheus label value.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    for &(le, count) in &histogram.buckets {
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, le, count);
+    }
+    let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, histogram.count);
+    let braces = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+    let _ = writeln!(out, "{}_sum{} {}", name, braces, histogram.sum);
+    let _ = writeln!(out, "{}_count{} {}", name, braces, histogram.count);
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl MetricsSnapshot {
+    /// The metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        write_header(&mut out, "mariadb_query_duration_seconds", "histogram", "Time taken by statements, by kind.");
+        for (kind, histogram) in &self.queries {
+            write_histogram(&mut out, "mariadb_query_duration_seconds", &format!("kind=\"{}\"", label(kind)), histogram);
+        }
+        write_header(&mut out, "mariadb_query_errors_total", "counter", "Failed statements, by error code and SQLSTATE.");
+        for (&(errno, ref sqlstate), count) in &self.errors {
+            let _ = writeln!(out, "mariadb_query_errors_total{{errno=\"{}\",sqlstate=\"{}\"}} {}", errno, label(sqlstate), count);
+        }
+        write_header(&mut out, "mariadb_pool_wait_seconds", "histogram", "Time spent waiting for a pooled connection.");
+        write_histogram(&mut out, "mariadb_pool_wait_seconds", "", &self.pool_wait);
+        write_header(&mut out, "mariadb_pool_timeouts_total", "counter", "Times a pool gave up waiting for a connection.");
+        let _ = writeln!(out, "mariadb_pool_timeouts_total {}", self.pool_timeouts);
+        write_header(&mut out, "mariadb_pool_connections", "gauge", "Pooled connections, by state.");
+        let _ = writeln!(out, "mariadb_pool_connections{{state=\"in_use\"}} {}", self.pool_in_use);
+        let _ = writeln!(out, "mariadb_pool_connections{{state=\"idle\"}} {}", self.pool_idle);
+        write_header(&mut out, "mariadb_reconnects_total", "counter", "Lost connections that were re-established.");
+        let _ = writeln!(out, "mariadb_reconnects_total {}", self.reconnects);
+        write_header(&mut out, "mariadb_bytes_sent_total", "counter", "Bytes of statement text sent.");
+        let _ = writeln!(out, "mariadb_bytes_sent_total {}", self.bytes_sent);
+        write_header(&mut out, "mariadb_bytes_received_total", "counter", "Bytes of column values read.");
+        let _ = writeln!(out, "mariadb_bytes_received_total {}", self.bytes_received);
+        out
+    }
+}
+
+/// The kind a statement is counted under, from its first word.
+fn statement_kind(sql: &str) -> &'static str {
+    let word: String = sql.trim_start_matches(|c: char| c.is_whitespace() || c == '(')
+        .chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+    match &*word.to_uppercase() {
+        "SELECT" | "WITH" => "SELECT",
+        "INSERT" => "INSERT",
+        "UPDATE" => "UPDATE",
+        "DELETE" => "DELETE",
+        "REPLACE" => "REPLACE",
+        "CALL" => "CALL",
+        "SET" => "SET",
+        "SHOW" => "SHOW",
+        "BEGIN" | "START" | "COMMIT" | "ROLLBACK" | "SAVEPOINT" | "RELEASE" | "XA" => "TRANSACTION",
+        "CREATE" | "ALTER" | "DROP" | "TRUNCATE" | "RENAME" => "DDL",
+        _ => "OTHER",
+    }
+}
+
+/// Metrics shared by everything they were given to.  Clones share the same counts.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    state: Arc<Mutex<MetricsSnapshot>>,
+}
+
+impl Metrics {
+    /// Metrics with nothing counted yet.
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// A copy of the metrics as they are now.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.update(|m| m.clone())
+    }
+
+    /// The metrics as they are now, in the Prometheus text exposition format.
+    pub fn prometheus(&self) -> String {
+        self.snapshot().to_prometheus()
+    }
+
+    fn update<T, F: FnOnce(&mut MetricsSnapshot) -> T>(&self, f: F) -> T {
+        //A panic in another thread can't leave the counts half updated, so carry on.
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut state)
+    }
+
+    pub(crate) fn record_reconnect(&self) {
+        self.update(|m| m.reconnects += 1);
+    }
+
+    pub(crate) fn record_pool_wait(&self, waited: Duration) {
+        self.update(|m| m.pool_wait.observe(waited));
+    }
+
+    pub(crate) fn record_pool_timeout(&self) {
+        self.update(|m| m.pool_timeouts += 1);
+    }
+
+    /// Moves the pool gauges by the given amounts.
+    pub(crate) fn record_pool_change(&self, in_use: i64, idle: i64) {
+        self.update(|m| {
+            m.pool_in_use = (m.pool_in_use as i64 + in_use).max(0) as u64;
+            m.pool_idle = (m.pool_idle as i64 + idle).max(0) as u64;
+        });
+    }
+}
+
+impl QueryObserver for Metrics {
+    fn on_query(&self, event: &QueryEvent) {
+        self.update(|m| {
+            m.queries.entry(statement_kind(event.sql).to_string()).or_default().observe(event.duration);
+            if event.error.is_some() {
+                *m.errors.entry((event.errno, event.sqlstate.to_string())).or_insert(0) += 1;
+            }
+            m.bytes_sent += event.sql.len() as u64;
+            m.bytes_received += event.bytes_received;
+        });
+    }
+}
+
+#[test]
+fn test_metrics() {
+    use ::std::time::Instant;
+    assert_eq!(statement_kind("  (select 1)"), "SELECT");
+    assert_eq!(statement_kind("start transaction"), "TRANSACTION");
+    assert_eq!(statement_kind("LOAD DATA"), "OTHER");
+
+    let metrics = Metrics::new();
+    let started = Instant::now();
+    metrics.on_query(&QueryEvent::read("SELECT 1", started, 1, 1, 0));
+    metrics.on_query(&QueryEvent::failed("SELEC 1", started, "syntax", 1064, "42000"));
+    metrics.record_pool_change(2, 1);
+    metrics.record_pool_change(-1, 1);
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.queries["SELECT"].count, 1);
+    assert_eq!(snapshot.queries["OTHER"].count, 1);
+    assert_eq!(snapshot.errors[&(1064, "42000".to_string())], 1);
+    assert_eq!((snapshot.pool_in_use, snapshot.pool_idle, snapshot.bytes_sent, snapshot.bytes_received), (1, 2, 15, 1));
+    let text = metrics.prometheus();
+    assert!(text.contains("mariadb_query_duration_seconds_bucket{kind=\"SELECT\",le=\"10\"} 1\n"));
+    assert!(text.contains("mariadb_query_duration_seconds_count{kind=\"OTHER\"} 1\n"));
+    assert!(text.contains("mariadb_query_errors_total{errno=\"1064\",sqlstate=\"42000\"} 1\n"));
+    assert!(text.contains("mariadb_pool_wait_seconds_bucket{le=\"+Inf\"} 0\n"));
+    assert!(text.contains("mariadb_pool_connections{state=\"idle\"} 2\n"));
+}
@@ -0,0 +1,250 @@
+//! A backend that answers from a script, for testing code built on `Connection` without a
+//! server.
+//!
+//! Give `MockBackend` the responses it should send, keep its `QueryLog`, and connect with
+//! `ConnectOptions::connect_with()`.  Every statement the connection sends is recorded in the
+//! log, including the `SET SESSION` statements of the options on connect.
+
+use ::backend::Backend;
+use ::options::ConnectOptions;
+use ::result::{Field, ExecResult};
+use ::types::SQLFieldType;
+use ::std::collections::VecDeque;
+use ::std::sync::{Arc, Mutex};
+
+/// Character set number of utf8mb4_general_ci, given to every mock column.
+const UTF8MB4_GENERAL_CI: u32 = 45;
+/// The error code given to scripted errors. (ER_UNKNOWN_ERROR)
+const ER_UNKNOWN_ERROR: u32 = 1105;
+
+/// How the mock answers a statement.
+#[derive(Clone, Debug)]
+pub enum MockResponse {
+    /// A result set, with these columns and rows.
+    Rows(Vec<Field>, Vec<Vec<Option<String>>>),
+    /// A statement that didn't return rows.
+    Done(ExecResult),
+    /// An error with this message.
+    Error(String),
+    /// The connection being lost, which a reconnect policy recovers from.
+    Lost,
+}
+
+impl MockResponse {
+    /// A result set of text columns with the given names.
+    pub fn rows(columns: &[&str], rows: Vec<Vec<Option<&str>>>) -> Self {
+        let fields = columns.iter().map(|name| Field {
+            name: name.to_string(),
+            table: String::new(),
+            field_type: SQLFieldType::VarString,
+            length: 0,
+            decimals: 0,
+            flags: 0,
+            charset: UTF8MB4_GENERAL_CI,
+        }).collect();
+        let rows = rows.into_iter().map(|row| row.into_iter().map(|cell| cell.map(|c| c.to_string())).collect()).collect();
+        MockResponse::Rows(fields, rows)
+    }
+
+    /// A statement that changed the given number of rows.
+    pub fn affected(rows: u64) -> Self {
+        MockResponse::Done(ExecResult { affected_rows: rows, insert_id: 0, warning_count: 0, info: None })
+    }
+
+    /// An error with the given message.
+    pub fn error(message: &str) -> Self {
+        MockResponse::Error(message.to_string())
+    }
+}
+
+/// The statements a `MockBackend` was sent, in order.  Clones share the same log, so keep one
+/// before handing the mock to a connection.
+#[derive(Clone, Debug, Default)]
+pub struct QueryLog(Arc<Mutex<Vec<String>>>);
+
+impl QueryLog {
+    /// Every statement so far.
+    pub fn queries(&self) -> Vec<String> {
+        self.0.lock().map(|q| q.clone()).unwrap_or_default()
+    }
+
+    /// Forgets the statements so far.
+    pub fn clear(&self) {
+        if let Ok(mut queries) = self.0.lock() {
+            queries.clear();
+        }
+    }
+
+    fn push(&self, query: &str) {
+        if let Ok(mut queries) = self.0.lock() {
+            queries.push(query.to_string());
+        }
+    }
+}
+
+/// A scripted backend.  Each statement is answered by the next queued response if there is
+/// one, then by the first rule whose pattern it contains, and otherwise as a statement that
+/// changed nothing.  `SELECT DATABASE()` is answered with the database it was connected to or
+/// last switched to with `USE`, unless a response is scripted for it.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    queue: VecDeque<MockResponse>,
+    rules: Vec<(String, MockResponse)>,
+    log: QueryLog,
+    rows: VecDeque<Vec<Option<String>>>,
+    last: Option<ExecResult>,
+    error: String,
+    errno: u32,
+    lost: bool,
+    db: String,
+    charset: String,
+}
+
+impl MockBackend {
+    /// A mock without any responses.
+    pub fn new() -> Self {
+        MockBackend { charset: "utf8mb4".to_string(), ..MockBackend::default() }
+    }
+
+    /// Answers every statement containing `pattern` with `response`.  Rules are tried in the
+    /// order they were added.
+    pub fn on(mut self, pattern: &str, response: MockResponse) -> Self {
+        self.rules.push((pattern.to_string(), response));
+        self
+    }
+
+    /// Answers the next statement, whatever it is, with `response`.  Queued responses are used
+    /// up in order, before any rule is tried.
+    pub fn then(mut self, response: MockResponse) -> Self {
+        self.queue.push_back(response);
+        self
+    }
+
+    /// The log of the statements sent to this mock.
+    pub fn log(&self) -> QueryLog {
+        self.log.clone()
+    }
+
+    fn respond(&mut self, sql: &str) -> MockResponse {
+        if let Some(response) = self.queue.pop_front() {
+            return response;
+        }
+        if let Some(rule) = self.rules.iter().find(|rule| sql.contains(&*rule.0)) {
+            return rule.1.clone();
+        }
+        if sql.trim().trim_end_matches(';').eq_ignore_ascii_case("SELECT DATABASE()") {
+            let db = if self.db.is_empty() { None } else { Some(&*self.db) };
+            return MockResponse::rows(&["DATABASE()"], vec![vec![db]]);
+        }
+        MockResponse::affected(0)
+    }
+}
+
+impl Backend for MockBackend {
+    fn connect(&mut self, opts: &ConnectOptions) -> Result<(), String> {
+        self.lost = false;
+        self.db = opts.database.clone();
+        self.charset = opts.charset.clone();
+        for (name, value) in &opts.session_vars {
+            try!(self.query(&try!(::reconnect::set_session_sql(name, value))));
+        }
+        Ok(())
+    }
+
+    fn query(&mut self, sql: &str) -> Result<Option<Vec<Field>>, String> {
+        self.log.push(sql);
+        self.rows.clear();
+        self.last = None;
+        self.lost = false;
+        self.errno = 0;
+        let response = self.respond(sql);
+        if let MockResponse::Done(_) = response {
+            let lower = sql.trim().to_lowercase();
+            if lower.starts_with("use ") {
+                self.db = sql.trim()[4..].trim().trim_end_matches(';').trim_matches('`').to_string();
+            }
+        }
+        match response {
+            MockResponse::Rows(fields, rows) => {
+                self.rows = rows.into_iter().collect();
+                Ok(Some(fields))
+            },
+            MockResponse::Done(result) => {
+                self.last = Some(result);
+                Ok(None)
+            },
+            MockResponse::Error(message) => {
+                self.error = message.clone();
+                self.errno = ER_UNKNOWN_ERROR;
+                Err(message)
+            },
+            MockResponse::Lost => {
+                self.lost = true;
+                self.errno = ::backend::CR_SERVER_LOST;
+                self.error = "Lost connection to MySQL server during query".to_string();
+                Err(self.error.clone())
+            },
+        }
+    }
+
+    fn next_row(&mut self) -> Result<Option<Vec<Option<String>>>, String> {
+        Ok(self.rows.pop_front())
+    }
+
+    fn exec_result(&self) -> ExecResult {
+        self.last.clone().unwrap_or(ExecResult { affected_rows: 0, insert_id: 0, warning_count: 0, info: None })
+    }
+
+    fn last_error(&self) -> String {
+        self.error.clone()
+    }
+
+    fn last_errno(&self) -> u32 {
+        self.errno
+    }
+
+    fn connection_lost(&self) -> bool {
+        self.lost
+    }
+
+    fn charset(&self) -> String {
+        self.charset.clone()
+    }
+
+    fn set_charset(&mut self, name: &str) -> Result<(), String> {
+        try!(self.query(&format!("SET NAMES {};", name)));
+        self.charset = name.to_string();
+        Ok(())
+    }
+
+    fn server_version(&self) -> String {
+        "10.11.0-MariaDB-mock".to_string()
+    }
+}
+
+#[test]
+fn test_mock_backend() {
+    use ::reconnect::ReconnectPolicy;
+    let mock = MockBackend::new()
+        .on("name FROM users", MockResponse::rows(&["id", "name"], vec![vec![Some("1"), Some("ann")], vec![Some("2"), None]]))
+        .on("DELETE", MockResponse::affected(3))
+        .then(MockResponse::Lost);
+    let log = mock.log();
+    let opts = ConnectOptions::new("mock", "", "", "shop").reconnect(ReconnectPolicy::full());
+    let mut conn = opts.connect_with(mock).unwrap();
+
+    //The first statement loses the connection, which is re-established but not sent again.
+    assert!(conn.raw_query_no_res("SELECT 1;").unwrap_err().contains("(reconnected)"));
+    assert_eq!(conn.reconnect_count(), 1);
+    assert_eq!(conn.raw_query("SELECT id, name FROM users;", 2).unwrap(),
+               vec![vec!["1".to_string(), "ann".to_string()], vec!["2".to_string(), "NULL".to_string()]]);
+    conn.begin_transaction().unwrap();
+    assert_eq!(conn.raw_query_no_res("DELETE FROM users;").unwrap().affected_rows, 3);
+    conn.commit().unwrap();
+    conn.switch_db("archive".to_string()).unwrap();
+    assert_eq!(conn.current_db(), "archive");
+    assert_eq!(conn.escape("it's"), "it''s");
+    assert!(conn.prepare("SELECT ?").is_err());
+    assert_eq!(log.queries(), vec!["SELECT 1;", "USE `shop`;", "SET NAMES utf8mb4;", "SELECT id, name FROM users;",
+                                   "START TRANSACTION;", "DELETE FROM users;", "COMMIT;", "USE `archive`;"]);
+}
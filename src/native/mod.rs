@@ -0,0 +1,1062 @@
+//! A client for the MySQL/MariaDB client/server protocol, written in Rust rather than bound
+//! from libmysqlclient.  Enabled with the `native` cargo feature.
+//!
+//! It covers what `Connection` needs through the `Backend` trait: the handshake with
+//! `mysql_native_password`, text queries (COM_QUERY) and prepared statements over the binary
+//! protocol (COM_STMT_*), with read-only cursors.  There is no TLS, compression or
+//! `LOAD DATA LOCAL INFILE`, and the only authentication plugin is `mysql_native_password`, so
+//! accounts using `caching_sha2_password` or `ed25519` are refused.  Rows come back as text
+//! either way, the same as from the client library.
+//!
+//! Connect with `ConnectOptions::connect_with(NativeBackend::new())`.  The parts of
+//! `Connection` that need the client library, such as `admin()`, fail over it.
+
+mod auth;
+mod packet;
+
+use self::packet::{PacketStream, Stream, Reader, put_lenenc_bytes};
+use ::backend::{Backend, PreparedStatement, CR_SERVER_GONE_ERROR, unknown_statement};
+use ::cstr::Charset;
+use ::info::Capabilities;
+use ::optfile::with_option_files;
+use ::query::escape_string;
+use ::options::ConnectOptions;
+use ::result::{Field, ExecResult, decode_row};
+use ::types::{SQLType, SQLFieldType};
+use ::std::collections::{HashMap, VecDeque};
+use ::std::net::{TcpStream, ToSocketAddrs};
+use ::std::time::Duration;
+#[cfg(unix)]
+use ::std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use ::std::path::Path;
+
+/// CLIENT_LONG_PASSWORD
+const LONG_PASSWORD: u64 = 1;
+/// CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA
+const PLUGIN_AUTH_LENENC: u64 = 1 << 21;
+
+const COM_QUIT: u8 = 0x01;
+const COM_INIT_DB: u8 = 0x02;
+const COM_QUERY: u8 = 0x03;
+const COM_PING: u8 = 0x0e;
+const COM_STMT_PREPARE: u8 = 0x16;
+const COM_STMT_EXECUTE: u8 = 0x17;
+const COM_STMT_CLOSE: u8 = 0x19;
+const COM_STMT_RESET: u8 = 0x1a;
+const COM_STMT_FETCH: u8 = 0x1c;
+
+//Parameter types of COM_STMT_EXECUTE, so the native client doesn't need the client library.
+const MYSQL_TYPE_TINY: u32 = 1;
+const MYSQL_TYPE_SHORT: u32 = 2;
+const MYSQL_TYPE_LONG: u32 = 3;
+const MYSQL_TYPE_FLOAT: u32 = 4;
+const MYSQL_TYPE_DOUBLE: u32 = 5;
+const MYSQL_TYPE_NULL: u32 = 6;
+const MYSQL_TYPE_LONGLONG: u32 = 8;
+const MYSQL_TYPE_STRING: u32 = 254;
+/// CURSOR_TYPE_READ_ONLY, a flag of COM_STMT_EXECUTE.
+const CURSOR_TYPE_READ_ONLY: u8 = 1;
+
+/// SERVER_STATUS_IN_TRANS
+const STATUS_IN_TRANS: u16 = 0x0001;
+/// SERVER_MORE_RESULTS_EXISTS
+const STATUS_MORE_RESULTS: u16 = 0x0008;
+/// SERVER_STATUS_CURSOR_EXISTS
+const STATUS_CURSOR_EXISTS: u16 = 0x0040;
+/// SERVER_STATUS_LAST_ROW_SENT
+const STATUS_LAST_ROW_SENT: u16 = 0x0080;
+/// SERVER_STATUS_NO_BACKSLASH_ESCAPES
+const STATUS_NO_BACKSLASH_ESCAPES: u16 = 0x0200;
+
+/// The port used when the options leave it at 0.
+const DEFAULT_PORT: u16 = 3306;
+/// Where servers usually put their socket, tried in order for "localhost" like the client
+/// library does.
+const DEFAULT_SOCKETS: [&str; 3] = ["/run/mysqld/mysqld.sock", "/var/run/mysqld/mysqld.sock", "/tmp/mysql.sock"];
+
+use ::backend::CR_SERVER_LOST;
+/// Client error code for something the native client can't do. (CR_NOT_IMPLEMENTED)
+const CR_NOT_IMPLEMENTED: u32 = 2054;
+
+/// The collation to ask for in the handshake for a character set, or None to send
+/// `SET NAMES` afterwards instead.
+fn collation_id(charset: &str) -> Option<u8> {
+    match &*charset.to_lowercase() {
+        "utf8mb4"          => Some(45),
+        "utf8" | "utf8mb3" => Some(33),
+        "latin1"           => Some(8),
+        "ascii"            => Some(11),
+        "binary"           => Some(63),
+        _                  => None,
+    }
+}
+
+/// What is left to read of the response to the last command.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Pending {
+    /// Nothing, the next command can be sent.
+    Nothing,
+    /// Rows of a text result set.
+    TextRows,
+    /// Rows of a binary result set, from the prepared statement with this id.
+    BinaryRows(u32),
+    /// Another result, after a stored procedure's result set.
+    MoreResults,
+}
+
+/// The server's answer to a statement.
+pub(crate) enum Response {
+    /// It didn't return rows.
+    Done(ExecResult),
+    /// It returned rows with these columns, to be read with `next_row()`.
+    Rows(Vec<Field>),
+}
+
+/// A statement prepared on the server.
+struct NativeStatement {
+    param_count: usize,
+    fields: Vec<Field>,
+    /// How many rows to fetch at a time from the cursor of the last execution, or 0 if it has
+    /// no cursor open.
+    fetch: u32,
+    /// Rows fetched from the cursor and not read yet.  A whole fetch is read at once, so other
+    /// commands can be sent before they are.
+    fetched: VecDeque<Vec<u8>>,
+}
+
+/// A connection to the server.  Sends COM_QUIT when dropped.
+pub(crate) struct NativeConn {
+    stream: PacketStream,
+    capabilities: u64,
+    charset: Charset,
+    server_version: String,
+    thread_id: u32,
+    status: u16,
+    warnings: u16,
+    error: (u32, String),
+    sqlstate: String,
+    pending: Pending,
+    columns: Vec<Field>,
+    /// Prepared statements, by the id the server gave them.
+    statements: HashMap<u32, NativeStatement>,
+}
+
+impl NativeConn {
+    /// Connects and logs in with the given options.  Session variables and connection
+    /// attributes are sent; reconnecting and local infile are left to the caller.
+    pub(crate) fn connect(opts: &ConnectOptions) -> Result<NativeConn, String> {
+        let resolved = try!(with_option_files(opts));
+        let opts = &resolved;
+        if opts.ssl_mode.is_required() {
+            return Err(format!("Failed to connect to SQL. Reason: ssl-mode is {} but the native client has no TLS.", opts.ssl_mode));
+        }
+        let stream = try!(open_stream(opts).map_err(|e| format!("Failed to connect to SQL. Reason: {}", e)));
+        let seconds = |s: Option<u32>| s.map(|s| Duration::from_secs(s as u64));
+        try!(stream.set_timeouts(seconds(opts.read_timeout), seconds(opts.write_timeout))
+             .map_err(|e| format!("Failed to connect to SQL. Reason: {}", e)));
+        let mut conn = NativeConn {
+            stream: PacketStream::new(stream, opts.max_allowed_packet),
+            capabilities: 0,
+            charset: Charset::from_name(&opts.charset),
+            server_version: String::new(),
+            thread_id: 0,
+            status: 0,
+            warnings: 0,
+            error: (0, String::new()),
+            sqlstate: "00000".to_string(),
+            pending: Pending::Nothing,
+            columns: Vec::new(),
+            statements: HashMap::new(),
+        };
+        try!(conn.handshake(opts).map_err(|e| format!("Failed to connect to SQL. Reason: {}", e)));
+        if collation_id(&opts.charset).is_none() {
+            if !opts.charset.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("Invalid character set name: {}", opts.charset));
+            }
+            try!(conn.query(&format!("SET NAMES {};", opts.charset)));
+        }
+        for (name, value) in &opts.session_vars {
+            try!(conn.query(&try!(::reconnect::set_session_sql(name, value))));
+        }
+        Ok(conn)
+    }
+
+    /// Reads the server's greeting and logs in.
+    fn handshake(&mut self, opts: &ConnectOptions) -> Result<(), String> {
+        let greeting = try!(self.read());
+        if greeting.first() == Some(&0xff) {
+            return Err(self.server_error(&greeting));
+        }
+        let mut r = Reader::new(&greeting);
+        let protocol = try!(r.u8());
+        if protocol != 10 {
+            return Err(format!("Unsupported protocol version {}.", protocol));
+        }
+        self.server_version = String::from_utf8_lossy(r.null_terminated()).into_owned();
+        self.thread_id = try!(r.u32());
+        let mut seed = try!(r.bytes(8)).to_vec();
+        try!(r.u8());
+        let mut server_caps = try!(r.u16()) as u64;
+        let mut plugin: &[u8] = b"mysql_native_password";
+        if !r.is_empty() {
+            try!(r.u8());
+            self.status = try!(r.u16());
+            server_caps |= (try!(r.u16()) as u64) << 16;
+            let seed_len = try!(r.u8()) as usize;
+            try!(r.bytes(10));
+            if server_caps & Capabilities::SECURE_CONNECTION != 0 {
+                let part = try!(r.bytes(::std::cmp::max(13, seed_len.saturating_sub(8))));
+                seed.extend_from_slice(part.split(|&b| b == 0).next().unwrap_or(part));
+            }
+            if server_caps & Capabilities::PLUGIN_AUTH != 0 {
+                plugin = r.null_terminated();
+            }
+        }
+        if server_caps & Capabilities::PROTOCOL_41 == 0 {
+            return Err("The server is too old, it doesn't speak protocol 4.1.".to_string());
+        }
+        let mut wanted = LONG_PASSWORD | Capabilities::PROTOCOL_41 | Capabilities::TRANSACTIONS |
+                         Capabilities::SECURE_CONNECTION | Capabilities::MULTI_RESULTS | Capabilities::PS_MULTI_RESULTS |
+                         Capabilities::PLUGIN_AUTH | PLUGIN_AUTH_LENENC | Capabilities::CONNECT_ATTRS;
+        if !opts.database.is_empty() {
+            wanted |= Capabilities::CONNECT_WITH_DB;
+        }
+        self.capabilities = wanted & server_caps;
+
+        //Always answer as mysql_native_password, a server using something else for the
+        //account asks to switch.
+        let password = try!(self.charset.encode(&opts.password));
+        let reply = if plugin == b"mysql_native_password" { auth::scramble(&seed, &password) } else { Vec::new() };
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.capabilities as u32).to_le_bytes());
+        out.extend_from_slice(&(opts.max_allowed_packet.min(u32::MAX as usize) as u32).to_le_bytes());
+        out.push(collation_id(&opts.charset).unwrap_or(45));
+        out.extend_from_slice(&[0; 23]);
+        out.extend_from_slice(&try!(self.charset.encode(&opts.user)));
+        out.push(0);
+        if self.capabilities & PLUGIN_AUTH_LENENC != 0 {
+            put_lenenc_bytes(&mut out, &reply);
+        } else {
+            out.push(reply.len() as u8);
+            out.extend_from_slice(&reply);
+        }
+        if self.capabilities & Capabilities::CONNECT_WITH_DB != 0 {
+            out.extend_from_slice(&try!(self.charset.encode(&opts.database)));
+            out.push(0);
+        }
+        if self.capabilities & Capabilities::PLUGIN_AUTH != 0 {
+            out.extend_from_slice(b"mysql_native_password\0");
+        }
+        if self.capabilities & Capabilities::CONNECT_ATTRS != 0 {
+            let mut attrs = Vec::new();
+            for (key, value) in &opts.connect_attrs {
+                put_lenenc_bytes(&mut attrs, key.as_bytes());
+                put_lenenc_bytes(&mut attrs, value.as_bytes());
+            }
+            put_lenenc_bytes(&mut out, &attrs);
+        }
+        try!(self.write(&out));
+
+        loop {
+            let packet = try!(self.read());
+            match packet.first() {
+                Some(&0x00) => {
+                    try!(self.read_ok(&packet));
+                    break;
+                },
+                Some(&0xff) => return Err(self.server_error(&packet)),
+                Some(&0xfe) if packet.len() > 1 => {
+                    let mut r = Reader::new(&packet[1..]);
+                    let plugin = r.null_terminated();
+                    if plugin != b"mysql_native_password" {
+                        return Err(self.fail(CR_NOT_IMPLEMENTED, &format!("The authentication plugin {} isn't supported.",
+                                                                         String::from_utf8_lossy(plugin))));
+                    }
+                    let data = r.rest();
+                    let seed = data.split(|&b| b == 0).next().unwrap_or(data);
+                    try!(self.write(&auth::scramble(seed, &password)));
+                },
+                _ => return Err(self.fail(CR_NOT_IMPLEMENTED, "The server asked for an authentication method that isn't supported.")),
+            }
+        }
+        if !opts.database.is_empty() && self.capabilities & Capabilities::CONNECT_WITH_DB == 0 {
+            try!(self.select_db(&opts.database));
+        }
+        Ok(())
+    }
+
+    /// The version string the server sent in its greeting.
+    pub(crate) fn server_version(&self) -> &str {
+        &self.server_version
+    }
+
+    /// The connection id on the server.
+    pub(crate) fn thread_id(&self) -> u64 {
+        self.thread_id as u64
+    }
+
+    /// The capabilities both sides agreed on.
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        Capabilities(self.capabilities)
+    }
+
+    /// Returns true if the server says a transaction is open.
+    pub(crate) fn in_transaction(&self) -> bool {
+        self.status & STATUS_IN_TRANS != 0
+    }
+
+    /// Returns true if the server's sql_mode has `NO_BACKSLASH_ESCAPES`, so a backslash in a
+    /// string is just a backslash.
+    pub(crate) fn no_backslash_escapes(&self) -> bool {
+        self.status & STATUS_NO_BACKSLASH_ESCAPES != 0
+    }
+
+    /// The warnings raised by the last statement, counted when its response was read.
+    pub(crate) fn warning_count(&self) -> u32 {
+        self.warnings as u32
+    }
+
+    /// The code of the last error, or 0 if the last command worked.
+    pub(crate) fn last_errno(&self) -> u32 {
+        self.error.0
+    }
+
+    /// The SQLSTATE of the last error, "HY000" for errors of our own, or "00000" if the last
+    /// command worked.
+    pub(crate) fn sqlstate(&self) -> &str {
+        &self.sqlstate
+    }
+
+    /// The message of the last error, or an empty string if the last command worked.
+    pub(crate) fn last_error(&self) -> String {
+        self.error.1.clone()
+    }
+
+    /// Records an error of our own, and returns its message.
+    fn fail(&mut self, code: u32, message: &str) -> String {
+        self.error = (code, message.to_string());
+        self.sqlstate = "HY000".to_string();
+        message.to_string()
+    }
+
+    fn read(&mut self) -> Result<Vec<u8>, String> {
+        match self.stream.read_packet() {
+            Ok(packet) => Ok(packet),
+            Err(e)     => {
+                self.pending = Pending::Nothing;
+                Err(self.fail(CR_SERVER_LOST, &e))
+            },
+        }
+    }
+
+    fn write(&mut self, payload: &[u8]) -> Result<(), String> {
+        match self.stream.write_packet(payload) {
+            Ok(())  => Ok(()),
+            Err(e)  => Err(self.fail(CR_SERVER_LOST, &e)),
+        }
+    }
+
+    /// Reads an ERR packet, records it, and returns its message.
+    fn server_error(&mut self, packet: &[u8]) -> String {
+        let mut r = Reader::new(packet);
+        let _ = r.u8();
+        let code = r.u16().unwrap_or(0) as u32;
+        let mut message = r.rest();
+        let mut sqlstate = "HY000".to_string();
+        if message.first() == Some(&b'#') && message.len() >= 6 {
+            sqlstate = String::from_utf8_lossy(&message[1..6]).into_owned();
+            message = &message[6..];
+        }
+        let message = self.charset.decode(message);
+        let message = self.fail(code, &message);
+        self.sqlstate = sqlstate;
+        message
+    }
+
+    /// Reads an OK packet.
+    fn read_ok(&mut self, packet: &[u8]) -> Result<ExecResult, String> {
+        let mut r = Reader::new(packet);
+        try!(r.u8());
+        let affected_rows = try!(r.lenenc());
+        let insert_id = try!(r.lenenc());
+        self.status = try!(r.u16());
+        self.warnings = try!(r.u16());
+        let info = self.charset.decode(r.rest());
+        if self.status & STATUS_MORE_RESULTS != 0 {
+            self.pending = Pending::MoreResults;
+        }
+        Ok(ExecResult {
+            affected_rows: affected_rows,
+            insert_id: insert_id,
+            warning_count: self.warnings as u32,
+            info: if info.is_empty() { None } else { Some(info) },
+        })
+    }
+
+    /// Reads a column definition.
+    fn read_field(&self, packet: &[u8]) -> Result<Field, String> {
+        let mut r = Reader::new(packet);
+        for _ in 0..2 {
+            try!(r.lenenc_bytes());
+        }
+        let table = try!(r.lenenc_bytes()).unwrap_or(b"");
+        try!(r.lenenc_bytes());
+        let name = try!(r.lenenc_bytes()).unwrap_or(b"");
+        try!(r.lenenc_bytes());
+        try!(r.lenenc());
+        let charset = try!(r.u16());
+        let length = try!(r.u32());
+        let field_type = try!(r.u8());
+        let flags = try!(r.u16());
+        let decimals = try!(r.u8());
+        Ok(Field {
+            name: self.charset.decode(name),
+            table: self.charset.decode(table),
+            field_type: SQLFieldType::from_raw(field_type as u32).unwrap_or(SQLFieldType::String),
+            length: length as u64,
+            decimals: decimals as u32,
+            flags: flags as u32,
+            charset: charset as u32,
+        })
+    }
+
+    /// Reads `count` column definitions and the EOF packet after them, which says whether a
+    /// cursor was opened.
+    fn read_fields(&mut self, count: usize) -> Result<Vec<Field>, String> {
+        let mut fields = Vec::with_capacity(count);
+        for _ in 0..count {
+            let packet = try!(self.read());
+            fields.push(try!(self.read_field(&packet)));
+        }
+        if count > 0 {
+            let packet = try!(self.read());
+            try!(self.read_eof(&packet));
+        }
+        Ok(fields)
+    }
+
+    /// Reads an EOF packet.
+    fn read_eof(&mut self, packet: &[u8]) -> Result<(), String> {
+        let mut r = Reader::new(packet);
+        try!(r.u8());
+        self.warnings = try!(r.u16());
+        self.status = try!(r.u16());
+        Ok(())
+    }
+
+    /// Sends a command, after reading and throwing away whatever is left of the last one.
+    fn command(&mut self, command: u8, payload: &[u8]) -> Result<(), String> {
+        try!(self.finish());
+        self.error = (0, String::new());
+        self.sqlstate = "00000".to_string();
+        self.stream.reset();
+        let mut packet = Vec::with_capacity(payload.len() + 1);
+        packet.push(command);
+        packet.extend_from_slice(payload);
+        self.write(&packet)
+    }
+
+    /// Reads the first packet of the answer to a statement.
+    fn read_response(&mut self) -> Result<Response, String> {
+        let packet = try!(self.read());
+        match packet.first() {
+            Some(&0x00) => Ok(Response::Done(try!(self.read_ok(&packet)))),
+            Some(&0xff) => Err(self.server_error(&packet)),
+            Some(&0xfb) => {
+                //A LOAD DATA LOCAL INFILE request.  An empty packet tells the server there is no file.
+                try!(self.write(&[]));
+                let _ = self.read_response();
+                Err(self.fail(CR_NOT_IMPLEMENTED, "LOAD DATA LOCAL INFILE isn't supported by the native client."))
+            },
+            _ => {
+                let count = try!(Reader::new(&packet).lenenc());
+                let fields = try!(self.read_fields(count as usize));
+                self.columns = fields.clone();
+                self.pending = Pending::TextRows;
+                Ok(Response::Rows(fields))
+            },
+        }
+    }
+
+    /// Reads and throws away the rest of the last response.
+    pub(crate) fn finish(&mut self) -> Result<(), String> {
+        loop {
+            match self.pending {
+                Pending::Nothing     => return Ok(()),
+                Pending::MoreResults => {
+                    self.pending = Pending::Nothing;
+                    try!(self.read_response());
+                },
+                _ => while try!(self.next_row_packet()).is_some() {},
+            }
+        }
+    }
+
+    /// Sends a query.  If it returned rows, read them with `next_row()`.
+    pub(crate) fn query(&mut self, sql: &str) -> Result<Response, String> {
+        let sql = try!(self.charset.encode(sql));
+        try!(self.command(COM_QUERY, &sql));
+        self.read_response()
+    }
+
+    /// The next row of the current result set, with NULL as None, or None at the end.
+    pub(crate) fn next_row(&mut self) -> Result<Option<Vec<Option<String>>>, String> {
+        let charset = self.charset;
+        Ok(try!(self.next_raw_row()).map(|row| decode_row(row, charset)))
+    }
+
+    /// The next row of the current result with the bytes of each column as they were sent.
+    pub(crate) fn next_raw_row(&mut self) -> Result<Option<Vec<Option<Vec<u8>>>>, String> {
+        if self.pending != Pending::TextRows {
+            return Ok(None);
+        }
+        let packet = match try!(self.next_row_packet()) {
+            Some(packet) => packet,
+            None         => return Ok(None),
+        };
+        let mut r = Reader::new(&packet);
+        let mut row = Vec::with_capacity(self.columns.len());
+        for _ in 0..self.columns.len() {
+            row.push(try!(r.lenenc_bytes()).map(|b| b.to_vec()));
+        }
+        Ok(Some(row))
+    }
+
+    /// Reads the next row of the current result set, text or binary, as it was sent.  None at
+    /// the end, after reading what the server says about the statement.
+    fn next_row_packet(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let packet = try!(self.read());
+        match packet.first() {
+            Some(&0xfe) if packet.len() < 9 => {
+                try!(self.read_eof(&packet));
+                self.pending = if self.status & STATUS_MORE_RESULTS != 0 { Pending::MoreResults } else { Pending::Nothing };
+                Ok(None)
+            },
+            Some(&0xff) => {
+                self.pending = Pending::Nothing;
+                Err(self.server_error(&packet))
+            },
+            _ => Ok(Some(packet)),
+        }
+    }
+
+    /// Changes the character set, on the server and for the text sent and read here.
+    pub(crate) fn set_charset(&mut self, name: &str) -> Result<(), String> {
+        try!(self.query(&format!("SET NAMES {};", name)));
+        self.charset = Charset::from_name(name);
+        Ok(())
+    }
+
+    /// Makes `db` the current database.
+    pub(crate) fn select_db(&mut self, db: &str) -> Result<(), String> {
+        let db = try!(self.charset.encode(db));
+        try!(self.command(COM_INIT_DB, &db));
+        self.expect_ok()
+    }
+
+    /// Checks the connection is still alive.
+    pub(crate) fn ping(&mut self) -> Result<(), String> {
+        try!(self.command(COM_PING, &[]));
+        self.expect_ok()
+    }
+
+    fn expect_ok(&mut self) -> Result<(), String> {
+        let packet = try!(self.read());
+        if packet.first() == Some(&0xff) {
+            return Err(self.server_error(&packet));
+        }
+        self.read_ok(&packet).map(|_| ())
+    }
+
+    /// Prepares a statement on the server.  The id is the server's.
+    pub(crate) fn prepare(&mut self, sql: &str) -> Result<PreparedStatement, String> {
+        let sql = try!(self.charset.encode(sql));
+        try!(self.command(COM_STMT_PREPARE, &sql));
+        let packet = try!(self.read());
+        if packet.first() == Some(&0xff) {
+            return Err(self.server_error(&packet));
+        }
+        let mut r = Reader::new(&packet);
+        try!(r.u8());
+        let id = try!(r.u32());
+        let columns = try!(r.u16()) as usize;
+        let params = try!(r.u16()) as usize;
+        try!(self.read_fields(params));
+        let fields = try!(self.read_fields(columns));
+        self.statements.insert(id, NativeStatement { param_count: params, fields: fields.clone(), fetch: 0, fetched: VecDeque::new() });
+        Ok(PreparedStatement { id: id, param_count: params, fields: fields })
+    }
+
+    /// Executes a prepared statement.  If it returned rows, read them with
+    /// `next_statement_row()`.  With `prefetch_rows` above 0 they are fetched through a
+    /// read-only cursor, that many at a time.
+    pub(crate) fn execute(&mut self, id: u32, params: &[SQLType], prefetch_rows: u64) -> Result<Response, String> {
+        let param_count = match self.statements.get_mut(&id) {
+            Some(statement) => {
+                statement.fetch = 0;
+                statement.fetched.clear();
+                statement.param_count
+            },
+            None => return Err(unknown_statement(id)),
+        };
+        if params.len() != param_count {
+            return Err(format!("The statement takes {} parameters, but {} were given.", param_count, params.len()));
+        }
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&id.to_le_bytes());
+        payload.push(if prefetch_rows > 0 { CURSOR_TYPE_READ_ONLY } else { 0 });
+        //One iteration.
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        if !params.is_empty() {
+            let mut nulls = vec![0u8; params.len().div_ceil(8)];
+            let mut types = Vec::with_capacity(params.len() * 2);
+            let mut values = Vec::new();
+            for (i, param) in params.iter().enumerate() {
+                let param_type = match *param {
+                    SQLType::Tiny(v)   => { values.extend_from_slice(&v.to_le_bytes()); MYSQL_TYPE_TINY },
+                    SQLType::Short(v)  => { values.extend_from_slice(&v.to_le_bytes()); MYSQL_TYPE_SHORT },
+                    SQLType::Int(v)    => { values.extend_from_slice(&v.to_le_bytes()); MYSQL_TYPE_LONG },
+                    SQLType::Long(v)   => { values.extend_from_slice(&v.to_le_bytes()); MYSQL_TYPE_LONGLONG },
+                    SQLType::Float(v)  => { values.extend_from_slice(&v.to_le_bytes()); MYSQL_TYPE_FLOAT },
+                    SQLType::Double(v) => { values.extend_from_slice(&v.to_le_bytes()); MYSQL_TYPE_DOUBLE },
+                    SQLType::VarChar(ref s, _) => {
+                        put_lenenc_bytes(&mut values, &try!(self.charset.encode(s)));
+                        MYSQL_TYPE_STRING
+                    },
+                    SQLType::Unsupported(_, _, SQLFieldType::NULL) => { nulls[i / 8] |= 1 << (i % 8); MYSQL_TYPE_NULL },
+                    SQLType::Unsupported(ref literal, ref name, _) =>
+                        return Err(format!("Can't bind {} ({}) to a parameter, only numbers, strings and NULL.", literal, name)),
+                };
+                types.push(param_type as u8);
+                types.push(0);
+            }
+            payload.extend_from_slice(&nulls);
+            //The types are sent along with the values.
+            payload.push(1);
+            payload.extend_from_slice(&types);
+            payload.extend_from_slice(&values);
+        }
+        try!(self.command(COM_STMT_EXECUTE, &payload));
+        let response = try!(self.read_response());
+        if let Response::Rows(ref fields) = response {
+            let cursor = self.status & STATUS_CURSOR_EXISTS != 0;
+            if let Some(statement) = self.statements.get_mut(&id) {
+                statement.fields = fields.clone();
+                if cursor {
+                    statement.fetch = ::std::cmp::min(prefetch_rows, u32::MAX as u64) as u32;
+                }
+            }
+            //With a cursor open the rows are only sent when fetched.
+            self.pending = if cursor { Pending::Nothing } else { Pending::BinaryRows(id) };
+        }
+        Ok(response)
+    }
+
+    /// The next row of a prepared statement's last execution, or None at the end.
+    pub(crate) fn next_statement_row(&mut self, id: u32) -> Result<Option<Vec<Option<String>>>, String> {
+        let charset = self.charset;
+        loop {
+            let fetch = match self.statements.get_mut(&id) {
+                Some(statement) => {
+                    if let Some(packet) = statement.fetched.pop_front() {
+                        return binary_row(&packet, &statement.fields, charset).map(Some);
+                    }
+                    statement.fetch
+                },
+                None => return Err(unknown_statement(id)),
+            };
+            if self.pending == Pending::BinaryRows(id) {
+                return match try!(self.next_row_packet()) {
+                    Some(packet) => binary_row(&packet, &self.statements[&id].fields, charset).map(Some),
+                    None         => Ok(None),
+                };
+            }
+            if fetch == 0 {
+                return Ok(None);
+            }
+            try!(self.fetch(id, fetch));
+        }
+    }
+
+    /// Fetches the next rows from a statement's cursor, and keeps them until they are read.
+    fn fetch(&mut self, id: u32, rows: u32) -> Result<(), String> {
+        let mut payload = Vec::with_capacity(8);
+        payload.extend_from_slice(&id.to_le_bytes());
+        payload.extend_from_slice(&rows.to_le_bytes());
+        let mut fetched = VecDeque::new();
+        let result = self.command(COM_STMT_FETCH, &payload).and_then(|_| {
+            self.pending = Pending::BinaryRows(id);
+            while let Some(packet) = try!(self.next_row_packet()) {
+                fetched.push_back(packet);
+            }
+            Ok(())
+        });
+        let last = result.is_err() || self.status & STATUS_LAST_ROW_SENT != 0;
+        if let Some(statement) = self.statements.get_mut(&id) {
+            statement.fetched = fetched;
+            if last {
+                statement.fetch = 0;
+            }
+        }
+        result
+    }
+
+    /// Throws away the rest of the rows of a prepared statement's last execution, and closes
+    /// its cursor if it has one.
+    pub(crate) fn free_statement_result(&mut self, id: u32) -> Result<(), String> {
+        if self.pending == Pending::BinaryRows(id) {
+            try!(self.finish());
+        }
+        let cursor = match self.statements.get_mut(&id) {
+            Some(statement) => {
+                statement.fetched.clear();
+                ::std::mem::replace(&mut statement.fetch, 0) != 0
+            },
+            None => false,
+        };
+        if cursor {
+            try!(self.command(COM_STMT_RESET, &id.to_le_bytes()));
+            try!(self.expect_ok());
+        }
+        Ok(())
+    }
+
+    /// Closes a prepared statement.  The server doesn't answer.
+    pub(crate) fn close_statement(&mut self, id: u32) -> Result<(), String> {
+        if self.statements.remove(&id).is_none() {
+            return Ok(());
+        }
+        self.command(COM_STMT_CLOSE, &id.to_le_bytes())
+    }
+}
+
+impl Drop for NativeConn {
+    fn drop(&mut self) {
+        if self.error.0 != CR_SERVER_LOST {
+            self.pending = Pending::Nothing;
+            let _ = self.command(COM_QUIT, &[]);
+        }
+    }
+}
+
+/// The native client as a `Backend`.
+#[derive(Default)]
+pub struct NativeBackend {
+    conn: Option<NativeConn>,
+    charset: String,
+    last: Option<ExecResult>,
+    /// The server's id for each prepared statement, under the id handed out for it, which
+    /// stays unique when the connection is opened again and the server starts over.
+    statements: HashMap<u32, u32>,
+    next_statement: u32,
+}
+
+impl NativeBackend {
+    /// A backend that isn't connected yet.
+    pub fn new() -> Self {
+        NativeBackend::default()
+    }
+
+    fn conn(&mut self) -> Result<&mut NativeConn, String> {
+        self.conn.as_mut().ok_or_else(|| "Not connected.".to_string())
+    }
+
+    /// The server's id for a prepared statement.
+    fn statement(&self, id: u32) -> Result<u32, String> {
+        self.statements.get(&id).cloned().ok_or_else(|| unknown_statement(id))
+    }
+}
+
+impl Backend for NativeBackend {
+    fn connect(&mut self, opts: &ConnectOptions) -> Result<(), String> {
+        let conn = try!(NativeConn::connect(opts));
+        self.conn = Some(conn);
+        self.charset = opts.charset.clone();
+        self.statements.clear();
+        Ok(())
+    }
+
+    fn query(&mut self, sql: &str) -> Result<Option<Vec<Field>>, String> {
+        self.last = None;
+        match try!(try!(self.conn()).query(sql)) {
+            Response::Done(result) => {
+                self.last = Some(result);
+                Ok(None)
+            },
+            Response::Rows(fields) => Ok(Some(fields)),
+        }
+    }
+
+    fn next_row(&mut self) -> Result<Option<Vec<Option<String>>>, String> {
+        try!(self.conn()).next_row()
+    }
+
+    fn next_raw_row(&mut self) -> Result<Option<Vec<Option<Vec<u8>>>>, String> {
+        try!(self.conn()).next_raw_row()
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        try!(self.conn()).finish()
+    }
+
+    fn exec_result(&self) -> ExecResult {
+        let warning_count = self.conn.as_ref().map_or(0, |c| c.warning_count());
+        self.last.clone().unwrap_or(ExecResult { affected_rows: u64::MAX, insert_id: 0, warning_count: warning_count, info: None })
+    }
+
+    fn last_error(&self) -> String {
+        self.conn.as_ref().map_or("Not connected.".to_string(), |c| c.last_error())
+    }
+
+    fn last_errno(&self) -> u32 {
+        self.conn.as_ref().map_or(0, |c| c.last_errno())
+    }
+
+    fn sqlstate(&self) -> String {
+        self.conn.as_ref().map_or("HY000".to_string(), |c| c.sqlstate().to_string())
+    }
+
+    fn connection_lost(&self) -> bool {
+        self.conn.as_ref().is_some_and(|c| c.last_errno() == CR_SERVER_LOST || c.last_errno() == CR_SERVER_GONE_ERROR)
+    }
+
+    fn escape(&self, text: &str) -> String {
+        match self.conn {
+            Some(ref conn) if conn.no_backslash_escapes() => text.replace('\'', "''"),
+            _ => escape_string(text),
+        }
+    }
+
+    fn select_db(&mut self, db: &str) -> Result<(), String> {
+        try!(self.conn()).select_db(db)
+    }
+
+    fn charset(&self) -> String {
+        if self.charset.is_empty() { "utf8mb4".to_string() } else { self.charset.clone() }
+    }
+
+    fn set_charset(&mut self, name: &str) -> Result<(), String> {
+        try!(try!(self.conn()).set_charset(name));
+        self.charset = name.to_string();
+        Ok(())
+    }
+
+    fn server_version(&self) -> String {
+        self.conn.as_ref().map_or(String::new(), |c| c.server_version().to_string())
+    }
+
+    fn thread_id(&self) -> u64 {
+        self.conn.as_ref().map_or(0, |c| c.thread_id())
+    }
+
+    fn prepare(&mut self, sql: &str) -> Result<PreparedStatement, String> {
+        let mut prepared = try!(try!(self.conn()).prepare(sql));
+        let id = self.next_statement;
+        self.next_statement = self.next_statement.wrapping_add(1);
+        self.statements.insert(id, prepared.id);
+        prepared.id = id;
+        Ok(prepared)
+    }
+
+    fn execute(&mut self, id: u32, params: &[SQLType], prefetch_rows: u64) -> Result<ExecResult, String> {
+        self.last = None;
+        let server_id = try!(self.statement(id));
+        match try!(try!(self.conn()).execute(server_id, params, prefetch_rows)) {
+            Response::Done(result) => {
+                self.last = Some(result.clone());
+                Ok(result)
+            },
+            Response::Rows(_) => Ok(self.exec_result()),
+        }
+    }
+
+    fn next_statement_row(&mut self, id: u32) -> Result<Option<Vec<Option<String>>>, String> {
+        let server_id = try!(self.statement(id));
+        try!(self.conn()).next_statement_row(server_id)
+    }
+
+    fn free_statement_result(&mut self, id: u32) -> Result<(), String> {
+        let server_id = try!(self.statement(id));
+        try!(self.conn()).free_statement_result(server_id)
+    }
+
+    fn close_statement(&mut self, id: u32) {
+        if let Some(server_id) = self.statements.remove(&id) {
+            if let Some(ref mut conn) = self.conn {
+                let _ = conn.close_statement(server_id);
+            }
+        }
+    }
+}
+
+/// Opens the socket: the unix socket if one was given, or one of the usual places for
+/// "localhost", and TCP otherwise.
+fn open_stream(opts: &ConnectOptions) -> Result<Stream, String> {
+    #[cfg(unix)]
+    {
+        let socket = match opts.unix_socket {
+            Some(ref path) => Some(path.as_str()),
+            None if opts.host.is_empty() || opts.host == "localhost" =>
+                DEFAULT_SOCKETS.iter().cloned().find(|path| Path::new(path).exists()),
+            None => None,
+        };
+        if let Some(path) = socket {
+            return UnixStream::connect(path).map(Stream::Unix).map_err(|e| format!("{} ({})", e, path));
+        }
+    }
+    let host = if opts.host.is_empty() { "localhost" } else { &opts.host };
+    let port = if opts.port == 0 { DEFAULT_PORT } else { opts.port };
+    let stream = match opts.connect_timeout {
+        Some(seconds) => try!(connect_timeout(host, port, Duration::from_secs(seconds as u64))),
+        None          => try!(TcpStream::connect((host, port)).map_err(|e| format!("{} ({}:{})", e, host, port))),
+    };
+    let _ = stream.set_nodelay(true);
+    Ok(Stream::Tcp(stream))
+}
+
+/// Tries each address of the host in turn, giving each the whole timeout.
+fn connect_timeout(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, String> {
+    let addrs = try!((host, port).to_socket_addrs().map_err(|e| format!("{} ({}:{})", e, host, port)));
+    let mut last = format!("No addresses for {}", host);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e)     => last = format!("{} ({}:{})", e, host, port),
+        }
+    }
+    Err(last)
+}
+
+/// Reads a row of the binary protocol, and writes each value out as the text protocol would.
+fn binary_row(packet: &[u8], fields: &[Field], charset: Charset) -> Result<Vec<Option<String>>, String> {
+    let mut r = Reader::new(packet);
+    try!(r.u8());
+    //The NULL bitmap of a result row starts at bit 2.
+    let nulls = try!(r.bytes((fields.len() + 2).div_ceil(8)));
+    let mut row = Vec::with_capacity(fields.len());
+    for (i, field) in fields.iter().enumerate() {
+        if nulls[(i + 2) / 8] & (1 << ((i + 2) % 8)) != 0 {
+            row.push(None);
+        } else {
+            row.push(Some(try!(binary_value(&mut r, field, charset))));
+        }
+    }
+    Ok(row)
+}
+
+fn binary_value(r: &mut Reader, field: &Field, charset: Charset) -> Result<String, String> {
+    let unsigned = field.is_unsigned();
+    let integer = |r: &mut Reader, size: usize| -> Result<String, String> {
+        let value = try!(r.uint(size));
+        if unsigned || size == 8 && value <= i64::MAX as u64 {
+            Ok(value.to_string())
+        } else {
+            //Sign-extend from the width of the column.
+            let shift = 64 - size * 8;
+            Ok((((value << shift) as i64) >> shift).to_string())
+        }
+    };
+    Ok(match field.field_type {
+        SQLFieldType::Tiny                       => try!(integer(r, 1)),
+        SQLFieldType::Short | SQLFieldType::Year => try!(integer(r, 2)),
+        SQLFieldType::Long | SQLFieldType::Int24 => try!(integer(r, 4)),
+        SQLFieldType::LongLong                   => try!(integer(r, 8)),
+        SQLFieldType::Float  => f32::from_bits(try!(r.u32())).to_string(),
+        SQLFieldType::Double => f64::from_bits(try!(r.uint(8))).to_string(),
+        SQLFieldType::Date | SQLFieldType::NewDate => {
+            let length = try!(r.u8()) as usize;
+            let mut parts = Reader::new(try!(r.bytes(length)));
+            let (year, month, day) = if length >= 4 { (try!(parts.u16()), try!(parts.u8()), try!(parts.u8())) } else { (0, 0, 0) };
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        },
+        SQLFieldType::DateTime | SQLFieldType::DateTime2 | SQLFieldType::Timestamp | SQLFieldType::TimeStamp2 => {
+            let length = try!(r.u8()) as usize;
+            let mut parts = Reader::new(try!(r.bytes(length)));
+            let mut values = [0u64; 7];
+            for (i, value) in values.iter_mut().enumerate() {
+                let size = if i == 0 { 2 } else if i == 6 { 4 } else { 1 };
+                if parts.is_empty() {
+                    break;
+                }
+                *value = try!(parts.uint(size));
+            }
+            format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}{}", values[0], values[1], values[2], values[3], values[4], values[5],
+                    fraction(values[6], field.decimals))
+        },
+        SQLFieldType::Time | SQLFieldType::Time2 => {
+            let length = try!(r.u8()) as usize;
+            let mut parts = Reader::new(try!(r.bytes(length)));
+            if length == 0 {
+                "00:00:00".to_string()
+            } else {
+                let negative = try!(parts.u8()) != 0;
+                let hours = try!(parts.u32()) as u64 * 24 + try!(parts.u8()) as u64;
+                let (minutes, seconds) = (try!(parts.u8()), try!(parts.u8()));
+                let micros = if parts.is_empty() { 0 } else { try!(parts.u32()) as u64 };
+                format!("{}{:02}:{:02}:{:02}{}", if negative { "-" } else { "" }, hours, minutes, seconds, fraction(micros, field.decimals))
+            }
+        },
+        _ => charset.decode(try!(r.lenenc_bytes()).unwrap_or(b"")),
+    })
+}
+
+/// The fractional seconds of a temporal value, to as many digits as the column has.
+fn fraction(micros: u64, decimals: u32) -> String {
+    if decimals == 0 || decimals > 6 {
+        return String::new();
+    }
+    format!(".{:06}", micros)[..decimals as usize + 1].to_string()
+}
+
+#[test]
+fn test_binary_row() {
+    assert_eq!(fraction(123456, 0), "");
+    assert_eq!(fraction(123456, 3), ".123");
+    assert_eq!(fraction(5, 6), ".000005");
+    assert_eq!(fraction(5, 31), "");
+    let field = |field_type, flags, decimals| Field { name: String::new(), table: String::new(), field_type: field_type, length: 0,
+                                                      decimals: decimals, flags: flags, charset: 45 };
+    let fields = [field(SQLFieldType::Tiny, 0, 0), field(SQLFieldType::Long, 32, 0), field(SQLFieldType::VarString, 0, 0),
+                  field(SQLFieldType::DateTime, 0, 3), field(SQLFieldType::Time, 0, 0)];
+    //The header, then a NULL bitmap with the third column (bit 4) set.
+    let mut packet = vec![0x00, 0b0001_0000];
+    packet.push(0xff);
+    packet.extend_from_slice(&u32::MAX.to_le_bytes());
+    packet.extend_from_slice(&[11, 0xe8, 0x07, 2, 29, 13, 5, 9]);
+    packet.extend_from_slice(&250_000u32.to_le_bytes());
+    packet.extend_from_slice(&[8, 1, 1, 0, 0, 0, 2, 3, 4]);
+    assert_eq!(binary_row(&packet, &fields, Charset::from_name("utf8mb4")).unwrap(),
+               vec![Some("-1".to_string()), Some(u32::MAX.to_string()), None, Some("2024-02-29 13:05:09.250".to_string()),
+                    Some("-26:03:04".to_string())]);
+}
+
+#[test]
+fn test_collation_id() {
+    assert_eq!(collation_id("UTF8MB4"), Some(45));
+    assert_eq!(collation_id("sjis"), None);
+}
+
+#[test]
+fn test_connection_native() {
+    #[cfg(feature = "test-server")]
+    let test_server = ::testing::TestServer::start().unwrap();
+    #[cfg(feature = "test-server")]
+    let opts = test_server.options();
+    #[cfg(not(feature = "test-server"))]
+    let opts = ConnectOptions::new("localhost", "", "", "test");
+    let mut conn = NativeConn::connect(&opts).unwrap();
+    match conn.query("SELECT 1, NULL, 'a';").unwrap() {
+        Response::Rows(fields) => assert_eq!(fields.len(), 3),
+        Response::Done(_)      => panic!("expected rows"),
+    }
+    assert_eq!(conn.next_row().unwrap(), Some(vec![Some("1".to_string()), None, Some("a".to_string())]));
+    assert_eq!(conn.next_row().unwrap(), None);
+    let statement = conn.prepare("SELECT ? + 1, ?").unwrap();
+    assert_eq!(statement.param_count, 2);
+    conn.execute(statement.id, &[SQLType::Int(41), SQLType::VarChar("b".to_string(), 1)], 0).unwrap();
+    assert_eq!(conn.next_statement_row(statement.id).unwrap(), Some(vec![Some("42".to_string()), Some("b".to_string())]));
+    assert_eq!(conn.next_statement_row(statement.id).unwrap(), None);
+    conn.close_statement(statement.id).unwrap();
+    conn.ping().unwrap();
+}
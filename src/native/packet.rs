@@ -0,0 +1,297 @@
+//! Framing of protocol packets, and reading and writing the values inside them.
+
+use ::std::io::{Read, Write};
+use ::std::net::TcpStream;
+use ::std::time::Duration;
+#[cfg(unix)]
+use ::std::os::unix::net::UnixStream;
+
+/// The largest payload one packet can carry.  Longer payloads are split, and a payload of
+/// exactly this length is followed by an empty packet.
+const MAX_PAYLOAD: usize = 0xff_ffff;
+
+/// The socket to the server.
+pub(crate) enum Stream {
+    /// A TCP connection.
+    Tcp(TcpStream),
+    /// A unix socket.
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Sets how long a read or a write may block before failing.  None blocks forever.
+    pub(crate) fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> ::std::io::Result<()> {
+        match *self {
+            Stream::Tcp(ref s)  => s.set_read_timeout(read).and_then(|_| s.set_write_timeout(write)),
+            #[cfg(unix)]
+            Stream::Unix(ref s) => s.set_read_timeout(read).and_then(|_| s.set_write_timeout(write)),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s)  => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s)  => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut s)  => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// Reads and writes whole packets, keeping track of the sequence id.
+pub(crate) struct PacketStream {
+    stream: Stream,
+    seq: u8,
+    /// The largest payload, split or not, that is read.  A server sending more is refused
+    /// rather than filling the memory.
+    max_packet: usize,
+    /// Set by any error, after which the stream is somewhere in the middle of a packet and
+    /// can't be used again.
+    broken: bool,
+}
+
+impl PacketStream {
+    pub(crate) fn new(stream: Stream, max_packet: usize) -> Self {
+        PacketStream { stream: stream, seq: 0, max_packet: max_packet, broken: false }
+    }
+
+    /// Starts a new command, whose first packet has sequence id 0.
+    pub(crate) fn reset(&mut self) {
+        self.seq = 0;
+    }
+
+    /// Reads one payload, joining it back together if the server split it.
+    pub(crate) fn read_packet(&mut self) -> Result<Vec<u8>, String> {
+        if self.broken {
+            return Err("The connection was broken by an earlier error.".to_string());
+        }
+        let result = self.read_payload();
+        self.broken = result.is_err();
+        result
+    }
+
+    fn read_payload(&mut self) -> Result<Vec<u8>, String> {
+        let mut payload = Vec::new();
+        loop {
+            let mut header = [0u8; 4];
+            try!(self.stream.read_exact(&mut header).map_err(|e| format!("Lost connection to the server: {}", e)));
+            let length = header[0] as usize | (header[1] as usize) << 8 | (header[2] as usize) << 16;
+            if header[3] != self.seq {
+                return Err(format!("Packets out of order, expected {} but got {}.", self.seq, header[3]));
+            }
+            self.seq = self.seq.wrapping_add(1);
+            let start = payload.len();
+            if start + length > self.max_packet {
+                return Err(format!("The server sent a packet larger than max_allowed_packet ({} bytes).", self.max_packet));
+            }
+            payload.resize(start + length, 0);
+            try!(self.stream.read_exact(&mut payload[start..]).map_err(|e| format!("Lost connection to the server: {}", e)));
+            if length < MAX_PAYLOAD {
+                return Ok(payload);
+            }
+        }
+    }
+
+    /// Writes one payload, splitting it into as many packets as it takes.
+    pub(crate) fn write_packet(&mut self, payload: &[u8]) -> Result<(), String> {
+        if self.broken {
+            return Err("The connection was broken by an earlier error.".to_string());
+        }
+        let result = self.write_payload(payload);
+        self.broken = result.is_err();
+        result
+    }
+
+    fn write_payload(&mut self, payload: &[u8]) -> Result<(), String> {
+        let mut out = Vec::with_capacity(payload.len() + 4);
+        for chunk in payload.chunks(MAX_PAYLOAD) {
+            push_header(&mut out, chunk.len(), self.seq);
+            out.extend_from_slice(chunk);
+            self.seq = self.seq.wrapping_add(1);
+        }
+        //The reader only knows it has the whole payload once a packet comes up short.
+        if payload.len().is_multiple_of(MAX_PAYLOAD) {
+            push_header(&mut out, 0, self.seq);
+            self.seq = self.seq.wrapping_add(1);
+        }
+        try!(self.stream.write_all(&out).map_err(|e| format!("Lost connection to the server: {}", e)));
+        self.stream.flush().map_err(|e| format!("Lost connection to the server: {}", e))
+    }
+}
+
+fn push_header(out: &mut Vec<u8>, length: usize, seq: u8) {
+    out.extend_from_slice(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, seq]);
+}
+
+/// Reads values from a payload in order.  Running off the end is an error rather than a panic,
+/// so a malformed packet can't take the process down.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Reader { buf: buf, pos: 0 }
+    }
+
+    /// Returns true once everything has been read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    /// The next `n` bytes.
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.pos < n {
+            return Err("Malformed packet from the server.".to_string());
+        }
+        let out = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    /// Everything left.
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        let out = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        out
+    }
+
+    /// A little-endian unsigned number of `n` bytes.
+    pub(crate) fn uint(&mut self, n: usize) -> Result<u64, String> {
+        let bytes = try!(self.bytes(n));
+        Ok(bytes.iter().rev().fold(0, |value, &b| value << 8 | b as u64))
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(try!(self.bytes(1))[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, String> {
+        Ok(try!(self.uint(2)) as u16)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, String> {
+        Ok(try!(self.uint(4)) as u32)
+    }
+
+    /// A length-encoded integer, or None for the 0xfb that stands for NULL in a row.
+    pub(crate) fn lenenc_int(&mut self) -> Result<Option<u64>, String> {
+        match try!(self.u8()) {
+            0xfb => Ok(None),
+            0xfc => Ok(Some(try!(self.uint(2)))),
+            0xfd => Ok(Some(try!(self.uint(3)))),
+            0xfe => Ok(Some(try!(self.uint(8)))),
+            0xff => Err("Malformed packet from the server.".to_string()),
+            n    => Ok(Some(n as u64)),
+        }
+    }
+
+    /// A length-encoded integer where NULL isn't allowed.
+    pub(crate) fn lenenc(&mut self) -> Result<u64, String> {
+        try!(self.lenenc_int()).ok_or_else(|| "Malformed packet from the server.".to_string())
+    }
+
+    /// A string prefixed with its length, or None for NULL.
+    pub(crate) fn lenenc_bytes(&mut self) -> Result<Option<&'a [u8]>, String> {
+        match try!(self.lenenc_int()) {
+            Some(n) => Ok(Some(try!(self.bytes(n as usize)))),
+            None    => Ok(None),
+        }
+    }
+
+    /// A string ending in a NUL, without the NUL.  The end of the payload ends it too.
+    pub(crate) fn null_terminated(&mut self) -> &'a [u8] {
+        let rest = &self.buf[self.pos..];
+        match rest.iter().position(|&b| b == 0) {
+            Some(end) => {
+                self.pos += end + 1;
+                &rest[..end]
+            },
+            None => self.rest(),
+        }
+    }
+}
+
+/// Appends a length-encoded integer.
+pub(crate) fn put_lenenc_int(out: &mut Vec<u8>, n: u64) {
+    if n < 0xfb {
+        out.push(n as u8);
+    } else if n <= 0xffff {
+        out.push(0xfc);
+        out.extend_from_slice(&(n as u16).to_le_bytes());
+    } else if n <= 0xff_ffff {
+        out.push(0xfd);
+        out.extend_from_slice(&(n as u32).to_le_bytes()[..3]);
+    } else {
+        out.push(0xfe);
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+}
+
+/// Appends a string prefixed with its length.
+pub(crate) fn put_lenenc_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_lenenc_int(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+#[test]
+fn test_lenenc() {
+    let mut out = Vec::new();
+    for &n in &[0u64, 250, 251, 0xffff, 0x10000, 0xff_ffff, 0x100_0000, u64::MAX] {
+        put_lenenc_int(&mut out, n);
+    }
+    put_lenenc_bytes(&mut out, b"abc");
+    out.push(0xfb);
+    out.extend_from_slice(b"db\0");
+    let mut reader = Reader::new(&out);
+    for &n in &[0u64, 250, 251, 0xffff, 0x10000, 0xff_ffff, 0x100_0000, u64::MAX] {
+        assert_eq!(reader.lenenc().unwrap(), n);
+    }
+    assert_eq!(reader.lenenc_bytes().unwrap(), Some(&b"abc"[..]));
+    assert_eq!(reader.lenenc_bytes().unwrap(), None);
+    assert_eq!(reader.null_terminated(), b"db");
+    assert!(reader.is_empty());
+    assert!(reader.u32().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_packet_too_large() {
+    let (client, mut server) = UnixStream::pair().unwrap();
+    //One full packet, then one more byte than max_allowed_packet leaves room for.
+    let writer = ::std::thread::spawn(move || {
+        let mut out = Vec::new();
+        push_header(&mut out, MAX_PAYLOAD, 0);
+        out.resize(out.len() + MAX_PAYLOAD, b'x');
+        push_header(&mut out, 1, 1);
+        out.push(b'x');
+        let _ = server.write_all(&out);
+    });
+    let mut stream = PacketStream::new(Stream::Unix(client), MAX_PAYLOAD);
+    assert!(stream.read_packet().unwrap_err().contains("max_allowed_packet"));
+    //The rest of the payload is still unread, so the stream can't be used again.
+    assert!(stream.read_packet().unwrap_err().contains("broken"));
+    assert!(stream.write_packet(b"\x0e").unwrap_err().contains("broken"));
+    drop(stream);
+    writer.join().unwrap();
+}
@@ -0,0 +1,260 @@
+//! Watching the statements a connection sends.
+//!
+//! Register a `QueryObserver` with `ConnectOptions::observer()` or `Connection::add_observer()`
+//! and it hears about every statement: queries, `raw_query_no_res()`, stored results and
+//! prepared statements.  A statement that returns rows is reported once they have all been read
+//! or the rows were dropped, so its duration includes reading them.  `QueryLogger` is an
+//! observer that writes a line per statement, or only for the slow ones.
+
+use ::std::io::{self, Write};
+use ::std::sync::Mutex;
+use ::std::time::{Duration, Instant};
+
+/// What happened to one statement.
+#[derive(Clone, Debug)]
+pub struct QueryEvent<'a> {
+    /// The statement as it was sent.  See `redacted_sql()` for a version without the values.
+    pub sql: &'a str,
+    /// How long it took, including reading its rows.
+    pub duration: Duration,
+    /// How many rows it returned, for a statement that returns rows and whose rows were
+    /// counted.  None otherwise.
+    pub rows: Option<u64>,
+    /// How many rows it changed, for a statement that doesn't return rows.
+    pub affected_rows: u64,
+    /// How many warnings it raised.
+    pub warning_count: u32,
+    /// The bytes of column values read, for rows read through `query_iter()` and what is built
+    /// on it.  0 otherwise.
+    pub bytes_received: u64,
+    /// The error it failed with, if it did.
+    pub error: Option<&'a str>,
+    /// The error code it failed with, or 0.
+    pub errno: u32,
+    /// The SQLSTATE it failed with, or "00000".
+    pub sqlstate: &'a str,
+}
+
+impl<'a> QueryEvent<'a> {
+    /// A statement that worked, and returned no rows or rows that weren't read.
+    pub(crate) fn done(sql: &'a str, started: Instant, affected_rows: u64, warning_count: u32) -> Self {
+        QueryEvent { sql: sql, duration: started.elapsed(), rows: None, affected_rows: affected_rows, warning_count: warning_count,
+                     bytes_received: 0, error: None, errno: 0, sqlstate: "00000" }
+    }
+
+    /// A statement whose rows were read.
+    pub(crate) fn read(sql: &'a str, started: Instant, rows: u64, bytes_received: u64, warning_count: u32) -> Self {
+        QueryEvent { rows: Some(rows), bytes_received: bytes_received, ..QueryEvent::done(sql, started, 0, warning_count) }
+    }
+
+    /// A statement that failed.
+    pub(crate) fn failed(sql: &'a str, started: Instant, error: &'a str, errno: u32, sqlstate: &'a str) -> Self {
+        QueryEvent { error: Some(error), errno: errno, sqlstate: sqlstate, ..QueryEvent::done(sql, started, 0, 0) }
+    }
+
+    /// The statement with its literal values replaced by `?`, see `redact_sql()`.
+    pub fn redacted_sql(&self) -> String {
+        redact_sql(self.sql)
+    }
+}
+
+/// Something told about every statement a connection sends.  Observers are shared between
+/// connections, so they are called from whichever thread the connection is on.
+pub trait QueryObserver: Send + Sync {
+    /// Called once the statement has finished, or failed.
+    fn on_query(&self, event: &QueryEvent);
+}
+
+impl<F> QueryObserver for F where F: Fn(&QueryEvent) + Send + Sync {
+    fn on_query(&self, event: &QueryEvent) {
+        self(event)
+    }
+}
+
+/// Replaces the string, number and hex literals in a statement with `?`, so it can be logged
+/// without the values in it.  Identifiers in backticks, keywords and comments are left alone,
+/// except for `/*! ... */` and `/*+ ... */`, which the server runs as SQL.
+pub fn redact_sql(sql: &str) -> String {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut out = String::with_capacity(sql.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let prev_is_word = i > 0 && (chars[i - 1].is_alphanumeric() || chars[i - 1] == '_' || chars[i - 1] == '$');
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            if chars.get(i + 2) == Some(&'!') || chars.get(i + 2) == Some(&'+') {
+                out.extend(&chars[i..i + 3]);
+                i += 3;
+            } else {
+                let end = skip_comment(&chars, i + 2, "*/");
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+        } else if c == '#' || (c == '-' && chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_none_or(|c| c.is_whitespace())) {
+            let end = skip_comment(&chars, i + 1, "\n");
+            out.extend(&chars[i..end]);
+            i = end;
+        } else if c == '\'' || c == '"' {
+            i = skip_quoted(&chars, i);
+            out.push('?');
+        } else if c == '`' {
+            let end = skip_quoted(&chars, i);
+            out.extend(&chars[i..end]);
+            i = end;
+        } else if (c == 'x' || c == 'X' || c == 'b' || c == 'B') && !prev_is_word && chars.get(i + 1) == Some(&'\'') {
+            i = skip_quoted(&chars, i + 1);
+            out.push('?');
+        } else if c.is_ascii_digit() && !prev_is_word {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            out.push('?');
+        } else {
+            out.push(c);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// The position after the `end` of a comment, searching from `from`.  The end of the text ends
+/// an unclosed one.
+fn skip_comment(chars: &[char], from: usize, end: &str) -> usize {
+    let end: Vec<char> = end.chars().collect();
+    (from..chars.len()).find(|&i| chars[i..].starts_with(&end)).map_or(chars.len(), |i| i + end.len())
+}
+
+/// The position after a quoted string starting at `start`, allowing for backslash escapes and
+/// doubled quotes.  The end of the text ends an unclosed one.
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == '\\' && quote != '`' {
+            i += 2;
+        } else if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+/// A statement that returns rows, followed until its rows are done with.
+pub(crate) struct Trace {
+    pub(crate) sql: String,
+    pub(crate) started: Instant,
+    pub(crate) rows: u64,
+    pub(crate) bytes: u64,
+    pub(crate) error: Option<(String, u32, String)>,
+}
+
+impl Trace {
+    pub(crate) fn new(sql: &str, started: Instant) -> Self {
+        Trace { sql: sql.to_string(), started: started, rows: 0, bytes: 0, error: None }
+    }
+}
+
+/// Writes a line for every statement, or with `slow_threshold()` only for the slow ones and the
+/// ones that failed.
+pub struct QueryLogger {
+    out: Mutex<Box<dyn Write + Send>>,
+    redact: bool,
+    slow: Option<Duration>,
+}
+
+impl QueryLogger {
+    /// A logger that writes to standard error.
+    pub fn stderr() -> Self {
+        QueryLogger::new(io::stderr())
+    }
+
+    /// A logger that writes to `out`, such as a file.
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        QueryLogger { out: Mutex::new(Box::new(out)), redact: false, slow: None }
+    }
+
+    /// Log statements with their literal values replaced by `?`, and errors by their code and
+    /// SQLSTATE, since the server's messages quote values too.  Off by default.
+    pub fn redact(mut self, enable: bool) -> Self {
+        self.redact = enable;
+        self
+    }
+
+    /// Only log statements that took at least this long, and those that failed.
+    pub fn slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow = Some(threshold);
+        self
+    }
+}
+
+impl QueryObserver for QueryLogger {
+    fn on_query(&self, event: &QueryEvent) {
+        if self.slow.is_some_and(|slow| event.duration < slow) && event.error.is_none() {
+            return;
+        }
+        let sql = if self.redact { event.redacted_sql() } else { event.sql.to_string() };
+        let millis = event.duration.as_secs_f64() * 1000.0;
+        let outcome = match (event.error, event.rows) {
+            (Some(_), _) if self.redact => format!("failed (error {}, SQLSTATE {})", event.errno, event.sqlstate),
+            (Some(error), _) => format!("failed ({})", error),
+            (None, Some(rows)) => format!("{} rows, {} warnings", rows, event.warning_count),
+            (None, None) => format!("{} affected, {} warnings", event.affected_rows, event.warning_count),
+        };
+        if let Ok(mut out) = self.out.lock() {
+            //A log that can't be written to shouldn't break the queries.
+            let _ = writeln!(out, "[mariadb-rs] {:.3} ms, {}: {}", millis, outcome, sql.trim());
+        }
+    }
+}
+
+#[test]
+fn test_observers() {
+    use ::mock::{MockBackend, MockResponse};
+    use ::options::ConnectOptions;
+    use ::std::sync::Arc;
+
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    assert_eq!(redact_sql("SELECT `a1`, 'it''s', \"x\\\"y\", 42, 1.5e3, X'ff', col2 FROM t WHERE id=7"),
+               "SELECT `a1`, ?, ?, ?, ?, ?, col2 FROM t WHERE id=?");
+    assert_eq!(redact_sql("/* don't */ SELECT 'secret' -- it's\n# isn't\nFROM t /*+ SET_VAR(x=1) */"),
+               "/* don't */ SELECT ? -- it's\n# isn't\nFROM t /*+ SET_VAR(x=?) */");
+
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let logger = QueryLogger::new(Shared(buffer.clone())).redact(true);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = events.clone();
+    let mock = MockBackend::new()
+        .on("SELECT name", MockResponse::rows(&["name"], vec![vec![Some("ann")], vec![Some("bob")]]))
+        .on("DELETE", MockResponse::affected(2))
+        .on("bad", MockResponse::error("syntax error"));
+    let conn = ConnectOptions::new("mock", "", "", "").observer(Arc::new(logger)).connect_with(mock).unwrap();
+    conn.add_observer(Arc::new(move |event: &QueryEvent| {
+        seen.lock().unwrap().push((event.sql.to_string(), event.rows, event.affected_rows, event.error.is_some()));
+    }));
+    assert_eq!(conn.raw_query("SELECT name FROM users WHERE id > 1;", 1).unwrap().len(), 2);
+    conn.raw_query_no_res("DELETE FROM users WHERE name = 'ann';").unwrap();
+    assert!(conn.raw_query_no_res("bad;").is_err());
+    assert_eq!(*events.lock().unwrap(), vec![("SELECT name FROM users WHERE id > 1;".to_string(), Some(2), 0, false),
+                                             ("DELETE FROM users WHERE name = 'ann';".to_string(), None, 2, false),
+                                             ("bad;".to_string(), None, 0, true)]);
+    let log = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = log.lines().map(|l| &l[l.find(" ms, ").unwrap() + 5..]).collect();
+    assert_eq!(lines, vec!["2 rows, 0 warnings: SELECT name FROM users WHERE id > ?;",
+                           "2 affected, 0 warnings: DELETE FROM users WHERE name = ?;",
+                           "failed (error 1105, SQLSTATE HY000): bad;"]);
+}
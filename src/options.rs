@@ -0,0 +1,326 @@
+//! Settings used when opening a connection.
+
+use ::backend::Backend;
+use ::connection::Connection;
+use ::cstr::from_cstr;
+use ::libc::{c_char, gethostname};
+use ::metrics::Metrics;
+use ::observe::QueryObserver;
+use ::reconnect::ReconnectPolicy;
+use ::types::SQLType;
+use ::std::fmt;
+use ::std::str::FromStr;
+use ::std::sync::Arc;
+
+/// Whether to use TLS, and how far to trust the server's certificate.  The names match the
+/// `--ssl-mode` option of the `mysql` tools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SslMode {
+    /// Never use TLS.  The default.
+    Disabled,
+    /// Use TLS if the server supports it.
+    Preferred,
+    /// Fail unless the connection uses TLS.  The certificate isn't checked.
+    Required,
+    /// Also check the server's certificate against the CA.  The client library checks the host
+    /// name too, so this is the same as `VerifyIdentity` there.
+    VerifyCa,
+    /// Also check that the certificate is for the host connected to.
+    VerifyIdentity,
+}
+
+impl SslMode {
+    /// Returns true if connecting must fail when the connection doesn't use TLS.
+    pub fn is_required(&self) -> bool {
+        *self != SslMode::Disabled && *self != SslMode::Preferred
+    }
+}
+
+impl FromStr for SslMode {
+    type Err = String;
+
+    /// Parses a mode such as "REQUIRED" or "verify-ca", in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_uppercase().replace('-', "_") {
+            "DISABLED"        => Ok(SslMode::Disabled),
+            "PREFERRED"       => Ok(SslMode::Preferred),
+            "REQUIRED"        => Ok(SslMode::Required),
+            "VERIFY_CA"       => Ok(SslMode::VerifyCa),
+            "VERIFY_IDENTITY" => Ok(SslMode::VerifyIdentity),
+            _                 => Err(format!("Unknown SSL mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SslMode::Disabled       => "DISABLED",
+            SslMode::Preferred      => "PREFERRED",
+            SslMode::Required       => "REQUIRED",
+            SslMode::VerifyCa       => "VERIFY_CA",
+            SslMode::VerifyIdentity => "VERIFY_IDENTITY",
+        })
+    }
+}
+
+/// Everything needed to open a connection.  Build one with `ConnectOptions::new()`, change what
+/// you need with the builder methods, then call `connect()`.  Can also be parsed from a URL,
+/// see `FromStr`.  Neither `Display` nor `Debug` show the password.
+#[derive(Clone)]
+pub struct ConnectOptions {
+    pub(crate) host: String,
+    pub(crate) user: String,
+    pub(crate) password: String,
+    pub(crate) database: String,
+    pub(crate) port: u16,
+    pub(crate) unix_socket: Option<String>,
+    pub(crate) local_infile: bool,
+    pub(crate) ssl_mode: SslMode,
+    pub(crate) ssl_ca: Option<String>,
+    pub(crate) connect_timeout: Option<u32>,
+    pub(crate) read_timeout: Option<u32>,
+    pub(crate) write_timeout: Option<u32>,
+    pub(crate) max_allowed_packet: usize,
+    pub(crate) default_file: Option<String>,
+    pub(crate) default_group: Option<String>,
+    pub(crate) charset: String,
+    pub(crate) reconnect: ReconnectPolicy,
+    pub(crate) session_vars: Vec<(String, SQLType)>,
+    pub(crate) connect_attrs: Vec<(String, String)>,
+    pub(crate) observers: Vec<Arc<dyn QueryObserver>>,
+    pub(crate) metrics: Option<Metrics>,
+}
+
+/// The largest `max_allowed_packet` a server can be set to, 1GB.
+pub(crate) const DEFAULT_MAX_ALLOWED_PACKET: usize = 1 << 30;
+
+/// The name of this machine, if it has one.
+fn local_hostname() -> Option<String> {
+    let mut buf = [0 as c_char; 256];
+    if unsafe { gethostname(buf.as_mut_ptr(), buf.len() - 1) } != 0 {
+        return None;
+    }
+    let name = from_cstr(buf.as_ptr());
+    if name.is_empty() { None } else { Some(name) }
+}
+
+/// The connection attributes every connection starts with.  Names starting with an underscore
+/// are reserved for the client library, which replaces any we set, so these don't use one.
+fn default_connect_attrs() -> Vec<(String, String)> {
+    let mut attrs = vec![("client_name".to_string(), "mariadb-rs".to_string()),
+                         ("client_version".to_string(), env!("CARGO_PKG_VERSION").to_string())];
+    if let Some(host) = local_hostname() {
+        attrs.push(("client_host".to_string(), host));
+    }
+    attrs
+}
+
+impl ConnectOptions {
+    /// Options for connecting to a server at the given address, with the given username,
+    /// password, and database name.
+    pub fn new(address: &str, user: &str, password: &str, database: &str) -> Self {
+        ConnectOptions {
+            host: address.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            database: database.to_string(),
+            port: 0,
+            unix_socket: None,
+            local_infile: false,
+            ssl_mode: SslMode::Disabled,
+            ssl_ca: None,
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            max_allowed_packet: DEFAULT_MAX_ALLOWED_PACKET,
+            default_file: None,
+            default_group: None,
+            charset: "utf8mb4".to_string(),
+            reconnect: ReconnectPolicy::never(),
+            session_vars: Vec::new(),
+            connect_attrs: default_connect_attrs(),
+            observers: Vec::new(),
+            metrics: None,
+        }
+    }
+
+    /// The TCP port to connect to.  0 (the default) uses the client library's default port.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Connect through the given unix socket instead of TCP.
+    pub fn unix_socket(mut self, path: &str) -> Self {
+        self.unix_socket = Some(path.to_string());
+        self
+    }
+
+    /// Allow `LOAD DATA LOCAL INFILE`.  Off by default.
+    /// Only streams handed to `Connection::load_data_local` are ever sent to the server; any
+    /// other request for a local file is refused, and no file is read from disk.
+    pub fn local_infile(mut self, enable: bool) -> Self {
+        self.local_infile = enable;
+        self
+    }
+
+    /// Whether to use TLS.  Defaults to `SslMode::Disabled`.
+    pub fn ssl_mode(mut self, mode: SslMode) -> Self {
+        self.ssl_mode = mode;
+        self
+    }
+
+    /// The file with the certificates of the CAs to trust, for the verifying SSL modes.
+    pub fn ssl_ca(mut self, path: &str) -> Self {
+        self.ssl_ca = Some(path.to_string());
+        self
+    }
+
+    /// Give up connecting after this many seconds.  By default the system's TCP timeout applies.
+    pub fn connect_timeout(mut self, seconds: u32) -> Self {
+        self.connect_timeout = Some(seconds);
+        self
+    }
+
+    /// Give up waiting for the server to answer after this many seconds.  The client library
+    /// retries a read that times out, so a statement can wait up to three times as long.  The
+    /// statement keeps running on the server, see `Connection::cancel_handle()` to stop it.
+    pub fn read_timeout(mut self, seconds: u32) -> Self {
+        self.read_timeout = Some(seconds);
+        self
+    }
+
+    /// Give up sending to the server after this many seconds.
+    pub fn write_timeout(mut self, seconds: u32) -> Self {
+        self.write_timeout = Some(seconds);
+        self
+    }
+
+    /// The largest packet, in bytes, the native client reads from the server.  A larger one
+    /// fails the statement and breaks the connection.  Defaults to 1GB, the most a server
+    /// allows; the client library always allows that much.
+    pub fn max_allowed_packet(mut self, bytes: usize) -> Self {
+        self.max_allowed_packet = bytes;
+        self
+    }
+
+    /// Reads this option file instead of the default ones when connecting.  Whatever the
+    /// options leave empty (host, user, password, database, port, socket) comes from its
+    /// `[client]` group, and from the group given to `read_default_group()`.
+    pub fn read_default_file(mut self, path: &str) -> Self {
+        self.default_file = Some(path.to_string());
+        self
+    }
+
+    /// Also reads this group of the option files, after `[client]`.  Without
+    /// `read_default_file()`, the default files such as `~/.my.cnf` are read.
+    pub fn read_default_group(mut self, group: &str) -> Self {
+        self.default_group = Some(group.to_string());
+        self
+    }
+
+    /// The character set for queries and results.  Defaults to utf8mb4, which holds any text.
+    pub fn charset(mut self, name: &str) -> Self {
+        self.charset = name.to_string();
+        self
+    }
+
+    /// What to do when the connection to the server is lost.  Defaults to `ReconnectPolicy::never()`.
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Set a session variable right after connecting, and again after every reconnect.
+    pub fn session_var(mut self, name: &str, value: SQLType) -> Self {
+        self.session_vars.push((name.to_string(), value));
+        self
+    }
+
+    /// Names the program in the connection attributes, as the `mysql` tools do.
+    pub fn program_name(self, name: &str) -> Self {
+        self.connect_attr("program_name", name)
+    }
+
+    /// Adds a connection attribute, shown in `performance_schema.session_connect_attrs`,
+    /// replacing any attribute with the same key.  Every connection starts with `client_name`
+    /// (mariadb-rs), `client_version` (the crate version) and `client_host`.
+    pub fn connect_attr(mut self, key: &str, value: &str) -> Self {
+        self.connect_attrs.retain(|a| a.0 != key);
+        self.connect_attrs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Removes a connection attribute, including one of the defaults.
+    pub fn remove_connect_attr(mut self, key: &str) -> Self {
+        self.connect_attrs.retain(|a| a.0 != key);
+        self
+    }
+
+    /// Removes every connection attribute, including the defaults.  The client library still
+    /// sends its own.
+    pub fn clear_connect_attrs(mut self) -> Self {
+        self.connect_attrs.clear();
+        self
+    }
+
+    /// Tells `observer` about every statement of every connection opened with these options.
+    pub fn observer(mut self, observer: Arc<dyn QueryObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Counts the statements and reconnects of every connection opened with these options in
+    /// `metrics`.
+    pub fn metrics(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self.observer(Arc::new(metrics.clone()))
+    }
+
+    /// Attempts to connect using these options.
+    pub fn connect(&self) -> Result<Connection, String> {
+        Connection::connect(self)
+    }
+
+    /// Attempts to connect through the given backend instead of the client library.
+    pub fn connect_with<B: Backend + 'static>(&self, backend: B) -> Result<Connection, String> {
+        Connection::with_backend(self, Box::new(backend))
+    }
+}
+
+impl fmt::Debug for ConnectOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectOptions")
+            .field("host", &self.host)
+            .field("user", &self.user)
+            .field("password", &if self.password.is_empty() { "" } else { "***" })
+            .field("database", &self.database)
+            .field("port", &self.port)
+            .field("unix_socket", &self.unix_socket)
+            .field("local_infile", &self.local_infile)
+            .field("ssl_mode", &self.ssl_mode)
+            .field("ssl_ca", &self.ssl_ca)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_timeout", &self.read_timeout)
+            .field("write_timeout", &self.write_timeout)
+            .field("max_allowed_packet", &self.max_allowed_packet)
+            .field("default_file", &self.default_file)
+            .field("default_group", &self.default_group)
+            .field("charset", &self.charset)
+            .field("reconnect", &self.reconnect)
+            .field("session_vars", &self.session_vars)
+            .field("connect_attrs", &self.connect_attrs)
+            .field("observers", &self.observers.len())
+            .field("metrics", &self.metrics.is_some())
+            .finish()
+    }
+}
+
+#[test]
+fn test_connect_attrs() {
+    let opts = ConnectOptions::new("localhost", "", "", "").program_name("billing").connect_attr("client_name", "custom");
+    assert!(opts.connect_attrs.contains(&("program_name".to_string(), "billing".to_string())));
+    assert_eq!(opts.connect_attrs.iter().filter(|a| a.0 == "client_name").count(), 1);
+    assert!(opts.remove_connect_attr("client_version").connect_attrs.iter().all(|a| a.0 != "client_version"));
+}
@@ -0,0 +1,254 @@
+//! A pool of connections shared between threads.
+//!
+//! `Pool::get()` hands out an idle connection, opens a new one while there are fewer than the
+//! maximum, and otherwise waits for one to be returned.  Connections go back to the pool when
+//! the `PooledConnection` is dropped, after rolling back any transaction left open.  One that
+//! lost its connection, or was moved to another database or user or had session variables set
+//! through `switch_db()`, `change_user()` or `set_session_var()`, is closed instead.  Session
+//! state changed with raw statements, such as `SET @x` or `SET SESSION`, isn't noticed.
+
+use ::backend::Backend;
+use ::connection::Connection;
+use ::metrics::Metrics;
+use ::observe::QueryObserver;
+use ::options::ConnectOptions;
+use ::std::ops::{Deref, DerefMut};
+use ::std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use ::std::time::{Duration, Instant};
+
+/// How long `get()` waits for a connection by default.
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+
+struct PoolState {
+    idle: Vec<Connection>,
+    /// Connections open, idle or handed out.
+    open: usize,
+}
+
+/// A pool of connections opened with the same options.  Share it between threads by reference
+/// or in an `Arc`.
+pub struct Pool {
+    opts: ConnectOptions,
+    max_size: usize,
+    wait_timeout: Duration,
+    backend: Option<Box<dyn Fn() -> Box<dyn Backend> + Send + Sync>>,
+    metrics: Option<Metrics>,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+impl Pool {
+    /// A pool of at most `max_size` connections opened with `opts`.  None are opened until they
+    /// are asked for.
+    pub fn new(opts: ConnectOptions, max_size: usize) -> Self {
+        Pool {
+            opts: opts,
+            max_size: max_size.max(1),
+            wait_timeout: DEFAULT_WAIT,
+            backend: None,
+            metrics: None,
+            state: Mutex::new(PoolState { idle: Vec::new(), open: 0 }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// How long `get()` waits for a connection to be returned before giving up.  30 seconds by
+    /// default.
+    pub fn wait_timeout(mut self, timeout: Duration) -> Self {
+        self.wait_timeout = timeout;
+        self
+    }
+
+    /// Counts the pool and the statements of its connections in `metrics`.
+    pub fn metrics(mut self, metrics: &Metrics) -> Self {
+        self.opts = self.opts.clone().metrics(metrics);
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Tells `observer` about every statement of every connection in the pool.
+    pub fn observer(mut self, observer: Arc<dyn QueryObserver>) -> Self {
+        self.opts = self.opts.clone().observer(observer);
+        self
+    }
+
+    /// Opens connections through the backends `make` returns, instead of the client library.
+    pub fn backend<F>(mut self, make: F) -> Self where F: Fn() -> Box<dyn Backend> + Send + Sync + 'static {
+        self.backend = Some(Box::new(make));
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        //The state is only changed in single steps, so it is fine after a panic elsewhere.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn open(&self) -> Result<Connection, String> {
+        match self.backend {
+            Some(ref make) => Connection::with_backend(&self.opts, make()),
+            None           => Connection::connect(&self.opts),
+        }
+    }
+
+    /// A connection from the pool, waiting for one to be returned if they are all in use.
+    pub fn get(&self) -> Result<PooledConnection<'_>, String> {
+        let started = Instant::now();
+        let deadline = started + self.wait_timeout;
+        let mut state = self.lock();
+        loop {
+            if let Some(conn) = state.idle.pop() {
+                drop(state);
+                if let Some(ref metrics) = self.metrics {
+                    metrics.record_pool_wait(started.elapsed());
+                    metrics.record_pool_change(1, -1);
+                }
+                return Ok(PooledConnection { pool: self, conn: Some(conn) });
+            }
+            if state.open < self.max_size {
+                state.open += 1;
+                drop(state);
+                //Connecting can take a while, so it is done without holding the lock.
+                return match self.open() {
+                    Ok(conn) => {
+                        if let Some(ref metrics) = self.metrics {
+                            metrics.record_pool_wait(started.elapsed());
+                            metrics.record_pool_change(1, 0);
+                        }
+                        Ok(PooledConnection { pool: self, conn: Some(conn) })
+                    },
+                    Err(e) => {
+                        self.lock().open -= 1;
+                        self.available.notify_one();
+                        Err(e)
+                    },
+                };
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                if let Some(ref metrics) = self.metrics {
+                    metrics.record_pool_timeout();
+                }
+                return Err(format!("Timed out after {:?} waiting for a connection from the pool.", self.wait_timeout));
+            }
+            state = self.available.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// The most connections the pool opens.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// How many connections are handed out.
+    pub fn in_use(&self) -> usize {
+        let state = self.lock();
+        state.open - state.idle.len()
+    }
+
+    /// How many connections are waiting to be handed out.
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    /// Returns true if the connection can be handed out again as it is.
+    fn reusable(&self, conn: &Connection) -> bool {
+        if conn.connection_lost() {
+            return false;
+        }
+        //Whoever gets the connection next expects a session as the options made it.
+        if conn.current_db() != self.opts.database || conn.opts.user != self.opts.user || !conn.session_vars.borrow().is_empty() {
+            return false;
+        }
+        //A transaction left open would carry over too, including one started with a raw
+        //`BEGIN` that in_transaction() doesn't know about, so this is sent every time.
+        conn.rollback().is_ok()
+    }
+
+    fn put_back(&self, conn: Connection) {
+        let healthy = self.reusable(&conn);
+        let mut state = self.lock();
+        if healthy {
+            state.idle.push(conn);
+        } else {
+            state.open -= 1;
+        }
+        drop(state);
+        if let Some(ref metrics) = self.metrics {
+            metrics.record_pool_change(-1, if healthy { 1 } else { 0 });
+        }
+        self.available.notify_one();
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        if let Some(ref metrics) = self.metrics {
+            metrics.record_pool_change(0, -(self.lock().idle.len() as i64));
+        }
+    }
+}
+
+/// A connection borrowed from a `Pool`, which it goes back to when dropped.  Use it as a
+/// `Connection`.
+pub struct PooledConnection<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+}
+
+impl<'a> Deref for PooledConnection<'a> {
+    type Target = Connection;
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("a pooled connection is only taken when dropped")
+    }
+}
+
+impl<'a> DerefMut for PooledConnection<'a> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("a pooled connection is only taken when dropped")
+    }
+}
+
+impl<'a> Drop for PooledConnection<'a> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.put_back(conn);
+        }
+    }
+}
+
+#[test]
+fn test_pool() {
+    use ::mock::MockBackend;
+    let metrics = Metrics::new();
+    let pool = Pool::new(ConnectOptions::new("mock", "", "", ""), 2)
+        .wait_timeout(Duration::from_millis(20))
+        .metrics(&metrics)
+        .backend(|| Box::new(MockBackend::new()));
+    {
+        let first = pool.get().unwrap();
+        first.begin_transaction().unwrap();
+        let second = pool.get().unwrap();
+        second.raw_query_no_res("DELETE FROM t;").unwrap();
+        assert!(pool.get().is_err());
+        assert_eq!((pool.in_use(), pool.idle()), (2, 0));
+    }
+    assert_eq!((pool.in_use(), pool.idle()), (0, 2));
+    assert!(!pool.get().unwrap().in_transaction());
+    let snapshot = metrics.snapshot();
+    assert_eq!((snapshot.pool_in_use, snapshot.pool_idle, snapshot.pool_timeouts, snapshot.pool_wait.count), (0, 2, 1, 3));
+    //The BEGIN, and a ROLLBACK each time a connection went back.
+    assert_eq!(snapshot.queries["TRANSACTION"].count, 4);
+    assert_eq!(snapshot.queries["DELETE"].count, 1);
+
+    //Connections whose session was changed, or that lost the connection, are closed.
+    pool.get().unwrap().switch_db("other".to_string()).unwrap();
+    assert_eq!((pool.in_use(), pool.idle()), (0, 1));
+    pool.get().unwrap().set_session_var("sql_mode", ::types::SQLType::VarChar("ANSI".to_string(), 0)).unwrap();
+    assert_eq!((pool.in_use(), pool.idle()), (0, 0));
+    let lost = Pool::new(ConnectOptions::new("mock", "", "", ""), 1)
+        .backend(|| Box::new(MockBackend::new().on("SELECT 1", ::mock::MockResponse::Lost)));
+    assert!(lost.get().unwrap().raw_query_no_res("SELECT 1;").is_err());
+    assert_eq!((lost.in_use(), lost.idle()), (0, 0));
+    lost.get().unwrap();
+    assert_eq!(lost.idle(), 1);
+}
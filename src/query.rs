@@ -0,0 +1,698 @@
+//! A small query builder for SELECT / INSERT / UPDATE / DELETE statements.
+//!
+//! Identifiers are always quoted with backticks.  Values are kept apart from the SQL text until
+//! the very end, so a builder can either be rendered as one escaped string with `to_sql()`, or
+//! as a statement with `?` placeholders plus its parameters with `to_prepared()`.
+
+use ::types::SQLType;
+
+/// Quotes an identifier with backticks, doubling any backticks inside of it.
+/// A dotted name such as `db.table` or `table.column` has every part quoted on its own, and a
+/// lone `*` is passed through untouched.
+pub fn quote_ident(name: &str) -> String {
+    name.split('.').map(|part| {
+        if part == "*" {
+            part.to_string()
+        } else {
+            format!("`{}`", part.replace('`', "``"))
+        }
+    }).collect::<Vec<String>>().join(".")
+}
+
+/// Escapes a string so it can be placed between single quotes in a query.
+/// Escapes the same characters as `mysql_escape_string`, except that a quote is doubled rather
+/// than escaped with a backslash, so a server running with `NO_BACKSLASH_ESCAPES` can't take it
+/// for the end of the string.  `Connection::escape()` also follows that mode for the rest.
+pub fn escape_string(str_in: &str) -> String {
+    let mut str_out = String::with_capacity(str_in.len());
+    for c in str_in.chars() {
+        match c {
+            '\0'     => str_out.push_str("\\0"),
+            '\n'     => str_out.push_str("\\n"),
+            '\r'     => str_out.push_str("\\r"),
+            '\\'     => str_out.push_str("\\\\"),
+            '\''     => str_out.push_str("''"),
+            '"'      => str_out.push_str("\\\""),
+            '\x1a'   => str_out.push_str("\\Z"),
+            _        => str_out.push(c),
+        }
+    }
+    str_out
+}
+
+/// Sort direction for ORDER BY.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Order {
+    /// Ascending
+    Asc,
+    /// Descending
+    Desc,
+}
+
+/// A piece of a statement, either plain SQL text or a bound value.
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Param(SQLType),
+}
+
+/// SQL text with the bound values kept to the side.
+#[derive(Clone, Debug)]
+struct Fragment {
+    parts: Vec<Part>,
+}
+
+impl Fragment {
+    fn new() -> Self {
+        Fragment { parts: Vec::new() }
+    }
+    fn text(&mut self, text: &str) {
+        if let Some(&mut Part::Text(ref mut last)) = self.parts.last_mut() {
+            last.push_str(text);
+            return;
+        }
+        self.parts.push(Part::Text(text.to_string()));
+    }
+    fn param(&mut self, value: &SQLType) {
+        self.parts.push(Part::Param(value.clone()));
+    }
+    fn append(&mut self, other: &Fragment) {
+        for part in &other.parts {
+            match *part {
+                Part::Text(ref t)  => self.text(t),
+                Part::Param(ref v) => self.param(v),
+            }
+        }
+    }
+    fn to_sql(&self) -> Result<String, String> {
+        let mut sql = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref t)  => sql.push_str(t),
+                Part::Param(ref v) => sql.push_str(&try!(v.to_sql_literal())),
+            }
+        }
+        Ok(sql)
+    }
+    fn to_prepared(&self) -> (String, Vec<SQLType>) {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref t)  => sql.push_str(t),
+                Part::Param(ref v) => { sql.push('?'); params.push(v.clone()); },
+            }
+        }
+        (sql, params)
+    }
+}
+
+/// Comparison operators that are allowed in a where clause.
+const OPERATORS: [&'static str; 10] = ["=", "<>", "!=", "<", "<=", ">", ">=", "<=>", "LIKE", "NOT LIKE"];
+
+/// The shared join / where / order / limit state of SELECT, UPDATE and DELETE.
+#[derive(Clone, Debug)]
+struct Clauses {
+    joins: Vec<String>,
+    conditions: Vec<(&'static str, Fragment)>,
+    group_by: Vec<String>,
+    order_by: Vec<String>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    /// The first mistake made while building, returned when the statement is rendered.
+    error: Option<String>,
+}
+
+impl Clauses {
+    fn new() -> Self {
+        Clauses {
+            joins: Vec::new(),
+            conditions: Vec::new(),
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            error: None,
+        }
+    }
+    fn join(&mut self, kind: &str, table: &str, left: &str, right: &str) {
+        self.joins.push(format!(" {} {} ON {} = {}", kind, quote_ident(table), quote_ident(left), quote_ident(right)));
+    }
+    fn condition(&mut self, glue: &'static str, column: &str, op: &str, value: &SQLType) {
+        let op = op.trim().to_uppercase();
+        if !OPERATORS.contains(&op.as_str()) {
+            self.fail(format!("Invalid operator in where clause: {}", op));
+            return;
+        }
+        let mut frag = Fragment::new();
+        frag.text(&format!("{} {} ", quote_ident(column), op));
+        frag.param(value);
+        self.push(glue, frag);
+    }
+    /// Adds a condition.  Mixing AND and OR puts everything before in parentheses, so the
+    /// conditions apply in the order they were added: `a AND b OR c` becomes `(a AND b) OR c`,
+    /// and a further AND applies to all of that.
+    fn push(&mut self, glue: &'static str, frag: Fragment) {
+        if self.conditions.len() > 1 && self.conditions.last().map(|c| c.0) != Some(glue) {
+            let mut grouped = Fragment::new();
+            self.write_conditions(&mut grouped);
+            self.conditions = vec![(glue, grouped)];
+        }
+        self.conditions.push((glue, frag));
+    }
+    /// Adds the conditions of a group in parentheses, as one condition.
+    fn group(&mut self, glue: &'static str, group: Where) {
+        if let Some(e) = group.clauses.error {
+            self.fail(e);
+        } else if !group.clauses.conditions.is_empty() {
+            let mut frag = Fragment::new();
+            group.clauses.write_conditions(&mut frag);
+            self.push(glue, frag);
+        }
+    }
+    fn fail(&mut self, error: String) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+    fn check(&self) -> Result<(), String> {
+        match self.error {
+            Some(ref e) => Err(e.clone()),
+            None        => Ok(()),
+        }
+    }
+    fn condition_in(&mut self, glue: &'static str, column: &str, values: &[SQLType]) {
+        let mut frag = Fragment::new();
+        if values.is_empty() {
+            //`col IN ()` is a syntax error, and matching nothing is what an empty list means.
+            frag.text("FALSE");
+        } else {
+            frag.text(&format!("{} IN (", quote_ident(column)));
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    frag.text(", ");
+                }
+                frag.param(v);
+            }
+            frag.text(")");
+        }
+        self.push(glue, frag);
+    }
+    fn condition_text(&mut self, glue: &'static str, text: &str) {
+        let mut frag = Fragment::new();
+        frag.text(text);
+        self.push(glue, frag);
+    }
+    fn write_joins(&self, out: &mut Fragment) {
+        for j in &self.joins {
+            out.text(j);
+        }
+    }
+    /// Writes the conditions in parentheses, unless there is only one.
+    fn write_conditions(&self, out: &mut Fragment) {
+        let many = self.conditions.len() > 1;
+        if many {
+            out.text("(");
+        }
+        for (i, &(glue, ref frag)) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                out.text(glue);
+            }
+            out.append(frag);
+        }
+        if many {
+            out.text(")");
+        }
+    }
+    fn write_where(&self, out: &mut Fragment) {
+        for (i, &(glue, ref frag)) in self.conditions.iter().enumerate() {
+            if i == 0 {
+                out.text(" WHERE ");
+            } else {
+                out.text(glue);
+            }
+            out.append(frag);
+        }
+    }
+    fn write_tail(&self, out: &mut Fragment) {
+        if !self.group_by.is_empty() {
+            out.text(&format!(" GROUP BY {}", self.group_by.join(", ")));
+        }
+        if !self.order_by.is_empty() {
+            out.text(&format!(" ORDER BY {}", self.order_by.join(", ")));
+        }
+        match (self.limit, self.offset) {
+            (Some(l), Some(o)) => out.text(&format!(" LIMIT {} OFFSET {}", l, o)),
+            (Some(l), None)    => out.text(&format!(" LIMIT {}", l)),
+            //MySQL has no OFFSET without LIMIT, this is the documented workaround.
+            (None, Some(o))    => out.text(&format!(" LIMIT 18446744073709551615 OFFSET {}", o)),
+            (None, None)       => {},
+        }
+    }
+}
+
+/// Adds the where clause methods to a builder with a `clauses` field.
+///
+/// Conditions apply in the order they are added.  Each AND or OR applies to everything before
+/// it, so `.and_where(a).and_where(b).or_where(c)` is `(a AND b) OR c`.  Use `and_group()` for
+/// `a AND (b OR c)`.
+macro_rules! impl_where_methods {
+    ($name:ident) => (
+        impl $name {
+            /// Adds the conditions `build` adds to an empty group, in parentheses, joined to
+            /// earlier conditions with AND.
+            pub fn and_group<F: FnOnce(Where) -> Where>(mut self, build: F) -> Self {
+                self.clauses.group(" AND ", build(Where { clauses: Clauses::new() }));
+                self
+            }
+            /// Adds the conditions `build` adds to an empty group, in parentheses, joined to
+            /// earlier conditions with OR.
+            pub fn or_group<F: FnOnce(Where) -> Where>(mut self, build: F) -> Self {
+                self.clauses.group(" OR ", build(Where { clauses: Clauses::new() }));
+                self
+            }
+            /// Adds `column op value` to the where clause, joined to earlier conditions with AND.
+            /// If `op` is not a comparison operator, rendering the statement fails.
+            pub fn and_where(mut self, column: &str, op: &str, value: SQLType) -> Self {
+                self.clauses.condition(" AND ", column, op, &value);
+                self
+            }
+            /// Adds `column op value` to the where clause, joined to earlier conditions with OR.
+            /// If `op` is not a comparison operator, rendering the statement fails.
+            pub fn or_where(mut self, column: &str, op: &str, value: SQLType) -> Self {
+                self.clauses.condition(" OR ", column, op, &value);
+                self
+            }
+            /// Adds `column IN (values...)` to the where clause, joined with AND.
+            pub fn where_in(mut self, column: &str, values: Vec<SQLType>) -> Self {
+                self.clauses.condition_in(" AND ", column, &values);
+                self
+            }
+            /// Adds `column IS NULL` to the where clause, joined with AND.
+            pub fn where_null(mut self, column: &str) -> Self {
+                let text = format!("{} IS NULL", quote_ident(column));
+                self.clauses.condition_text(" AND ", &text);
+                self
+            }
+            /// Adds `column IS NOT NULL` to the where clause, joined with AND.
+            pub fn where_not_null(mut self, column: &str) -> Self {
+                let text = format!("{} IS NOT NULL", quote_ident(column));
+                self.clauses.condition_text(" AND ", &text);
+                self
+            }
+        }
+    );
+}
+
+/// Adds the join and where clause methods to a builder with a `clauses` field.
+macro_rules! impl_clause_methods {
+    ($name:ident) => (
+        impl $name {
+            /// Adds an `INNER JOIN table ON left = right`.
+            pub fn join(mut self, table: &str, left: &str, right: &str) -> Self {
+                self.clauses.join("INNER JOIN", table, left, right);
+                self
+            }
+            /// Adds a `LEFT JOIN table ON left = right`.
+            pub fn left_join(mut self, table: &str, left: &str, right: &str) -> Self {
+                self.clauses.join("LEFT JOIN", table, left, right);
+                self
+            }
+        }
+        impl_where_methods!($name);
+    );
+}
+
+/// A group of conditions, which `and_group()` and `or_group()` put in parentheses.
+#[derive(Clone, Debug)]
+pub struct Where {
+    clauses: Clauses,
+}
+impl_where_methods!(Where);
+
+/// Builder for a SELECT statement.
+#[derive(Clone, Debug)]
+pub struct Select {
+    table: String,
+    columns: Vec<String>,
+    distinct: bool,
+    clauses: Clauses,
+}
+
+impl Select {
+    /// Starts a `SELECT * FROM table`.
+    pub fn from(table: &str) -> Self {
+        Select {
+            table: table.to_string(),
+            columns: Vec::new(),
+            distinct: false,
+            clauses: Clauses::new(),
+        }
+    }
+    /// Adds a column to the select list.  Selects `*` if no columns are given.
+    pub fn column(mut self, name: &str) -> Self {
+        self.columns.push(quote_ident(name));
+        self
+    }
+    /// Adds several columns to the select list.
+    pub fn columns(mut self, names: &[&str]) -> Self {
+        for n in names {
+            self.columns.push(quote_ident(n));
+        }
+        self
+    }
+    /// Adds a raw expression, such as `COUNT(*)`, to the select list.
+    /// The expression is not quoted or escaped, so never build it from user input.
+    pub fn expr(mut self, expression: &str) -> Self {
+        self.columns.push(expression.to_string());
+        self
+    }
+    /// Makes this a SELECT DISTINCT.
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+    /// Adds a column to the GROUP BY clause.
+    pub fn group_by(mut self, column: &str) -> Self {
+        self.clauses.group_by.push(quote_ident(column));
+        self
+    }
+    /// Adds a column to the ORDER BY clause.
+    pub fn order_by(mut self, column: &str, order: Order) -> Self {
+        let dir = match order { Order::Asc => "ASC", Order::Desc => "DESC" };
+        self.clauses.order_by.push(format!("{} {}", quote_ident(column), dir));
+        self
+    }
+    /// Limits the number of returned rows.
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.clauses.limit = Some(limit);
+        self
+    }
+    /// Skips the first `offset` rows.
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.clauses.offset = Some(offset);
+        self
+    }
+    fn build(&self) -> Result<Fragment, String> {
+        try!(self.clauses.check());
+        let mut out = Fragment::new();
+        out.text("SELECT ");
+        if self.distinct {
+            out.text("DISTINCT ");
+        }
+        if self.columns.is_empty() {
+            out.text("*");
+        } else {
+            out.text(&self.columns.join(", "));
+        }
+        out.text(&format!(" FROM {}", quote_ident(&self.table)));
+        self.clauses.write_joins(&mut out);
+        self.clauses.write_where(&mut out);
+        self.clauses.write_tail(&mut out);
+        Ok(out)
+    }
+    /// Renders the statement with every value escaped inline.  Fails for values that have no
+    /// safe literal, see `SQLType::to_sql_literal()`.
+    pub fn to_sql(&self) -> Result<String, String> {
+        try!(self.build()).to_sql()
+    }
+    /// Renders the statement with `?` placeholders, along with the values to bind to them.
+    pub fn to_prepared(&self) -> Result<(String, Vec<SQLType>), String> {
+        self.build().map(|out| out.to_prepared())
+    }
+}
+impl_clause_methods!(Select);
+
+/// Builder for an INSERT statement.
+#[derive(Clone, Debug)]
+pub struct Insert {
+    verb: &'static str,
+    table: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<SQLType>>,
+    on_duplicate: Vec<Fragment>,
+}
+
+impl Insert {
+    /// Starts an `INSERT INTO table`.
+    pub fn into(table: &str) -> Self {
+        Insert {
+            verb: "INSERT INTO",
+            table: table.to_string(),
+            columns: Vec::new(),
+            rows: Vec::new(),
+            on_duplicate: Vec::new(),
+        }
+    }
+    /// Makes this an `INSERT IGNORE`, which skips rows that would cause a duplicate key.
+    pub fn ignore(mut self) -> Self {
+        self.verb = "INSERT IGNORE INTO";
+        self
+    }
+    /// Makes this a `REPLACE`, which deletes the old row on a duplicate key before inserting.
+    pub fn replace(mut self) -> Self {
+        self.verb = "REPLACE INTO";
+        self
+    }
+    /// Sets the column list.  If left empty, values must be given for every column of the table
+    /// in order.
+    pub fn columns(mut self, names: &[&str]) -> Self {
+        self.columns = names.iter().map(|n| quote_ident(n)).collect();
+        self
+    }
+    /// Adds a row of values.  Calling this more than once makes a multi-row insert.
+    pub fn values(mut self, row: Vec<SQLType>) -> Self {
+        self.rows.push(row);
+        self
+    }
+    /// On a duplicate key, overwrite these columns with the values that were being inserted.
+    pub fn on_duplicate_key_update(mut self, columns: &[&str]) -> Self {
+        for c in columns {
+            let mut frag = Fragment::new();
+            frag.text(&format!("{0} = VALUES({0})", quote_ident(c)));
+            self.on_duplicate.push(frag);
+        }
+        self
+    }
+    /// On a duplicate key, set the column to the given value.
+    pub fn on_duplicate_key_set(mut self, column: &str, value: SQLType) -> Self {
+        let mut frag = Fragment::new();
+        frag.text(&format!("{} = ", quote_ident(column)));
+        frag.param(&value);
+        self.on_duplicate.push(frag);
+        self
+    }
+    fn build(&self) -> Result<Fragment, String> {
+        if self.rows.is_empty() {
+            return Err(format!("An INSERT into {} needs at least one row of values.", self.table));
+        }
+        for row in &self.rows {
+            if row.is_empty() || (!self.columns.is_empty() && row.len() != self.columns.len()) {
+                return Err(format!("An INSERT into {} has a row of {} values for {} columns.", self.table, row.len(), self.columns.len()));
+            }
+        }
+        let mut out = Fragment::new();
+        out.text(&format!("{} {}", self.verb, quote_ident(&self.table)));
+        if !self.columns.is_empty() {
+            out.text(&format!(" ({})", self.columns.join(", ")));
+        }
+        out.text(" VALUES ");
+        for (i, row) in self.rows.iter().enumerate() {
+            if i > 0 {
+                out.text(", ");
+            }
+            out.text("(");
+            for (j, v) in row.iter().enumerate() {
+                if j > 0 {
+                    out.text(", ");
+                }
+                out.param(v);
+            }
+            out.text(")");
+        }
+        for (i, frag) in self.on_duplicate.iter().enumerate() {
+            out.text(if i == 0 { " ON DUPLICATE KEY UPDATE " } else { ", " });
+            out.append(frag);
+        }
+        Ok(out)
+    }
+    /// Renders the statement with every value escaped inline.  Fails for values that have no
+    /// safe literal, see `SQLType::to_sql_literal()`.
+    pub fn to_sql(&self) -> Result<String, String> {
+        try!(self.build()).to_sql()
+    }
+    /// Renders the statement with `?` placeholders, along with the values to bind to them.
+    pub fn to_prepared(&self) -> Result<(String, Vec<SQLType>), String> {
+        self.build().map(|out| out.to_prepared())
+    }
+}
+
+/// Builder for an UPDATE statement, which can span several tables through joins.
+#[derive(Clone, Debug)]
+pub struct Update {
+    table: String,
+    sets: Vec<Fragment>,
+    clauses: Clauses,
+}
+
+impl Update {
+    /// Starts an `UPDATE table`.
+    pub fn table(table: &str) -> Self {
+        Update {
+            table: table.to_string(),
+            sets: Vec::new(),
+            clauses: Clauses::new(),
+        }
+    }
+    /// Sets a column to a value.  Use `table.column` to set columns of a joined table.
+    pub fn set(mut self, column: &str, value: SQLType) -> Self {
+        let mut frag = Fragment::new();
+        frag.text(&format!("{} = ", quote_ident(column)));
+        frag.param(&value);
+        self.sets.push(frag);
+        self
+    }
+    /// Limits the number of updated rows.  Only valid for single table updates.
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.clauses.limit = Some(limit);
+        self
+    }
+    fn build(&self) -> Result<Fragment, String> {
+        try!(self.clauses.check());
+        if self.sets.is_empty() {
+            return Err(format!("An UPDATE of {} needs at least one column to set.", self.table));
+        }
+        let mut out = Fragment::new();
+        out.text(&format!("UPDATE {}", quote_ident(&self.table)));
+        self.clauses.write_joins(&mut out);
+        for (i, frag) in self.sets.iter().enumerate() {
+            out.text(if i == 0 { " SET " } else { ", " });
+            out.append(frag);
+        }
+        self.clauses.write_where(&mut out);
+        self.clauses.write_tail(&mut out);
+        Ok(out)
+    }
+    /// Renders the statement with every value escaped inline.  Fails for values that have no
+    /// safe literal, see `SQLType::to_sql_literal()`.
+    pub fn to_sql(&self) -> Result<String, String> {
+        try!(self.build()).to_sql()
+    }
+    /// Renders the statement with `?` placeholders, along with the values to bind to them.
+    pub fn to_prepared(&self) -> Result<(String, Vec<SQLType>), String> {
+        self.build().map(|out| out.to_prepared())
+    }
+}
+impl_clause_methods!(Update);
+
+/// Builder for a DELETE statement, which can span several tables through joins.
+#[derive(Clone, Debug)]
+pub struct Delete {
+    table: String,
+    targets: Vec<String>,
+    clauses: Clauses,
+}
+
+impl Delete {
+    /// Starts a `DELETE FROM table`.
+    pub fn from(table: &str) -> Self {
+        Delete {
+            table: table.to_string(),
+            targets: Vec::new(),
+            clauses: Clauses::new(),
+        }
+    }
+    /// Deletes matching rows from the given table.  Only needed for a multi-table delete, where
+    /// it picks which of the joined tables to delete from.
+    pub fn target(mut self, table: &str) -> Self {
+        self.targets.push(quote_ident(table));
+        self
+    }
+    /// Limits the number of deleted rows.  Only valid for single table deletes.
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.clauses.limit = Some(limit);
+        self
+    }
+    fn build(&self) -> Result<Fragment, String> {
+        try!(self.clauses.check());
+        let mut out = Fragment::new();
+        out.text("DELETE ");
+        if !self.targets.is_empty() {
+            out.text(&format!("{} ", self.targets.join(", ")));
+        }
+        out.text(&format!("FROM {}", quote_ident(&self.table)));
+        self.clauses.write_joins(&mut out);
+        self.clauses.write_where(&mut out);
+        self.clauses.write_tail(&mut out);
+        Ok(out)
+    }
+    /// Renders the statement with every value escaped inline.  Fails for values that have no
+    /// safe literal, see `SQLType::to_sql_literal()`.
+    pub fn to_sql(&self) -> Result<String, String> {
+        try!(self.build()).to_sql()
+    }
+    /// Renders the statement with `?` placeholders, along with the values to bind to them.
+    pub fn to_prepared(&self) -> Result<(String, Vec<SQLType>), String> {
+        self.build().map(|out| out.to_prepared())
+    }
+}
+impl_clause_methods!(Delete);
+
+#[test]
+fn test_select() {
+    let q = Select::from("my-table")
+        .columns(&["id", "name"])
+        .join("other", "my-table.id", "other.ref")
+        .and_where("name", "=", SQLType::VarChar("O'Brien".to_string(), 60))
+        .or_where("id", ">", SQLType::Int(5))
+        .order_by("id", Order::Desc)
+        .limit(10)
+        .offset(20);
+    assert_eq!(q.to_sql().unwrap(), "SELECT `id`, `name` FROM `my-table` INNER JOIN `other` ON `my-table`.`id` = `other`.`ref` \
+                            WHERE `name` = 'O''Brien' OR `id` > 5 ORDER BY `id` DESC LIMIT 10 OFFSET 20");
+    let (sql, params) = q.to_prepared().unwrap();
+    assert_eq!(sql, "SELECT `id`, `name` FROM `my-table` INNER JOIN `other` ON `my-table`.`id` = `other`.`ref` \
+                     WHERE `name` = ? OR `id` > ? ORDER BY `id` DESC LIMIT 10 OFFSET 20");
+    assert_eq!(params.len(), 2);
+
+    //Values that have no safe literal can't be rendered into the SQL text.
+    assert!(Select::from("t").and_where("x", "=", SQLType::Double(f64::NAN)).to_sql().is_err());
+    assert!(Select::from("t").and_where("x", "=", SQLType::Unsupported("1 OR 1=1".to_string(), "DATE".to_string(),
+                                                                       ::types::SQLFieldType::Date)).to_sql().is_err());
+
+    let bad = Select::from("t").and_where("x", "; DROP TABLE t; --", SQLType::Int(1));
+    assert_eq!(bad.to_sql(), Err("Invalid operator in where clause: ; DROP TABLE T; --".to_string()));
+    assert!(bad.to_prepared().is_err());
+}
+
+#[test]
+fn test_insert_update_delete() {
+    let q = Insert::into("t")
+        .columns(&["a", "b"])
+        .values(vec![SQLType::Int(1), SQLType::VarChar("x".to_string(), 10)])
+        .values(vec![SQLType::Int(2), SQLType::VarChar("y".to_string(), 10)])
+        .on_duplicate_key_update(&["b"]);
+    assert_eq!(q.to_sql().unwrap(), "INSERT INTO `t` (`a`, `b`) VALUES (1, 'x'), (2, 'y') ON DUPLICATE KEY UPDATE `b` = VALUES(`b`)");
+
+    let q = Update::table("a")
+        .join("b", "a.id", "b.id")
+        .set("a.x", SQLType::Int(3))
+        .where_in("b.k", vec![SQLType::Int(1), SQLType::Int(2)]);
+    assert_eq!(q.to_sql().unwrap(), "UPDATE `a` INNER JOIN `b` ON `a`.`id` = `b`.`id` SET `a`.`x` = 3 WHERE `b`.`k` IN (1, 2)");
+
+    assert!(Insert::into("t").columns(&["a"]).to_sql().is_err());
+    assert!(Insert::into("t").columns(&["a", "b"]).values(vec![SQLType::Int(1)]).to_sql().is_err());
+    assert!(Update::table("t").and_where("id", "=", SQLType::Int(1)).to_sql().is_err());
+
+    let q = Update::table("t").set("x", SQLType::Int(0))
+        .and_where("a", "=", SQLType::Int(1)).and_where("b", "=", SQLType::Int(2)).or_where("c", "=", SQLType::Int(3))
+        .and_where("d", "=", SQLType::Int(4));
+    assert_eq!(q.to_sql().unwrap(), "UPDATE `t` SET `x` = 0 WHERE ((`a` = 1 AND `b` = 2) OR `c` = 3) AND `d` = 4");
+    let q = Delete::from("t").and_where("a", "=", SQLType::Int(1))
+        .and_group(|g| g.and_where("b", "=", SQLType::Int(2)).or_where("c", "<", SQLType::Int(3)));
+    assert_eq!(q.to_sql().unwrap(), "DELETE FROM `t` WHERE `a` = 1 AND (`b` = 2 OR `c` < 3)");
+    assert!(Delete::from("t").or_group(|g| g.and_where("b", "~", SQLType::Int(2))).to_sql().is_err());
+
+    let q = Delete::from("a").target("a").join("b", "a.id", "b.id").where_null("b.gone");
+    assert_eq!(q.to_sql().unwrap(), "DELETE `a` FROM `a` INNER JOIN `b` ON `a`.`id` = `b`.`id` WHERE `b`.`gone` IS NULL");
+}
@@ -0,0 +1,277 @@
+//! Recovering a connection after the server has gone away.
+//!
+//! Nothing is retried unless you ask for it.  With a policy other than `never()`, a query that
+//! fails because the connection was lost still returns its error, but the connection is
+//! re-established so the next query can work.  Only statements run through
+//! `Connection::retry_idempotent` are sent again, and never inside a transaction, since the
+//! server rolled the transaction back when the connection went away.
+
+use ::connection::Connection;
+#[cfg(feature = "ffi")]
+use ::ffi::mysql::{MYSQL, MYSQL_OPT_RECONNECT, my_bool, mysql_options, mysql_ping};
+#[cfg(feature = "ffi")]
+use ::libc::c_void;
+use ::types::SQLType;
+use ::std::thread;
+use ::std::sync::atomic::Ordering;
+use ::std::time::Duration;
+
+/// How a lost connection is re-established.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReconnectMode {
+    /// Don't, the connection stays broken.
+    Never,
+    /// Let the client library reconnect on `mysql_ping`, with `MYSQL_OPT_RECONNECT` set for
+    /// just that call, then restore the session as `Full` does.
+    /// The same as `Full` for backends other than the client library.
+    Ping,
+    /// Connect again with the saved `ConnectOptions`, replacing the old connection.
+    Full,
+}
+
+/// Turns the client library's own reconnect on or off.
+#[cfg(feature = "ffi")]
+fn set_auto_reconnect(conn: *mut MYSQL, enable: bool) {
+    let enable = enable as my_bool;
+    unsafe { mysql_options(conn, MYSQL_OPT_RECONNECT, &enable as *const my_bool as *const c_void) };
+}
+
+/// What to do when the server goes away.  Set it with `ConnectOptions::reconnect()`.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub(crate) mode: ReconnectMode,
+    pub(crate) attempts: u32,
+    pub(crate) initial_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+}
+
+impl ReconnectPolicy {
+    fn with_mode(mode: ReconnectMode) -> Self {
+        ReconnectPolicy {
+            mode: mode,
+            attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+    /// Never reconnect.  The default.
+    pub fn never() -> Self {
+        ReconnectPolicy::with_mode(ReconnectMode::Never)
+    }
+    /// Reconnect through `mysql_ping` and the client library's own reconnect.
+    pub fn ping() -> Self {
+        ReconnectPolicy::with_mode(ReconnectMode::Ping)
+    }
+    /// Reconnect by opening a new connection with the same options.
+    pub fn full() -> Self {
+        ReconnectPolicy::with_mode(ReconnectMode::Full)
+    }
+    /// How many times to try reconnecting, and how many times `retry_idempotent` may send a
+    /// statement again.  Defaults to 3.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+    /// The wait before the second attempt, doubled after every attempt up to `max`.
+    /// Defaults to 100ms, up to 5s.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+    /// The wait before the given attempt, counting from 0.
+    fn delay(&self, attempt: u32) -> Duration {
+        if attempt == 0 {
+            return Duration::from_secs(0);
+        }
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        ::std::cmp::min(self.initial_backoff.checked_mul(factor).unwrap_or(self.max_backoff), self.max_backoff)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy::never()
+    }
+}
+
+/// Builds `SET SESSION name = value`, checking the name as it can't be quoted.
+pub(crate) fn set_session_sql(name: &str, value: &SQLType) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Invalid session variable name: {}", name));
+    }
+    Ok(format!("SET SESSION {} = {};", name, try!(value.to_sql_literal())))
+}
+
+impl Connection {
+    /// Returns true if the last call failed because the connection to the server was lost.
+    pub fn connection_lost(&self) -> bool {
+        self.backend.borrow().connection_lost()
+    }
+
+    /// How many times the connection has been re-established.
+    pub fn reconnect_count(&self) -> u64 {
+        self.reconnects.get()
+    }
+
+    /// Sets a session variable, and remembers it so it is set again after a reconnect.
+    pub fn set_session_var(&self, name: &str, value: SQLType) -> Result<(), String> {
+        try!(self.send_query(&try!(set_session_sql(name, &value))));
+        let mut vars = self.session_vars.borrow_mut();
+        vars.retain(|v| v.0 != name);
+        vars.push((name.to_string(), value));
+        Ok(())
+    }
+
+    /// Re-establishes the connection now, as the reconnect policy says, and restores the
+    /// database, character set and session variables.  Works even when the policy is
+    /// `never()`, in which case a full reconnect is done once.
+    pub fn reconnect(&self) -> Result<(), String> {
+        let mut policy = self.opts.reconnect.clone();
+        if policy.mode == ReconnectMode::Never {
+            policy = ReconnectPolicy::full().attempts(1);
+        }
+        self.reconnect_with(&policy)
+    }
+
+    fn reconnect_with(&self, policy: &ReconnectPolicy) -> Result<(), String> {
+        let charset = self.charset().name;
+        let mut last_error = "No reconnect attempts were allowed.".to_string();
+        for attempt in 0..policy.attempts {
+            thread::sleep(policy.delay(attempt));
+            match self.reconnect_once(policy.mode, &charset) {
+                Ok(())   => {
+                    self.in_transaction.set(false);
+                    self.reconnects.set(self.reconnects.get() + 1);
+                    self.cancel_id.store(self.thread_id(), Ordering::SeqCst);
+                    if let Some(ref metrics) = self.opts.metrics {
+                        metrics.record_reconnect();
+                    }
+                    return Ok(());
+                },
+                Err(e)   => last_error = e,
+            }
+        }
+        Err(format!("Failed to reconnect. Reason: {}", last_error))
+    }
+
+    fn reconnect_once(&self, mode: ReconnectMode, charset: &str) -> Result<(), String> {
+        if mode != ReconnectMode::Ping || !try!(self.ping_reconnect()) {
+            let result = self.backend_mut().connect(&self.opts);
+            try!(result);
+        }
+        self.restore_session(charset)
+    }
+
+    /// Lets the client library reconnect through `mysql_ping`.  Returns false, having done
+    /// nothing, if the connection doesn't go through the client library.
+    #[cfg(feature = "ffi")]
+    fn ping_reconnect(&self) -> Result<bool, String> {
+        let conn = self.handle();
+        if conn.is_null() {
+            return Ok(false);
+        }
+        //Left on, the client library would also reconnect behind our back in the middle of
+        //other calls, with nothing restored and the transaction silently gone.
+        set_auto_reconnect(conn, true);
+        let failed = unsafe { mysql_ping(conn) } != 0;
+        set_auto_reconnect(conn, false);
+        if failed {
+            return Err(self.last_error());
+        }
+        ::infile::refuse_local_infile(conn);
+        Ok(true)
+    }
+
+    /// Lets the client library reconnect through `mysql_ping`, which there isn't without the
+    /// `ffi` feature.
+    #[cfg(not(feature = "ffi"))]
+    fn ping_reconnect(&self) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    /// Brings a new session back to where the old one was.
+    fn restore_session(&self, charset: &str) -> Result<(), String> {
+        let db = self.current_db();
+        if !db.is_empty() {
+            let result = self.backend_mut().select_db(&db);
+            try!(result.map_err(|e| format!("Failed to restore database {}. Reason: {}", db, e)));
+        }
+        let result = self.backend_mut().set_charset(charset);
+        try!(result.map_err(|e| format!("Failed to restore character set {}. Reason: {}", charset, e)));
+        //A reconnect through mysql_ping didn't go through the backend, which sets the ones
+        //from the options, so they are set here either way, before the ones set since.
+        for (name, value) in self.opts.session_vars.iter().chain(self.session_vars.borrow().iter()) {
+            try!(self.exec_quiet(&try!(set_session_sql(name, value))));
+        }
+        Ok(())
+    }
+
+    /// Called with the error of a failed call.  If it failed because the connection was lost
+    /// and the policy allows it, reconnects, and returns the error with a note on what happened.
+    pub(crate) fn recover_after(&self, error: String) -> String {
+        if self.opts.reconnect.mode == ReconnectMode::Never || !self.connection_lost() {
+            return error;
+        }
+        let lost_transaction = self.in_transaction.get();
+        //The server ended the transaction along with the session.
+        self.in_transaction.set(false);
+        match self.reconnect_with(&self.opts.reconnect.clone()) {
+            Ok(()) if lost_transaction => format!("{} (reconnected, the open transaction was rolled back)", error),
+            Ok(())                     => format!("{} (reconnected)", error),
+            Err(e)                     => format!("{} ({})", error, e),
+        }
+    }
+
+    /// Runs a statement, or several, that is safe to run more than once, sending it again if
+    /// the connection was lost and re-established part way through.
+    /// Nothing is retried inside a transaction, or when the policy is `never()`.
+    pub fn retry_idempotent<T, F>(&self, mut statement: F) -> Result<T, String>
+        where F: FnMut(&Connection) -> Result<T, String> {
+        let mut retries = 0;
+        loop {
+            let reconnects = self.reconnects.get();
+            let in_transaction = self.in_transaction.get();
+            match statement(self) {
+                Err(e) => {
+                    let reconnected = self.reconnects.get() != reconnects;
+                    if !reconnected || in_transaction || retries >= self.opts.reconnect.attempts {
+                        return Err(e);
+                    }
+                    retries += 1;
+                },
+                ok => return ok,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_backoff() {
+    let policy = ReconnectPolicy::full().backoff(Duration::from_millis(100), Duration::from_millis(500));
+    assert_eq!(policy.delay(0), Duration::from_secs(0));
+    assert_eq!(policy.delay(1), Duration::from_millis(100));
+    assert_eq!(policy.delay(3), Duration::from_millis(400));
+    assert_eq!(policy.delay(4), Duration::from_millis(500));
+    assert_eq!(policy.delay(40), Duration::from_millis(500));
+    assert_eq!(set_session_sql("sql_mode", &SQLType::VarChar("ANSI".to_string(), 0)), Ok("SET SESSION sql_mode = 'ANSI';".to_string()));
+    assert!(set_session_sql("x; drop table t", &SQLType::Int(1)).is_err());
+    assert!(set_session_sql("wait_timeout", &SQLType::Unsupported("1; DROP TABLE t".to_string(), String::new(), ::types::SQLFieldType::Long)).is_err());
+}
+
+#[test]
+fn test_restore_session() {
+    use ::mock::{MockBackend, MockResponse};
+    use ::options::ConnectOptions;
+    let mock = MockBackend::new().on("SELECT 1", MockResponse::Lost);
+    let log = mock.log();
+    let opts = ConnectOptions::new("mock", "", "", "").session_var("wait_timeout", SQLType::Int(60))
+        .reconnect(ReconnectPolicy::full());
+    let conn = opts.connect_with(mock).unwrap();
+    conn.set_session_var("sql_mode", SQLType::VarChar("ANSI".to_string(), 0)).unwrap();
+    log.clear();
+    assert!(conn.raw_query_no_res("SELECT 1;").is_err());
+    let queries = log.queries();
+    let sets: Vec<&String> = queries.iter().skip_while(|q| !q.starts_with("SET NAMES")).skip(1).collect();
+    assert_eq!(sets, vec!["SET SESSION wait_timeout = 60;", "SET SESSION sql_mode = 'ANSI';"]);
+}
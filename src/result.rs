@@ -0,0 +1,474 @@
+//! Results of a query, along with the metadata of their columns.
+
+use ::connection::Connection;
+use ::cstr::Charset;
+#[cfg(feature = "ffi")]
+use ::cstr::{cstr_bytes, from_cstr};
+#[cfg(feature = "ffi")]
+use ::ffi::mysql::{MYSQL, MYSQL_RES, MYSQL_FIELD, MYSQL_ROW_OFFSET, mysql_num_rows, mysql_data_seek, mysql_row_seek, mysql_row_tell, mysql_affected_rows, mysql_insert_id, mysql_warning_count, mysql_info, mysql_errno, mysql_error, mysql_num_fields, mysql_fetch_fields, mysql_fetch_row, mysql_fetch_lengths, mysql_free_result};
+use ::observe::{QueryEvent, Trace};
+use ::types::SQLFieldType;
+#[cfg(feature = "ffi")]
+use ::std::{ptr, slice};
+use ::std::str::FromStr;
+
+/// The column can't hold NULL.
+const NOT_NULL_FLAG: u32 = 1;
+/// The column is part of the primary key.
+const PRI_KEY_FLAG: u32 = 2;
+/// The column is an unsigned number.
+const UNSIGNED_FLAG: u32 = 32;
+/// Character set number that marks binary data.
+const BINARY_CHARSET: u32 = 63;
+
+/// Metadata for one column of a result.
+#[derive(Clone, Debug)]
+pub struct Field {
+    /// The column name, or its alias.
+    pub name: String,
+    /// The table the column came from, or its alias.  Empty for computed columns.
+    pub table: String,
+    /// The type of the column.  Types this crate doesn't know about are reported as String.
+    pub field_type: SQLFieldType,
+    /// The display width of the column.
+    pub length: u64,
+    /// Number of decimals for numeric columns.
+    pub decimals: u32,
+    /// Raw column flags from the server.
+    pub flags: u32,
+    /// The character set number of the column.
+    pub charset: u32,
+}
+
+impl Field {
+    #[cfg(feature = "ffi")]
+    fn from_raw(field: &MYSQL_FIELD, charset: Charset) -> Self {
+        Field {
+            name: charset.decode(cstr_bytes(field.name)),
+            table: charset.decode(cstr_bytes(field.table)),
+            field_type: SQLFieldType::from_raw(field._type).unwrap_or(SQLFieldType::String),
+            length: field.length as u64,
+            decimals: field.decimals as u32,
+            flags: field.flags as u32,
+            charset: field.charsetnr as u32,
+        }
+    }
+    /// Returns true if the column can hold NULL.
+    pub fn is_nullable(&self) -> bool {
+        self.flags & NOT_NULL_FLAG == 0
+    }
+    /// Returns true if the column is part of the primary key.
+    pub fn is_primary_key(&self) -> bool {
+        self.flags & PRI_KEY_FLAG != 0
+    }
+    /// Returns true if the column is an unsigned number.
+    pub fn is_unsigned(&self) -> bool {
+        self.flags & UNSIGNED_FLAG != 0
+    }
+    /// Returns true if the column holds binary data rather than text.
+    pub fn is_binary(&self) -> bool {
+        self.charset == BINARY_CHARSET
+    }
+    /// Returns true if the values are bytes rather than text: a binary string or BLOB, a BIT
+    /// or a GEOMETRY.  Unlike `is_binary()` this is false for numbers and dates, which the
+    /// server also gives the binary character set.  Read these with `Rows::next_raw()`.
+    pub fn holds_bytes(&self) -> bool {
+        match self.field_type {
+            SQLFieldType::VarChar | SQLFieldType::VarString | SQLFieldType::String |
+            SQLFieldType::TinyBlob | SQLFieldType::MediumBlob | SQLFieldType::LongBlob |
+            SQLFieldType::Blob => self.is_binary(),
+            SQLFieldType::Bit | SQLFieldType::Geometry => true,
+            _ => false,
+        }
+    }
+}
+
+/// What the server reported about a statement that doesn't return rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecResult {
+    /// Rows changed, deleted or inserted.  For an UPDATE this only counts rows whose values
+    /// actually changed.
+    pub affected_rows: u64,
+    /// The AUTO_INCREMENT id generated by an INSERT, or 0 if there was none.
+    pub insert_id: u64,
+    /// How many warnings the statement raised.  Read them with `Connection::warnings()`.
+    pub warning_count: u32,
+    /// The server's summary for statements that have one, such as
+    /// "Records: 3  Duplicates: 0  Warnings: 0" after a multi-row INSERT.
+    pub info: Option<String>,
+}
+
+impl ExecResult {
+    /// Reads the results of the last statement from a handle.
+    #[cfg(feature = "ffi")]
+    pub(crate) fn from_handle(conn: *mut MYSQL) -> Self {
+        let info = unsafe { mysql_info(conn) };
+        ExecResult {
+            affected_rows: unsafe { mysql_affected_rows(conn) } as u64,
+            insert_id: unsafe { mysql_insert_id(conn) } as u64,
+            warning_count: unsafe { mysql_warning_count(conn) } as u32,
+            info: if info.is_null() { None } else { Some(from_cstr(info)) },
+        }
+    }
+}
+
+/// How serious a warning is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WarningLevel {
+    /// Something worth knowing, such as `DROP TABLE IF EXISTS` on a missing table.
+    Note,
+    /// Something went wrong but the statement carried on, such as truncated data.
+    Warning,
+    /// An error, listed after a failed statement.
+    Error,
+}
+
+/// One row of `SHOW WARNINGS`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    /// How serious it is.
+    pub level: WarningLevel,
+    /// The server error code.
+    pub code: u32,
+    /// The message, as the server wrote it.
+    pub message: String,
+}
+
+impl Warning {
+    /// Reads a `SHOW WARNINGS` row: level, code, message.
+    pub(crate) fn from_row(row: &[Option<String>]) -> Result<Self, String> {
+        let cell = |i: usize| row.get(i).and_then(|c| c.clone()).unwrap_or_default();
+        let level = match &*cell(0) {
+            "Note"    => WarningLevel::Note,
+            "Warning" => WarningLevel::Warning,
+            "Error"   => WarningLevel::Error,
+            other     => return Err(format!("Unknown warning level: {}", other)),
+        };
+        let code = try!(u32::from_str(&cell(1)).map_err(|_| format!("Invalid warning code: {}", cell(1))));
+        Ok(Warning { level: level, code: code, message: cell(2) })
+    }
+}
+
+/// Reads the column metadata of a result, with names in the given character set.
+#[cfg(feature = "ffi")]
+pub(crate) fn read_fields(res: *mut MYSQL_RES, charset: Charset) -> Vec<Field> {
+    if res.is_null() {
+        return Vec::new();
+    }
+    let count = unsafe { mysql_num_fields(res) } as usize;
+    let raw = unsafe { slice::from_raw_parts(mysql_fetch_fields(res), count) };
+    raw.iter().map(|f| Field::from_raw(f, charset)).collect()
+}
+
+/// Fetches the next row of a result, with NULL columns as None and text decoded from the given
+/// character set.  Returns None once there are no rows left.
+#[cfg(feature = "ffi")]
+pub(crate) fn fetch_row(res: *mut MYSQL_RES, width: usize, charset: Charset) -> Option<Vec<Option<String>>> {
+    fetch_raw_row(res, width).map(|row| decode_row(row, charset))
+}
+
+/// Fetches the next row of a result, with NULL columns as None and the bytes of the others as
+/// they are.  Returns None once there are no rows left.
+#[cfg(feature = "ffi")]
+pub(crate) fn fetch_raw_row(res: *mut MYSQL_RES, width: usize) -> Option<Vec<Option<Vec<u8>>>> {
+    let row = unsafe { mysql_fetch_row(res) };
+    if row.is_null() {
+        return None;
+    }
+    let lengths = unsafe { slice::from_raw_parts(mysql_fetch_lengths(res), width) };
+    let mut out = Vec::with_capacity(width);
+    for (i, &length) in lengths.iter().enumerate() {
+        let cell = unsafe { *row.add(i) };
+        if cell.is_null() {
+            out.push(None);
+        } else {
+            out.push(Some(unsafe { slice::from_raw_parts(cell as *const u8, length as usize) }.to_vec()));
+        }
+    }
+    Some(out)
+}
+
+/// Decodes every column of a raw row from the given character set.
+pub(crate) fn decode_row(row: Vec<Option<Vec<u8>>>, charset: Charset) -> Vec<Option<String>> {
+    row.into_iter().map(|cell| cell.map(|bytes| charset.decode(&bytes))).collect()
+}
+
+/// The rows of a query, read from the server one at a time as you iterate.
+///
+/// Running another statement on the connection before the rows are done throws the rest of
+/// them away, and reading on returns an error.
+pub struct Rows<'a> {
+    conn: &'a Connection,
+    /// The result being read, or null if the rows come from the connection's backend.
+    #[cfg(feature = "ffi")]
+    res: *mut MYSQL_RES,
+    fields: Vec<Field>,
+    charset: Charset,
+    done: bool,
+    /// `Connection::results` when the rows started.  If it has moved on, the backend has since
+    /// been used for something else, which threw the rest of the rows away.
+    results: u64,
+    /// Kept for the observers while the rows are read.
+    trace: Option<Trace>,
+}
+
+impl<'a> Rows<'a> {
+    /// The rows of a result from the client library.  A null result has no rows.
+    #[cfg(feature = "ffi")]
+    pub(crate) fn new(conn: &'a Connection, res: *mut MYSQL_RES) -> Self {
+        let charset = conn.text_charset();
+        Rows {
+            conn: conn,
+            res: res,
+            fields: read_fields(res, charset),
+            charset: charset,
+            done: res.is_null(),
+            results: conn.results.get(),
+            trace: None,
+        }
+    }
+    /// The rows of the statement the connection's backend has just run.
+    pub(crate) fn from_backend(conn: &'a Connection, fields: Vec<Field>, trace: Option<Trace>) -> Self {
+        Rows {
+            conn: conn,
+            #[cfg(feature = "ffi")]
+            res: ptr::null_mut(),
+            fields: fields,
+            charset: conn.text_charset(),
+            done: false,
+            results: conn.results.get(),
+            trace: trace,
+        }
+    }
+    /// No rows, for a statement that didn't return any.
+    pub(crate) fn empty(conn: &'a Connection) -> Self {
+        let mut rows = Rows::from_backend(conn, Vec::new(), None);
+        rows.done = true;
+        rows
+    }
+    /// The metadata of every column, in order.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+    /// The next row with each column's bytes as the server sent them, rather than decoded as text
+    /// as iterating does.  Use this for binary columns, which text would corrupt.
+    pub fn next_raw(&mut self) -> Option<Result<Vec<Option<Vec<u8>>>, String>> {
+        if self.done {
+            return None;
+        }
+        #[cfg(feature = "ffi")]
+        {
+            if !self.res.is_null() {
+                return self.next_from_result();
+            }
+        }
+        if self.conn.results.get() != self.results {
+            self.done = true;
+            return Some(Err("The rest of the rows were thrown away by a later statement on the same connection.".to_string()));
+        }
+        let row = self.conn.backend.borrow_mut().next_raw_row();
+        self.done = !matches!(row, Ok(Some(_)));
+        if let Some(ref mut trace) = self.trace {
+            match row {
+                Ok(Some(ref cells)) => {
+                    trace.rows += 1;
+                    trace.bytes += cells.iter().map(|c| c.as_ref().map_or(0, |c| c.len() as u64)).sum::<u64>();
+                },
+                Err(ref e) => {
+                    let backend = self.conn.backend.borrow();
+                    trace.error = Some((e.clone(), backend.last_errno(), backend.sqlstate()));
+                },
+                Ok(None) => {},
+            }
+        }
+        row.transpose()
+    }
+    /// The next row of the client library's result.
+    #[cfg(feature = "ffi")]
+    fn next_from_result(&mut self) -> Option<Result<Vec<Option<Vec<u8>>>, String>> {
+        match fetch_raw_row(self.res, self.fields.len()) {
+            Some(row) => Some(Ok(row)),
+            None => {
+                self.done = true;
+                //A NULL row is either the end, or a dropped connection part way through.
+                let handle = self.conn.handle();
+                if unsafe { mysql_errno(handle) } != 0 {
+                    Some(Err(from_cstr(unsafe { mysql_error(handle) })))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<Vec<Option<String>>, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let charset = self.charset;
+        self.next_raw().map(|row| row.map(|row| decode_row(row, charset)))
+    }
+}
+
+impl<'a> Drop for Rows<'a> {
+    fn drop(&mut self) {
+        #[cfg(feature = "ffi")]
+        {
+            if !self.res.is_null() {
+                //Also reads and throws away any rows that weren't iterated over.
+                unsafe { mysql_free_result(self.res) };
+                self.res = ptr::null_mut();
+                self.done = true;
+            }
+        }
+        //Once the backend has moved on, what is pending belongs to someone else.
+        if !self.done && self.conn.results.get() == self.results {
+            let _ = self.conn.backend.borrow_mut().finish();
+        }
+        if let Some(trace) = self.trace.take() {
+            let warning_count = self.conn.backend.borrow().exec_result().warning_count;
+            let event = match trace.error {
+                Some((ref error, errno, ref sqlstate)) => QueryEvent { rows: Some(trace.rows), bytes_received: trace.bytes,
+                                                                       ..QueryEvent::failed(&trace.sql, trace.started, error, errno, sqlstate) },
+                None => QueryEvent::read(&trace.sql, trace.started, trace.rows, trace.bytes, warning_count),
+            };
+            self.conn.notify(&event);
+        }
+    }
+}
+
+/// A place in a `StoredResult`, from `StoredResult::tell()`.  Only means something to the
+/// result it came from.
+#[cfg(feature = "ffi")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RowOffset {
+    res: *mut MYSQL_RES,
+    offset: MYSQL_ROW_OFFSET,
+}
+
+/// The rows of a query, all read into memory by the client library before this is returned.
+///
+/// Unlike `Rows`, the connection is free for other queries while this is alive, and the rows
+/// can be read in any order with `seek()`, `tell()`/`seek_to()` and `rewind()`.
+#[cfg(feature = "ffi")]
+pub struct StoredResult {
+    res: *mut MYSQL_RES,
+    fields: Vec<Field>,
+    charset: Charset,
+}
+
+#[cfg(feature = "ffi")]
+impl StoredResult {
+    pub(crate) fn new(res: *mut MYSQL_RES, charset: Charset) -> Self {
+        StoredResult {
+            res: res,
+            fields: read_fields(res, charset),
+            charset: charset,
+        }
+    }
+    /// The metadata of every column, in order.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+    /// How many rows there are in all, whatever has been read so far.
+    pub fn num_rows(&self) -> u64 {
+        if self.res.is_null() {
+            return 0;
+        }
+        (unsafe { mysql_num_rows(self.res) }) as u64
+    }
+    /// Moves to the given row, counting from 0, so the next row read is that one.
+    /// Past the end, there is nothing left to read.
+    pub fn seek(&mut self, row: u64) {
+        if !self.res.is_null() {
+            unsafe { mysql_data_seek(self.res, row) };
+        }
+    }
+    /// Moves back to the first row.
+    pub fn rewind(&mut self) {
+        self.seek(0);
+    }
+    /// The current place, to come back to later with `seek_to()`.
+    pub fn tell(&self) -> RowOffset {
+        let offset = if self.res.is_null() { ptr::null_mut() } else { unsafe { mysql_row_tell(self.res) } };
+        RowOffset { res: self.res, offset: offset }
+    }
+    /// Moves to a place returned by `tell()` on this result.
+    pub fn seek_to(&mut self, offset: RowOffset) -> Result<(), String> {
+        if offset.res != self.res {
+            return Err("The row offset belongs to a different result.".to_string());
+        }
+        if !self.res.is_null() {
+            unsafe { mysql_row_seek(self.res, offset.offset) };
+        }
+        Ok(())
+    }
+    /// Reads the given row, counting from 0.  Reading carries on from the row after it.
+    pub fn row(&mut self, row: u64) -> Option<Vec<Option<String>>> {
+        self.seek(row);
+        self.next()
+    }
+}
+
+#[cfg(feature = "ffi")]
+impl Iterator for StoredResult {
+    type Item = Vec<Option<String>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.res.is_null() {
+            return None;
+        }
+        fetch_row(self.res, self.fields.len(), self.charset)
+    }
+}
+
+#[cfg(feature = "ffi")]
+impl Drop for StoredResult {
+    fn drop(&mut self) {
+        if !self.res.is_null() {
+            unsafe { mysql_free_result(self.res) };
+            self.res = ptr::null_mut();
+        }
+    }
+}
+
+#[test]
+fn test_warning_from_row() {
+    let row = vec![Some("Warning".to_string()), Some("1265".to_string()), Some("Data truncated for column 'name' at row 1".to_string())];
+    let warning = Warning::from_row(&row).unwrap();
+    assert_eq!(warning.level, WarningLevel::Warning);
+    assert_eq!(warning.code, 1265);
+    assert!(Warning::from_row(&[Some("Loud".to_string()), Some("1".to_string()), None]).is_err());
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_empty_stored_result() {
+    //A statement without a result set leaves nothing to store.
+    let mut empty = StoredResult::new(ptr::null_mut(), Charset::Utf8);
+    assert_eq!(empty.num_rows(), 0);
+    assert!(empty.row(3).is_none());
+    let offset = empty.tell();
+    assert!(empty.seek_to(offset).is_ok());
+    let other = RowOffset { res: ptr::NonNull::dangling().as_ptr(), offset: ptr::null_mut() };
+    assert!(empty.seek_to(other).is_err());
+}
+
+#[test]
+fn test_stale_rows() {
+    use ::mock::{MockBackend, MockResponse};
+    use ::options::ConnectOptions;
+    let mock = MockBackend::new()
+        .on("FROM a", MockResponse::rows(&["x"], vec![vec![Some("a1")], vec![Some("a2")]]))
+        .on("FROM b", MockResponse::rows(&["x"], vec![vec![Some("b1")], vec![Some("b2")]]));
+    let conn = ConnectOptions::new("mock", "", "", "").connect_with(mock).unwrap();
+    let mut first = conn.query_iter("SELECT x FROM a;").unwrap();
+    assert_eq!(first.next().unwrap().unwrap(), vec![Some("a1".to_string())]);
+    let mut second = conn.query_iter("SELECT x FROM b;").unwrap();
+    //The first result was thrown away, so its rows must not come from the second.
+    assert!(first.next().unwrap().is_err());
+    assert!(first.next().is_none());
+    drop(first);
+    assert_eq!(second.next().unwrap().unwrap(), vec![Some("b1".to_string())]);
+    assert_eq!(second.next().unwrap().unwrap(), vec![Some("b2".to_string())]);
+    assert!(second.next().is_none());
+}
@@ -50,7 +50,7 @@ pub struct TestStruct {
 impl SerializeSQL for TestStruct {
     fn to_sql(&self) -> Vec<SQLType> {
         vec![
-            SQLType::VarChar("'".to_string() + &self.name + "'", 60),
+            SQLType::VarChar(self.name.clone(), 60),
             SQLType::Int(self.id),
             SQLType::Tiny(self.flag),
         ]
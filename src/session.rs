@@ -0,0 +1,104 @@
+//! Re-using a connection as another user, or with a clean session.
+//!
+//! Both go through `mysql_change_user`, which logs in again over the open connection, so there
+//! is no new TCP or TLS handshake.  The server starts a new session either way: temporary
+//! tables, user variables and prepared statements are dropped, an open transaction is rolled
+//! back, and session variables go back to their defaults.  Those set through
+//! `ConnectOptions::session_var` are set again, those set with `set_session_var` are not.
+
+use ::connection::{Connection, check_db_name};
+use ::cstr::to_cstr;
+use ::ffi::mysql::mysql_change_user;
+use ::reconnect::set_session_sql;
+use ::std::ptr;
+
+impl Connection {
+    /// Logs in as another user, optionally switching database.  Pass an empty `db` for none.
+    /// Needs the client library backend.
+    ///
+    /// If the new user can't log in, the connection logs back in as the previous user, with a
+    /// new session, and the error says so.  If that fails too, the connection is opened again
+    /// from scratch with the previous settings, and if even that fails, the error says the
+    /// connection can't be used until `reconnect()` succeeds.
+    pub fn change_user(&mut self, user: &str, password: &str, db: &str) -> Result<(), String> {
+        try!(self.client_handle("Changing user"));
+        if !db.is_empty() {
+            try!(check_db_name(db));
+        }
+        let error = match self.login(user, password, db) {
+            Ok(()) => {
+                self.opts.user = user.to_string();
+                self.opts.password = password.to_string();
+                self.opts.database = db.to_string();
+                return self.start_session(db);
+            },
+            Err(e) => e,
+        };
+
+        let previous = (self.opts.user.clone(), self.opts.password.clone(), self.current_db());
+        if self.login(&previous.0, &previous.1, &previous.2).is_ok() && self.start_session(&previous.2).is_ok() {
+            return Err(format!("{} (still logged in as {}, with a new session)", error, previous.0));
+        }
+        match self.reconnect() {
+            Ok(())  => Err(format!("{} (reconnected as {})", error, previous.0)),
+            Err(e)  => Err(format!("{} ({}, the connection can't be used until reconnect() succeeds)", error, e)),
+        }
+    }
+
+    /// Throws away everything in the session, by logging in again as the same user on the
+    /// same database.
+    pub fn reset_session(&mut self) -> Result<(), String> {
+        let (user, password, db) = (self.opts.user.clone(), self.opts.password.clone(), self.current_db());
+        self.change_user(&user, &password, &db)
+    }
+
+    fn login(&self, user: &str, password: &str, db: &str) -> Result<(), String> {
+        let user_c = try!(to_cstr(user));
+        let password_c = try!(to_cstr(password));
+        let db_c = if db.is_empty() { None } else { Some(try!(to_cstr(db))) };
+        let conn = try!(self.client_handle("Changing user"));
+        let failed = unsafe { mysql_change_user(conn, user_c.as_ptr(), password_c.as_ptr(),
+                                                db_c.as_ref().map_or(ptr::null(), |d| d.as_ptr())) } != 0;
+        if failed {
+            return Err(format!("Failed to log in as {}. Reason: {}", user, self.last_error()));
+        }
+        Ok(())
+    }
+
+    /// Brings the bookkeeping in line with a session the server has just started.
+    fn start_session(&self, db: &str) -> Result<(), String> {
+        *self.db.borrow_mut() = db.to_string();
+        self.in_transaction.set(false);
+        self.session_vars.borrow_mut().clear();
+        for (name, value) in &self.opts.session_vars {
+            try!(self.exec_quiet(&try!(set_session_sql(name, value))));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "test-server")]
+#[test]
+fn test_change_user() {
+    let server = ::testing::TestServer::start().unwrap();
+    let mut conn = server.connect().unwrap();
+    conn.raw_query_no_res("CREATE USER 'ann'@'localhost' IDENTIFIED BY 'secret';").unwrap();
+    conn.raw_query_no_res("GRANT ALL ON test.* TO 'ann'@'localhost';").unwrap();
+    let current_user = |conn: &Connection| conn.raw_query("SELECT CURRENT_USER();", 1).unwrap()[0][0].clone();
+
+    conn.change_user("ann", "secret", "test").unwrap();
+    assert_eq!(current_user(&conn), "ann@localhost");
+
+    //A bad password falls back to the previous user, in a new session.
+    conn.raw_query_no_res("SET @kept = 1;").unwrap();
+    let error = conn.change_user("root", "wrong", "").unwrap_err();
+    assert!(error.contains("still logged in as ann"), "{}", error);
+    assert_eq!(current_user(&conn), "ann@localhost");
+    assert_eq!(conn.current_db(), "test");
+    assert_eq!(conn.raw_query("SELECT @kept IS NULL;", 1).unwrap(), vec![vec!["1".to_string()]]);
+
+    conn.raw_query_no_res("SET @kept = 1;").unwrap();
+    conn.reset_session().unwrap();
+    assert_eq!(conn.raw_query("SELECT @kept IS NULL;", 1).unwrap(), vec![vec!["1".to_string()]]);
+    assert_eq!(current_user(&conn), "ann@localhost");
+}
@@ -0,0 +1,493 @@
+//! Prepared statements, optionally read through a server-side cursor.
+//!
+//! Without a cursor, the rows of an execution are streamed to the client as they are read,
+//! and nothing else can be sent on the connection until they have been.  With a read-only
+//! cursor the server holds on to the result and hands it out `prefetch_rows` at a time, so
+//! other queries can run on the same connection between fetches.
+//!
+//! Parameters are bound from `SQLType`s, which fits the `to_prepared()` output of the query
+//! builders.  Every column is fetched as text, in the same form as `query_iter` returns it.
+//! Statements go through the connection's `Backend`; `ClientStatement` is the client library's.
+
+use ::connection::Connection;
+#[cfg(feature = "ffi")]
+use ::cstr::{Charset, from_cstr};
+#[cfg(feature = "ffi")]
+use ::ffi::mysql::{MYSQL, MYSQL_STMT, MYSQL_BIND, my_bool, mysql_error, mysql_stmt_init, mysql_stmt_prepare, mysql_stmt_close, mysql_stmt_error,
+                   mysql_stmt_errno, mysql_stmt_sqlstate, mysql_stmt_param_count, mysql_stmt_attr_set, mysql_stmt_bind_param,
+                   mysql_stmt_bind_result, mysql_stmt_execute, mysql_stmt_fetch, mysql_stmt_fetch_column, mysql_stmt_free_result,
+                   mysql_stmt_result_metadata, mysql_stmt_affected_rows, mysql_stmt_insert_id, mysql_free_result, mysql_warning_count,
+                   mysql_info, STMT_ATTR_CURSOR_TYPE, STMT_ATTR_PREFETCH_ROWS, CURSOR_TYPE_NO_CURSOR, CURSOR_TYPE_READ_ONLY,
+                   MYSQL_TYPE_TINY, MYSQL_TYPE_SHORT, MYSQL_TYPE_LONG, MYSQL_TYPE_LONGLONG, MYSQL_TYPE_FLOAT, MYSQL_TYPE_DOUBLE,
+                   MYSQL_TYPE_NULL, MYSQL_TYPE_STRING};
+#[cfg(feature = "ffi")]
+use ::libc::{c_char, c_ulong, c_void};
+use ::result::{Field, ExecResult};
+#[cfg(feature = "ffi")]
+use ::result::read_fields;
+use ::types::SQLType;
+#[cfg(feature = "ffi")]
+use ::types::SQLFieldType;
+use ::observe::QueryEvent;
+#[cfg(feature = "ffi")]
+use ::std::ptr;
+use ::std::time::Instant;
+
+/// mysql_stmt_fetch: there are no rows left.
+#[cfg(feature = "ffi")]
+const MYSQL_NO_DATA: i32 = 100;
+/// mysql_stmt_fetch: at least one column didn't fit its buffer.
+#[cfg(feature = "ffi")]
+const MYSQL_DATA_TRUNCATED: i32 = 101;
+/// How many bytes each column gets before it has to be fetched again at full size.
+#[cfg(feature = "ffi")]
+const COLUMN_BUFFER: usize = 256;
+
+/// A statement prepared on the server, which can be executed any number of times.
+pub struct Statement<'a> {
+    conn: &'a Connection,
+    /// What the backend calls it.
+    id: u32,
+    param_count: usize,
+    fields: Vec<Field>,
+    /// How many rows the cursor fetches at a time, or 0 for no cursor.
+    prefetch_rows: u64,
+    /// The statement as prepared, for the observers.
+    sql: String,
+}
+
+impl Connection {
+    /// Prepares a statement, with `?` where the parameters go.
+    pub fn prepare<'a>(&'a self, query: &str) -> Result<Statement<'a>, String> {
+        let prepared = self.backend_mut().prepare(query);
+        let prepared = try!(prepared.map_err(|e| format!("Preparing ({}) failed. Reason: {}", query, e)));
+        Ok(Statement {
+            conn: self,
+            id: prepared.id,
+            param_count: prepared.param_count,
+            fields: prepared.fields,
+            prefetch_rows: 0,
+            sql: query.to_string(),
+        })
+    }
+}
+
+impl<'a> Statement<'a> {
+    /// How many `?` placeholders the statement has.
+    pub fn param_count(&self) -> usize {
+        self.param_count
+    }
+
+    /// The metadata of every column the statement returns.  Empty if it doesn't return rows.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Read the rows of every later execution through a read-only server cursor, fetching
+    /// `prefetch_rows` of them at a time.  Pass 0 to go back to streaming.
+    pub fn cursor(&mut self, prefetch_rows: u64) -> Result<(), String> {
+        self.prefetch_rows = prefetch_rows;
+        Ok(())
+    }
+
+    /// Executes with the parameters, telling the observers if it fails.  Returns what the
+    /// server reported and when it started.
+    fn run(&mut self, params: &[SQLType]) -> Result<(ExecResult, Instant), String> {
+        let started = Instant::now();
+        let result = if params.len() != self.param_count {
+            Err(format!("The statement takes {} parameters, but {} were given.", self.param_count, params.len()))
+        } else {
+            let result = self.conn.backend_mut().execute(self.id, params, self.prefetch_rows);
+            result.map_err(|e| format!("Executing the statement failed. Reason: {}", e))
+        };
+        match result {
+            Ok(result) => Ok((result, started)),
+            Err(e)     => {
+                self.conn.notify_failed(&self.sql, started, &e);
+                Err(e)
+            },
+        }
+    }
+
+    /// Executes a statement that doesn't return rows, or throws away the rows if it does.
+    pub fn execute(&mut self, params: &[SQLType]) -> Result<ExecResult, String> {
+        let (result, started) = try!(self.run(params));
+        let _ = self.conn.backend_mut().free_statement_result(self.id);
+        self.conn.notify(&QueryEvent::done(&self.sql, started, result.affected_rows, result.warning_count));
+        Ok(result)
+    }
+
+    /// Executes the statement and returns its rows to be read one at a time, NULL as None.
+    /// Observers hear about it once it has executed, without a row count.
+    pub fn query<'s>(&'s mut self, params: &[SQLType]) -> Result<StatementRows<'s, 'a>, String> {
+        let (_, started) = try!(self.run(params));
+        self.conn.notify(&QueryEvent::done(&self.sql, started, 0, 0));
+        Ok(StatementRows { results: self.conn.results.get(), statement: self, done: false })
+    }
+}
+
+impl<'a> Drop for Statement<'a> {
+    fn drop(&mut self) {
+        self.conn.backend_mut().close_statement(self.id);
+    }
+}
+
+/// The rows of one execution of a statement.  Dropping it before the end throws away the
+/// rest, and closes the cursor if there is one.
+///
+/// Without a cursor the rows are streamed, so, as with `Rows`, a later statement on the same
+/// connection throws away the ones that weren't read yet, and reading on gives an error.
+pub struct StatementRows<'s, 'a: 's> {
+    statement: &'s mut Statement<'a>,
+    /// The connection's count of results when the statement ran.
+    results: u64,
+    done: bool,
+}
+
+impl<'s, 'a> StatementRows<'s, 'a> {
+    /// The metadata of every column, in order.
+    pub fn fields(&self) -> &[Field] {
+        &self.statement.fields
+    }
+
+    fn cursor(&self) -> bool {
+        self.statement.prefetch_rows > 0
+    }
+
+    /// Returns true if the rows were streamed and a later statement threw them away.
+    fn thrown_away(&self) -> bool {
+        !self.cursor() && self.statement.conn.results.get() != self.results
+    }
+}
+
+impl<'s, 'a> Iterator for StatementRows<'s, 'a> {
+    type Item = Result<Vec<Option<String>>, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.statement.fields.is_empty() {
+            return None;
+        }
+        if self.thrown_away() {
+            self.done = true;
+            return Some(Err("The rest of the rows were thrown away by a later statement on the same connection.".to_string()));
+        }
+        let conn = self.statement.conn;
+        let row = if self.cursor() {
+            //Fetching from the cursor is a command of its own, which ends any other result.
+            conn.backend_mut().next_statement_row(self.statement.id)
+        } else {
+            conn.backend.borrow_mut().next_statement_row(self.statement.id)
+        };
+        match row {
+            Ok(Some(row)) => Some(Ok(row)),
+            Ok(None)      => { self.done = true; None },
+            Err(e)        => { self.done = true; Some(Err(format!("Fetching a row failed. Reason: {}", e))) },
+        }
+    }
+}
+
+impl<'s, 'a> Drop for StatementRows<'s, 'a> {
+    fn drop(&mut self) {
+        let conn = self.statement.conn;
+        if self.cursor() && !self.done {
+            let _ = conn.backend_mut().free_statement_result(self.statement.id);
+        } else if !self.thrown_away() {
+            let _ = conn.backend.borrow_mut().free_statement_result(self.statement.id);
+        }
+    }
+}
+
+/// A parameter value, kept where the bind can point at it until the statement has run.
+#[cfg(feature = "ffi")]
+struct Param {
+    /// Numbers, with their bytes at the start in native order.
+    number: u64,
+    bytes: Vec<u8>,
+    length: c_ulong,
+    is_null: my_bool,
+}
+
+/// Where the value of one column is fetched to.
+#[cfg(feature = "ffi")]
+struct Column {
+    data: Vec<u8>,
+    length: c_ulong,
+    is_null: my_bool,
+    error: my_bool,
+}
+
+/// A statement prepared through the client library, which `FfiBackend` keeps under its id.
+/// Closed when dropped, which has to happen before the connection is closed.
+#[cfg(feature = "ffi")]
+pub(crate) struct ClientStatement {
+    stmt: *mut MYSQL_STMT,
+    charset: Charset,
+    /// Never resized once bound, the client library holds pointers into it.
+    columns: Vec<Column>,
+    /// Whether the last execution went through a cursor.
+    cursor: bool,
+    /// Set once the rows of the last execution have all been read or thrown away.
+    done: bool,
+}
+
+#[cfg(feature = "ffi")]
+impl ClientStatement {
+    /// Prepares a statement on the connection.  Returns it with its number of parameters and
+    /// its columns.
+    pub(crate) fn prepare(conn: *mut MYSQL, sql: &str, charset: Charset) -> Result<(ClientStatement, usize, Vec<Field>), String> {
+        let bytes = try!(charset.encode(sql));
+        let stmt = unsafe { mysql_stmt_init(conn) };
+        if stmt.is_null() {
+            return Err(format!("Failed to allocate a statement. Reason: {}", from_cstr(unsafe { mysql_error(conn) })));
+        }
+        let mut statement = ClientStatement { stmt: stmt, charset: charset, columns: Vec::new(), cursor: false, done: true };
+        if unsafe { mysql_stmt_prepare(stmt, bytes.as_ptr() as *const c_char, bytes.len() as c_ulong) } != 0 {
+            return Err(statement.error());
+        }
+        let meta = unsafe { mysql_stmt_result_metadata(stmt) };
+        let fields = read_fields(meta, charset);
+        if !meta.is_null() {
+            unsafe { mysql_free_result(meta) };
+        }
+        statement.columns = (0..fields.len())
+            .map(|_| Column { data: vec![0; COLUMN_BUFFER], length: 0, is_null: 0, error: 0 })
+            .collect();
+        let param_count = unsafe { mysql_stmt_param_count(stmt) } as usize;
+        Ok((statement, param_count, fields))
+    }
+
+    fn error(&self) -> String {
+        from_cstr(unsafe { mysql_stmt_error(self.stmt) })
+    }
+
+    /// The code of the statement's last error, or 0.
+    pub(crate) fn errno(&self) -> u32 {
+        unsafe { mysql_stmt_errno(self.stmt) }
+    }
+
+    /// The SQLSTATE of the statement's last error.
+    pub(crate) fn sqlstate(&self) -> String {
+        from_cstr(unsafe { mysql_stmt_sqlstate(self.stmt) })
+    }
+
+    /// Binds the parameters and executes, then binds the columns if there are any to read.  The
+    /// values only need to live until this returns, the client library has sent them by then.
+    pub(crate) fn execute(&mut self, conn: *mut MYSQL, params: &[SQLType], prefetch_rows: u64) -> Result<ExecResult, String> {
+        self.free_result();
+        let cursor_type = (if prefetch_rows == 0 { CURSOR_TYPE_NO_CURSOR } else { CURSOR_TYPE_READ_ONLY }) as c_ulong;
+        if unsafe { mysql_stmt_attr_set(self.stmt, STMT_ATTR_CURSOR_TYPE, &cursor_type as *const c_ulong as *const c_void) } != 0 {
+            return Err(format!("Setting the cursor type failed. Reason: {}", self.error()));
+        }
+        if prefetch_rows > 0 {
+            let prefetch = prefetch_rows as c_ulong;
+            if unsafe { mysql_stmt_attr_set(self.stmt, STMT_ATTR_PREFETCH_ROWS, &prefetch as *const c_ulong as *const c_void) } != 0 {
+                return Err(format!("Setting the prefetch size failed. Reason: {}", self.error()));
+            }
+        }
+        self.cursor = prefetch_rows > 0;
+        let mut values = Vec::with_capacity(params.len());
+        for p in params {
+            values.push(try!(self.to_param(p)));
+        }
+        let mut binds: Vec<MYSQL_BIND> = values.iter_mut().map(|&mut (ref mut value, buffer_type)| MYSQL_BIND {
+            buffer_type: buffer_type,
+            buffer: if value.bytes.is_empty() {
+                &mut value.number as *mut u64 as *mut c_void
+            } else {
+                value.bytes.as_mut_ptr() as *mut c_void
+            },
+            buffer_length: value.length,
+            length: &mut value.length,
+            is_null: &mut value.is_null,
+            ..MYSQL_BIND::default()
+        }).collect();
+        if !binds.is_empty() && unsafe { mysql_stmt_bind_param(self.stmt, binds.as_mut_ptr()) } != 0 {
+            return Err(format!("Binding parameters failed. Reason: {}", self.error()));
+        }
+        if unsafe { mysql_stmt_execute(self.stmt) } != 0 {
+            return Err(self.error());
+        }
+        let info = unsafe { mysql_info(conn) };
+        let result = ExecResult {
+            affected_rows: unsafe { mysql_stmt_affected_rows(self.stmt) } as u64,
+            insert_id: unsafe { mysql_stmt_insert_id(self.stmt) } as u64,
+            warning_count: unsafe { mysql_warning_count(conn) } as u32,
+            info: if info.is_null() { None } else { Some(from_cstr(info)) },
+        };
+        if !self.columns.is_empty() {
+            let mut binds: Vec<MYSQL_BIND> = self.columns.iter_mut().map(string_bind).collect();
+            if unsafe { mysql_stmt_bind_result(self.stmt, binds.as_mut_ptr()) } != 0 {
+                let error = format!("Binding results failed. Reason: {}", self.error());
+                unsafe { mysql_stmt_free_result(self.stmt) };
+                return Err(error);
+            }
+            self.done = false;
+        }
+        Ok(result)
+    }
+
+    /// Turns a value into a parameter and the type to send it as.
+    fn to_param(&self, value: &SQLType) -> Result<(Box<Param>, u32), String> {
+        let mut param = Box::new(Param { number: 0, bytes: Vec::new(), length: 0, is_null: 0 });
+        fn store(param: &mut Param, bytes: &[u8]) {
+            unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), &mut param.number as *mut u64 as *mut u8, bytes.len()) };
+            param.length = bytes.len() as c_ulong;
+        }
+        let buffer_type = match *value {
+            SQLType::Tiny(v)   => { store(&mut param, &v.to_ne_bytes()); MYSQL_TYPE_TINY },
+            SQLType::Short(v)  => { store(&mut param, &v.to_ne_bytes()); MYSQL_TYPE_SHORT },
+            SQLType::Int(v)    => { store(&mut param, &v.to_ne_bytes()); MYSQL_TYPE_LONG },
+            SQLType::Long(v)   => { store(&mut param, &v.to_ne_bytes()); MYSQL_TYPE_LONGLONG },
+            SQLType::Float(v)  => { store(&mut param, &v.to_ne_bytes()); MYSQL_TYPE_FLOAT },
+            SQLType::Double(v) => { store(&mut param, &v.to_ne_bytes()); MYSQL_TYPE_DOUBLE },
+            SQLType::VarChar(ref s, _) => {
+                param.bytes = try!(self.charset.encode(s));
+                param.length = param.bytes.len() as c_ulong;
+                if param.bytes.is_empty() {
+                    //An empty buffer would be taken for a number, point at something valid.
+                    param.bytes.push(0);
+                }
+                MYSQL_TYPE_STRING
+            },
+            SQLType::Unsupported(_, _, SQLFieldType::NULL) => { param.is_null = 1; MYSQL_TYPE_NULL },
+            SQLType::Unsupported(ref literal, ref name, _) =>
+                return Err(format!("Can't bind {} ({}) to a parameter, only numbers, strings and NULL.", literal, name)),
+        };
+        Ok((param, buffer_type))
+    }
+
+    /// The next row of the last execution, or None at the end.
+    pub(crate) fn next_row(&mut self) -> Result<Option<Vec<Option<String>>>, String> {
+        if self.done {
+            return Ok(None);
+        }
+        match unsafe { mysql_stmt_fetch(self.stmt) } {
+            0 | MYSQL_DATA_TRUNCATED => self.read_row().map(Some),
+            MYSQL_NO_DATA => { self.done = true; Ok(None) },
+            _ => { self.done = true; Err(self.error()) },
+        }
+    }
+
+    fn read_row(&mut self) -> Result<Vec<Option<String>>, String> {
+        let mut row = Vec::with_capacity(self.columns.len());
+        for (i, column) in self.columns.iter().enumerate() {
+            if column.is_null != 0 {
+                row.push(None);
+                continue;
+            }
+            let length = column.length as usize;
+            if length <= column.data.len() {
+                row.push(Some(self.charset.decode(&column.data[..length])));
+                continue;
+            }
+            //Too long for the buffer, fetch the whole of it on its own.
+            let mut full = Column { data: vec![0; length], length: 0, is_null: 0, error: 0 };
+            let mut bind = string_bind(&mut full);
+            if unsafe { mysql_stmt_fetch_column(self.stmt, &mut bind, i as u32, 0) } != 0 {
+                return Err(format!("Fetching a long column failed. Reason: {}", self.error()));
+            }
+            let fetched = ::std::cmp::min(full.length as usize, length);
+            row.push(Some(self.charset.decode(&full.data[..fetched])));
+        }
+        Ok(row)
+    }
+
+    /// Throws away the rest of the rows of the last execution, and closes its cursor.
+    pub(crate) fn free_result(&mut self) {
+        unsafe { mysql_stmt_free_result(self.stmt) };
+        self.done = true;
+    }
+
+    /// Throws away the rest of the rows of the last execution if they are being streamed,
+    /// before another command is sent.  A cursor is left open.
+    pub(crate) fn free_streamed_result(&mut self) {
+        if !self.done && !self.cursor {
+            self.free_result();
+        }
+    }
+}
+
+/// A bind that fetches into a column's buffer as text.
+#[cfg(feature = "ffi")]
+fn string_bind(column: &mut Column) -> MYSQL_BIND {
+    MYSQL_BIND {
+        buffer_type: MYSQL_TYPE_STRING,
+        buffer: column.data.as_mut_ptr() as *mut c_void,
+        buffer_length: column.data.len() as c_ulong,
+        length: &mut column.length,
+        is_null: &mut column.is_null,
+        error: &mut column.error,
+        ..MYSQL_BIND::default()
+    }
+}
+
+#[cfg(feature = "ffi")]
+impl Drop for ClientStatement {
+    fn drop(&mut self) {
+        unsafe { mysql_stmt_close(self.stmt) };
+    }
+}
+
+#[cfg(feature = "test-server")]
+#[test]
+fn test_statement() {
+    let server = ::testing::TestServer::start().unwrap();
+    let conn = server.connect().unwrap();
+    conn.create_table("stmt_test", "id INT, name VARCHAR(600)").unwrap();
+
+    let mut insert = conn.prepare("INSERT INTO stmt_test VALUES (?, ?)").unwrap();
+    assert_eq!(insert.param_count(), 2);
+    assert_eq!(insert.execute(&[SQLType::Int(1), SQLType::VarChar("ann".to_string(), 60)]).unwrap().affected_rows, 1);
+    assert_eq!(insert.execute(&[SQLType::Int(2), SQLType::null()]).unwrap().affected_rows, 1);
+    assert_eq!(insert.execute(&[SQLType::Int(3), SQLType::VarChar(String::new(), 60)]).unwrap().affected_rows, 1);
+    //Longer than the client library's column buffer, so it has to be fetched again on its own.
+    let long = "x".repeat(512);
+    assert_eq!(insert.execute(&[SQLType::Int(4), SQLType::VarChar(long.clone(), 600)]).unwrap().affected_rows, 1);
+    assert!(insert.execute(&[SQLType::Int(5)]).is_err());
+    let unsupported = SQLType::Unsupported("NOW()".to_string(), "DATETIME".to_string(), ::types::SQLFieldType::DateTime);
+    assert!(insert.execute(&[SQLType::Int(5), unsupported]).is_err());
+    drop(insert);
+
+    let mut select = conn.prepare("SELECT id, name FROM stmt_test WHERE id >= ? ORDER BY id").unwrap();
+    assert_eq!(select.fields().len(), 2);
+    let rows: Vec<Vec<Option<String>>> = select.query(&[SQLType::Int(1)]).unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(rows, vec![vec![Some("1".to_string()), Some("ann".to_string())],
+                          vec![Some("2".to_string()), None],
+                          vec![Some("3".to_string()), Some(String::new())],
+                          vec![Some("4".to_string()), Some(long)]]);
+    //Binding NULL matches nothing.
+    assert_eq!(select.query(&[SQLType::null()]).unwrap().count(), 0);
+    //Without a cursor the rows are streamed, so another statement throws away the ones not read.
+    let mut rows = select.query(&[SQLType::Int(1)]).unwrap();
+    assert!(rows.next().unwrap().is_ok());
+    conn.raw_query_no_res("DO 1;").unwrap();
+    assert!(rows.next().unwrap().is_err());
+    drop(rows);
+    conn.drop_table("stmt_test").unwrap();
+}
+
+#[cfg(feature = "test-server")]
+#[test]
+fn test_statement_cursor() {
+    let server = ::testing::TestServer::start().unwrap();
+    let conn = server.connect().unwrap();
+    conn.create_table("cursor_test", "id INT").unwrap();
+    conn.raw_query_no_res("INSERT INTO cursor_test VALUES (1), (2), (3), (4), (5);").unwrap();
+
+    let mut select = conn.prepare("SELECT id FROM cursor_test ORDER BY id").unwrap();
+    select.cursor(2).unwrap();
+    let mut rows = select.query(&[]).unwrap();
+    assert_eq!(rows.next().unwrap().unwrap(), vec![Some("1".to_string())]);
+    //With the cursor open, other statements can run on the connection between fetches.
+    assert_eq!(conn.raw_query("SELECT COUNT(*) FROM cursor_test;", 1).unwrap(), vec![vec!["5".to_string()]]);
+    assert_eq!(rows.next().unwrap().unwrap(), vec![Some("2".to_string())]);
+    let mut other = conn.prepare("SELECT 'other'").unwrap();
+    assert_eq!(other.query(&[]).unwrap().next().unwrap().unwrap(), vec![Some("other".to_string())]);
+    let rest: Vec<Vec<Option<String>>> = rows.map(|r| r.unwrap()).collect();
+    assert_eq!(rest, vec![vec![Some("3".to_string())], vec![Some("4".to_string())], vec![Some("5".to_string())]]);
+
+    select.cursor(0).unwrap();
+    assert_eq!(select.query(&[]).unwrap().count(), 5);
+    drop(select);
+    drop(other);
+    conn.drop_table("cursor_test").unwrap();
+}
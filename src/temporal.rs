@@ -0,0 +1,179 @@
+//! Dates and times as the server sends them.
+//!
+//! These hold exactly what a `DATE`, `TIME`, `DATETIME` or `TIMESTAMP` column can, including
+//! the zero date `0000-00-00` and times past 24 hours, and nothing about time zones.  They read
+//! and write the server's text forms, such as "2024-02-29 13:05:00.25".
+
+use ::std::fmt;
+use ::std::str::FromStr;
+
+/// A `DATE`.  The zero date has every part 0.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    /// The year, 0 to 9999.
+    pub year: u16,
+    /// The month, 1 to 12, or 0 in a zero date.
+    pub month: u8,
+    /// The day of the month, 1 to 31, or 0 in a zero date.
+    pub day: u8,
+}
+
+/// A `TIME`, which is a duration of up to 838:59:59 either way rather than a time of day.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Time {
+    /// True for a negative time.
+    pub negative: bool,
+    /// The hours, up to 838.
+    pub hours: u32,
+    /// The minutes, 0 to 59.
+    pub minutes: u8,
+    /// The seconds, 0 to 59.
+    pub seconds: u8,
+    /// The microseconds, 0 to 999999.
+    pub micros: u32,
+}
+
+/// A `DATETIME` or `TIMESTAMP`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    /// The date.
+    pub date: Date,
+    /// The hour, 0 to 23.
+    pub hour: u8,
+    /// The minute, 0 to 59.
+    pub minute: u8,
+    /// The second, 0 to 59.
+    pub second: u8,
+    /// The microseconds, 0 to 999999.
+    pub micros: u32,
+}
+
+impl Date {
+    /// Makes a date from its parts.
+    pub fn new(year: u16, month: u8, day: u8) -> Self {
+        Date { year: year, month: month, day: day }
+    }
+
+    /// The date at the given time of day.
+    pub fn and_hms(self, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime { date: self, hour: hour, minute: minute, second: second, micros: 0 }
+    }
+}
+
+impl Time {
+    /// Makes a positive time from its parts.
+    pub fn new(hours: u32, minutes: u8, seconds: u8) -> Self {
+        Time { negative: false, hours: hours, minutes: minutes, seconds: seconds, micros: 0 }
+    }
+}
+
+impl DateTime {
+    /// Makes a date and time from their parts.
+    pub fn new(date: Date, hour: u8, minute: u8, second: u8, micros: u32) -> Self {
+        DateTime { date: date, hour: hour, minute: minute, second: second, micros: micros }
+    }
+}
+
+/// Reads a number of at most `max` from all of `text`, which has to be digits.
+fn number(text: &str, max: u32, what: &str) -> Result<u32, String> {
+    match u32::from_str(text) {
+        Ok(n) if n <= max && text.chars().all(|c| c.is_ascii_digit()) => Ok(n),
+        _ => Err(format!("Invalid {}: {}", what, text)),
+    }
+}
+
+/// Reads "HH:MM:SS" with an optional fraction of up to six digits, with at most `max_hours`.
+fn hms(text: &str, max_hours: u32, what: &str) -> Result<(u32, u8, u8, u32), String> {
+    let (whole, fraction) = match text.find('.') {
+        Some(i) => (&text[..i], &text[i + 1..]),
+        None    => (text, ""),
+    };
+    let parts: Vec<&str> = whole.split(':').collect();
+    if parts.len() != 3 || fraction.len() > 6 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return Err(format!("Invalid {}: {}", what, text));
+    }
+    let micros = if fraction.is_empty() { 0 } else { try!(number(fraction, 999_999, what)) * 10u32.pow(6 - fraction.len() as u32) };
+    Ok((try!(number(parts[0], max_hours, what)), try!(number(parts[1], 59, what)) as u8,
+        try!(number(parts[2], 59, what)) as u8, micros))
+}
+
+/// Writes the microseconds as a fraction, or nothing if there are none.
+fn write_micros(f: &mut fmt::Formatter, micros: u32) -> fmt::Result {
+    if micros == 0 { Ok(()) } else { write!(f, ".{:06}", micros) }
+}
+
+impl FromStr for Date {
+    type Err = String;
+    /// Reads "YYYY-MM-DD".
+    fn from_str(text: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = text.trim().split('-').collect();
+        if parts.len() != 3 || parts[0].len() != 4 {
+            return Err(format!("Invalid date: {}", text));
+        }
+        Ok(Date::new(try!(number(parts[0], 9999, "date")) as u16, try!(number(parts[1], 12, "date")) as u8,
+                     try!(number(parts[2], 31, "date")) as u8))
+    }
+}
+
+impl FromStr for Time {
+    type Err = String;
+    /// Reads "[-]HHH:MM:SS[.ffffff]".
+    fn from_str(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let negative = text.starts_with('-');
+        let (hours, minutes, seconds, micros) = try!(hms(if negative { &text[1..] } else { text }, 838, "time"));
+        Ok(Time { negative: negative, hours: hours, minutes: minutes, seconds: seconds, micros: micros })
+    }
+}
+
+impl FromStr for DateTime {
+    type Err = String;
+    /// Reads "YYYY-MM-DD HH:MM:SS[.ffffff]", with a space or a `T` in between, or only a date
+    /// for midnight.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        match text.find(|c: char| c == ' ' || c == 'T') {
+            Some(i) => {
+                let (hour, minute, second, micros) = try!(hms(&text[i + 1..], 23, "datetime"));
+                Ok(DateTime::new(try!(Date::from_str(&text[..i])), hour as u8, minute, second, micros))
+            },
+            None => Ok(try!(Date::from_str(text)).and_hms(0, 0, 0)),
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}{:02}:{:02}:{:02}", if self.negative { "-" } else { "" }, self.hours, self.minutes, self.seconds));
+        write_micros(f, self.micros)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} {:02}:{:02}:{:02}", self.date, self.hour, self.minute, self.second));
+        write_micros(f, self.micros)
+    }
+}
+
+#[test]
+fn test_temporal() {
+    assert_eq!("2024-02-29".parse::<Date>(), Ok(Date::new(2024, 2, 29)));
+    assert_eq!("0000-00-00".parse::<Date>().unwrap().to_string(), "0000-00-00");
+    assert!("2024-13-01".parse::<Date>().is_err());
+    let time: Time = "-838:59:59.5".parse().unwrap();
+    assert_eq!((time.negative, time.hours, time.micros), (true, 838, 500_000));
+    assert_eq!(time.to_string(), "-838:59:59.500000");
+    assert!("24:60:00".parse::<Time>().is_err());
+    let datetime: DateTime = "2024-02-29T13:05:00.25".parse().unwrap();
+    assert_eq!(datetime, DateTime::new(Date::new(2024, 2, 29), 13, 5, 0, 250_000));
+    assert_eq!(datetime.to_string(), "2024-02-29 13:05:00.250000");
+    assert_eq!("2024-02-29".parse::<DateTime>().unwrap().to_string(), "2024-02-29 00:00:00");
+    assert!("2024-02-29 24:00:00".parse::<DateTime>().is_err());
+}
@@ -0,0 +1,205 @@
+//! A throwaway server for tests, enabled with the `test-server` cargo feature.
+//!
+//! `TestServer::start()` initialises a new data directory with `mariadb-install-db`, starts
+//! `mariadbd` on it listening on a free port and a unix socket, and creates a `test` database.
+//! Dropping it stops the server and deletes the directory.  `root` logs in without a password.
+//!
+//! The programs are looked for in `MARIADB_BIN_DIR` if it is set, then on the `PATH` and in the
+//! usual system directories.  The old names, `mysql_install_db` and `mysqld`, work too.
+
+use ::connection::Connection;
+use ::options::ConnectOptions;
+use ::std::env;
+use ::std::fs;
+use ::std::net::{TcpListener, TcpStream};
+use ::std::path::{Path, PathBuf};
+use ::std::process::{Child, Command, Stdio};
+use ::std::sync::atomic::{AtomicUsize, Ordering};
+use ::std::thread;
+use ::std::time::{Duration, Instant};
+
+/// How long to wait for the server to accept connections.
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for the server to stop before killing it.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Where packages put the server, which often isn't on the `PATH` of a normal user.
+const SYSTEM_DIRS: [&str; 4] = ["/usr/sbin", "/usr/libexec", "/usr/local/sbin", "/usr/local/mysql/bin"];
+
+/// Numbers the servers of this process, so tests running at once get their own directory.
+static SERVERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A server running on a temporary data directory.  Stopped and deleted when dropped.
+pub struct TestServer {
+    dir: PathBuf,
+    socket: PathBuf,
+    port: u16,
+    child: Child,
+}
+
+/// Finds the first of the given programs.
+fn find_program(names: &[&str]) -> Result<PathBuf, String> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Some(dir) = env::var_os("MARIADB_BIN_DIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(path) = env::var_os("PATH") {
+        dirs.extend(env::split_paths(&path));
+    }
+    dirs.extend(SYSTEM_DIRS.iter().map(PathBuf::from));
+    for name in names {
+        if let Some(found) = dirs.iter().map(|d| d.join(name)).find(|p| p.is_file()) {
+            return Ok(found);
+        }
+    }
+    Err(format!("Can't find {}. Install the MariaDB server or set MARIADB_BIN_DIR.", names.join(" or ")))
+}
+
+/// A TCP port nothing is listening on right now.
+fn free_port() -> Result<u16, String> {
+    let listener = try!(TcpListener::bind("127.0.0.1:0").map_err(|e| format!("Can't find a free port: {}", e)));
+    listener.local_addr().map(|a| a.port()).map_err(|e| format!("Can't find a free port: {}", e))
+}
+
+/// The server refuses to run as root unless told to.
+fn user_args() -> Vec<String> {
+    if unsafe { ::libc::getuid() } == 0 {
+        vec!["--user=root".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// The end of the server's error log, for explaining why it didn't start.
+fn log_tail(path: &Path) -> String {
+    let log = fs::read_to_string(path).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().collect();
+    lines[lines.len().saturating_sub(20)..].join("\n")
+}
+
+impl TestServer {
+    /// Initialises and starts a new server, and waits until it accepts connections.
+    pub fn start() -> Result<TestServer, String> {
+        let install_db = try!(find_program(&["mariadb-install-db", "mysql_install_db"]));
+        let server = try!(find_program(&["mariadbd", "mysqld"]));
+        let dir = env::temp_dir().join(format!("mariadb-rs-test-{}-{}", ::std::process::id(), SERVERS.fetch_add(1, Ordering::SeqCst)));
+        let data = dir.join("data");
+        let _ = fs::remove_dir_all(&dir);
+        try!(fs::create_dir_all(&data).map_err(|e| format!("Can't create {}: {}", data.display(), e)));
+        let socket = dir.join("mysqld.sock");
+        let error_log = dir.join("error.log");
+
+        let installed = Command::new(&install_db)
+            .arg("--no-defaults")
+            .arg(format!("--datadir={}", data.display()))
+            .arg("--auth-root-authentication-method=normal")
+            .arg("--skip-test-db")
+            .args(user_args())
+            .stdin(Stdio::null())
+            .output();
+        match installed {
+            Ok(ref out) if out.status.success() => {},
+            Ok(out) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(format!("{} failed: {}", install_db.display(), String::from_utf8_lossy(&out.stderr)));
+            },
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(format!("Can't run {}: {}", install_db.display(), e));
+            },
+        }
+
+        let port = try!(free_port());
+        let child = Command::new(&server)
+            .arg("--no-defaults")
+            .arg(format!("--datadir={}", data.display()))
+            .arg(format!("--socket={}", socket.display()))
+            .arg(format!("--port={}", port))
+            .arg("--bind-address=127.0.0.1")
+            .arg(format!("--pid-file={}", dir.join("mysqld.pid").display()))
+            .arg(format!("--log-error={}", error_log.display()))
+            .args(user_args())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(e)    => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(format!("Can't run {}: {}", server.display(), e));
+            },
+        };
+        //From here on, dropping the server cleans up.
+        let mut test_server = TestServer { dir: dir, socket: socket, port: port, child: child };
+        try!(test_server.wait_until_ready(&error_log));
+        let conn = try!(test_server.connect_to(""));
+        try!(conn.raw_query_no_res("CREATE DATABASE IF NOT EXISTS test;"));
+        Ok(test_server)
+    }
+
+    fn wait_until_ready(&mut self, error_log: &Path) -> Result<(), String> {
+        let started = Instant::now();
+        loop {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                return Err(format!("The server exited with {}:\n{}", status, log_tail(error_log)));
+            }
+            if self.socket.exists() && TcpStream::connect(("127.0.0.1", self.port)).is_ok() {
+                return Ok(());
+            }
+            if started.elapsed() > START_TIMEOUT {
+                return Err(format!("The server didn't start within {} seconds:\n{}", START_TIMEOUT.as_secs(), log_tail(error_log)));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn connect_to(&self, database: &str) -> Result<Connection, String> {
+        self.options_for(database).connect()
+    }
+
+    fn options_for(&self, database: &str) -> ConnectOptions {
+        ConnectOptions::new("localhost", "root", "", database).unix_socket(&self.socket.to_string_lossy())
+    }
+
+    /// A URL for the `test` database over TCP, such as "mysql://root@127.0.0.1:40123/test".
+    pub fn url(&self) -> String {
+        format!("mysql://root@127.0.0.1:{}/test", self.port)
+    }
+
+    /// The TCP port the server listens on, on 127.0.0.1.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The unix socket the server listens on.
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Options for connecting to the `test` database as root over the unix socket.
+    pub fn options(&self) -> ConnectOptions {
+        self.options_for("test")
+    }
+
+    /// Connects to the `test` database as root.
+    pub fn connect(&self) -> Result<Connection, String> {
+        self.connect_to("test")
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        //Ask nicely first, so the server doesn't spend the next start recovering.
+        unsafe { ::libc::kill(self.child.id() as ::libc::pid_t, ::libc::SIGTERM) };
+        let started = Instant::now();
+        while let Ok(None) = self.child.try_wait() {
+            if started.elapsed() > STOP_TIMEOUT {
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -1,45 +1,128 @@
-//! Enums for the possible types for SQL.
+//! Enums for the possible types for SQL.  For values read from or sent to the server, see
+//! `Value`, which holds a value without its column type.
 //! Might remove in the future. (Not sure if this is important or not yet)
 
-use ::ffi::mysql::*;
+use ::query::escape_string;
+use ::std::str::FromStr;
 
-/// An enum of the possible field types when working with SQL.
+/// An enum of the possible field types when working with SQL.  The values are the type codes
+/// of the protocol, the same as the client library's `MYSQL_TYPE_*`.
 #[repr(u32)]
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum SQLFieldType {
-    Decimal    = MYSQL_TYPE_DECIMAL,
-    Tiny       = MYSQL_TYPE_TINY,
-    Short      = MYSQL_TYPE_SHORT,
-    Long       = MYSQL_TYPE_LONG,
-    Float      = MYSQL_TYPE_FLOAT,
-    Double     = MYSQL_TYPE_DOUBLE,
-    NULL       = MYSQL_TYPE_NULL,
-    Timestamp  = MYSQL_TYPE_TIMESTAMP,
-    LongLong   = MYSQL_TYPE_LONGLONG,
-    Int24      = MYSQL_TYPE_INT24,
-    Date       = MYSQL_TYPE_DATE,
-    Time       = MYSQL_TYPE_TIME,
-    DateTime   = MYSQL_TYPE_DATETIME,
-    Year       = MYSQL_TYPE_YEAR,
-    NewDate    = MYSQL_TYPE_NEWDATE,
-    VarChar    = MYSQL_TYPE_VARCHAR,
-    Bit        = MYSQL_TYPE_BIT,
-    TimeStamp2 = MYSQL_TYPE_TIMESTAMP2,
-    DateTime2  = MYSQL_TYPE_DATETIME2,
-    Time2      = MYSQL_TYPE_TIME2,
-    NewDecimal = MYSQL_TYPE_NEWDECIMAL,
-    Enum       = MYSQL_TYPE_ENUM,
-    Set        = MYSQL_TYPE_SET,
-    TinyBlob   = MYSQL_TYPE_TINY_BLOB,
-    MediumBlob = MYSQL_TYPE_MEDIUM_BLOB,
-    LongBlob   = MYSQL_TYPE_LONG_BLOB,
-    Blob       = MYSQL_TYPE_BLOB,
-    VarString  = MYSQL_TYPE_VAR_STRING,
-    String     = MYSQL_TYPE_STRING,
-    Geometry   = MYSQL_TYPE_GEOMETRY
+    /// MYSQL_TYPE_DECIMAL
+    Decimal    = 0,
+    /// MYSQL_TYPE_TINY
+    Tiny       = 1,
+    /// MYSQL_TYPE_SHORT
+    Short      = 2,
+    /// MYSQL_TYPE_LONG
+    Long       = 3,
+    /// MYSQL_TYPE_FLOAT
+    Float      = 4,
+    /// MYSQL_TYPE_DOUBLE
+    Double     = 5,
+    /// MYSQL_TYPE_NULL
+    NULL       = 6,
+    /// MYSQL_TYPE_TIMESTAMP
+    Timestamp  = 7,
+    /// MYSQL_TYPE_LONGLONG
+    LongLong   = 8,
+    /// MYSQL_TYPE_INT24
+    Int24      = 9,
+    /// MYSQL_TYPE_DATE
+    Date       = 10,
+    /// MYSQL_TYPE_TIME
+    Time       = 11,
+    /// MYSQL_TYPE_DATETIME
+    DateTime   = 12,
+    /// MYSQL_TYPE_YEAR
+    Year       = 13,
+    /// MYSQL_TYPE_NEWDATE
+    NewDate    = 14,
+    /// MYSQL_TYPE_VARCHAR
+    VarChar    = 15,
+    /// MYSQL_TYPE_BIT
+    Bit        = 16,
+    /// MYSQL_TYPE_TIMESTAMP2
+    TimeStamp2 = 17,
+    /// MYSQL_TYPE_DATETIME2
+    DateTime2  = 18,
+    /// MYSQL_TYPE_TIME2
+    Time2      = 19,
+    /// MYSQL_TYPE_NEWDECIMAL
+    NewDecimal = 246,
+    /// MYSQL_TYPE_ENUM
+    Enum       = 247,
+    /// MYSQL_TYPE_SET
+    Set        = 248,
+    /// MYSQL_TYPE_TINY_BLOB
+    TinyBlob   = 249,
+    /// MYSQL_TYPE_MEDIUM_BLOB
+    MediumBlob = 250,
+    /// MYSQL_TYPE_LONG_BLOB
+    LongBlob   = 251,
+    /// MYSQL_TYPE_BLOB
+    Blob       = 252,
+    /// MYSQL_TYPE_VAR_STRING
+    VarString  = 253,
+    /// MYSQL_TYPE_STRING
+    String     = 254,
+    /// MYSQL_TYPE_GEOMETRY
+    Geometry   = 255
+}
+
+impl SQLFieldType {
+    /// Get the field type from the raw value the client library reports for a column.
+    /// Returns None for types this crate does not know about.
+    pub fn from_raw(raw: u32) -> Option<SQLFieldType> {
+        match raw {
+            0   => Some(SQLFieldType::Decimal),
+            1   => Some(SQLFieldType::Tiny),
+            2   => Some(SQLFieldType::Short),
+            3   => Some(SQLFieldType::Long),
+            4   => Some(SQLFieldType::Float),
+            5   => Some(SQLFieldType::Double),
+            6   => Some(SQLFieldType::NULL),
+            7   => Some(SQLFieldType::Timestamp),
+            8   => Some(SQLFieldType::LongLong),
+            9   => Some(SQLFieldType::Int24),
+            10  => Some(SQLFieldType::Date),
+            11  => Some(SQLFieldType::Time),
+            12  => Some(SQLFieldType::DateTime),
+            13  => Some(SQLFieldType::Year),
+            14  => Some(SQLFieldType::NewDate),
+            15  => Some(SQLFieldType::VarChar),
+            16  => Some(SQLFieldType::Bit),
+            17  => Some(SQLFieldType::TimeStamp2),
+            18  => Some(SQLFieldType::DateTime2),
+            19  => Some(SQLFieldType::Time2),
+            246 => Some(SQLFieldType::NewDecimal),
+            247 => Some(SQLFieldType::Enum),
+            248 => Some(SQLFieldType::Set),
+            249 => Some(SQLFieldType::TinyBlob),
+            250 => Some(SQLFieldType::MediumBlob),
+            251 => Some(SQLFieldType::LongBlob),
+            252 => Some(SQLFieldType::Blob),
+            253 => Some(SQLFieldType::VarString),
+            254 => Some(SQLFieldType::String),
+            255 => Some(SQLFieldType::Geometry),
+            _   => None,
+        }
+    }
+    /// Returns true for the types that hold numbers.
+    pub fn is_numeric(&self) -> bool {
+        match *self {
+            SQLFieldType::Decimal | SQLFieldType::NewDecimal | SQLFieldType::Tiny | SQLFieldType::Short |
+            SQLFieldType::Long | SQLFieldType::LongLong | SQLFieldType::Int24 | SQLFieldType::Float |
+            SQLFieldType::Double | SQLFieldType::Year => true,
+            _                                         => false
+        }
+    }
 }
 
 /// An enum for wrapping the currently supported types.
+#[derive(Clone, Debug)]
 pub enum SQLType {
     /// Tiny, aka i8
     Tiny(i8),
@@ -79,6 +162,54 @@ impl ToString for SQLType {
 
 
 impl SQLType {
+    /// Get the value as a literal that can be placed directly in a query.
+    /// VarChar values are quoted and escaped.  Unsupported values are refused, as their text
+    /// would go into the query unescaped, except for `null()` and `column_default()`.  So are
+    /// NaN and infinite floats, which SQL has no literal for.
+    pub fn to_sql_literal(&self) -> Result<String, String> {
+        match *self {
+            SQLType::Float(e) if !e.is_finite()  => Err(format!("{} can't be sent to SQL.", e)),
+            SQLType::Double(e) if !e.is_finite() => Err(format!("{} can't be sent to SQL.", e)),
+            SQLType::VarChar(ref e, _) => Ok(format!("'{}'", escape_string(e))),
+            SQLType::Unsupported(ref e, _, SQLFieldType::NULL) => Ok(if e == "DEFAULT" { "DEFAULT" } else { "NULL" }.to_string()),
+            SQLType::Unsupported(ref e, ref name, _) =>
+                Err(format!("Can't place {} ({}) in a query, only numbers, strings and NULL.", e, name)),
+            _ => Ok(self.to_string()),
+        }
+    }
+    /// A NULL, for passing to SQL.
+    pub fn null() -> SQLType {
+        SQLType::Unsupported("NULL".to_string(), "NULL".to_string(), SQLFieldType::NULL)
+    }
+    /// The column's default value, for an INSERT or UPDATE.  Works like NULL in a prepared
+    /// statement.
+    pub fn column_default() -> SQLType {
+        SQLType::Unsupported("DEFAULT".to_string(), "DEFAULT".to_string(), SQLFieldType::NULL)
+    }
+    /// Parse a value of this type out of its text form, such as a field of a CSV file.
+    /// Only the type of `self` matters, not the value it holds.
+    pub fn parse_value(&self, text: &str) -> Result<SQLType, String> {
+        let trimmed = text.trim();
+        let invalid = |e: &dyn ToString| format!("'{}' is not a valid {}. Reason: {}", text, self.get_name_of_enum(), e.to_string());
+        match *self {
+            SQLType::Tiny(_)   => i8::from_str(trimmed).map(SQLType::Tiny).map_err(|e| invalid(&e)),
+            SQLType::Short(_)  => i16::from_str(trimmed).map(SQLType::Short).map_err(|e| invalid(&e)),
+            SQLType::Int(_)    => i32::from_str(trimmed).map(SQLType::Int).map_err(|e| invalid(&e)),
+            SQLType::Long(_)   => i64::from_str(trimmed).map(SQLType::Long).map_err(|e| invalid(&e)),
+            SQLType::Float(_)  => f32::from_str(trimmed).map(SQLType::Float).map_err(|e| invalid(&e)),
+            SQLType::Double(_) => f64::from_str(trimmed).map(SQLType::Double).map_err(|e| invalid(&e)),
+            SQLType::VarChar(_, size) => {
+                if size > 0 && text.chars().count() > size {
+                    Err(invalid(&format!("longer than {} characters", size)))
+                } else {
+                    Ok(SQLType::VarChar(text.to_string(), size))
+                }
+            },
+            //Sent as a string, which the server converts to the column's type.
+            SQLType::Unsupported(_, _, _) => Ok(SQLType::VarChar(text.to_string(), 0)),
+        }
+    }
+    /// Get the name of the type, as used in a `create table` statement.
     pub fn get_name_of_enum(&self) -> String {
         //These may be wrong, need to test yet.
         match *self {
@@ -92,6 +223,7 @@ impl SQLType {
             SQLType::Unsupported(_, ref e, _) => e.clone()
         }
     }
+    /// Get the MySQL field type that this maps to.
     pub fn get_field_type(&self) -> SQLFieldType {
         match *self {
             SQLType::Tiny(_)                  => SQLFieldType::Tiny,
@@ -104,66 +236,77 @@ impl SQLType {
             SQLType::Unsupported(_, _, e)     => e
         }
     }
+    /// Returns true if this is a Tiny.
     pub fn is_tiny(&self) -> bool {
         match *self {
             SQLType::Tiny(_) => true,
             _                => false
         }
     }
+    /// Returns true if this is a Short.
     pub fn is_short(&self) -> bool {
         match *self {
             SQLType::Short(_) => true,
             _                 => false
         }
     }
+    /// Returns true if this is an Int.
     pub fn is_int(&self) -> bool {
         match *self {
             SQLType::Int(_) => true,
             _               => false
         }
     }
+    /// Returns true if this is a Long.
     pub fn is_long(&self) -> bool {
         match *self {
             SQLType::Long(_) => true,
             _                => false
         }
     }
+    /// Returns true if this is a Float.
     pub fn is_float(&self) -> bool {
         match *self {
             SQLType::Float(_) => true,
             _                 => false
         }
     }
+    /// Returns true if this is a Double.
     pub fn is_double(&self) -> bool {
         match *self {
             SQLType::Double(_) => true,
             _                  => false
         }
     }
+    /// Returns true if this is a VarChar.
     pub fn is_varchar(&self) -> bool {
         match *self {
             SQLType::VarChar(_, _) => true,
             _                      => false
         }
     }
+    /// Returns true if this is an Unsupported type.
     pub fn is_unsupported(&self) -> bool {
         match *self {
             SQLType::Unsupported(_, _, _) => true,
             _                             => false
         }
     }
+    /// Get the value if this is a Tiny.
     pub fn get_i8(&self) -> Option<i8> {
         match *self {
             SQLType::Tiny(i) => Some(i),
             _ => None
         }
     }
+    /// Get the value if this is an Int.
     pub fn get_i32(&self) -> Option<i32> {
         match *self {
             SQLType::Int(i) => Some(i),
             _ => None
         }
     }
+    /// Get the value if this is a VarChar.
     pub fn get_string(&self) -> Option<String> {
         match *self {
             SQLType::VarChar(ref s, _) => Some(s.clone()),
@@ -177,13 +320,15 @@ impl SQLType {
 impl ::std::str::FromStr for SQLType {
     type Err = String;
     fn from_str(words: &str) -> Result<Self, Self::Err> {
-        let v: Vec<String> = words.split('(').map(|e| e.to_string()).collect();
-        if v.len() != 2 {
-            return Err("Didn't split to 2".to_string());
-        }
-        //v[1].pop();
-        let name = v[0].clone();
-        //let size = usize::from_str(&v[1]);
+        //Either "name(size) extra" or, for types like float and double, just "name extra".
+        let words = words.trim().to_lowercase();
+        let (name, size) = match words.find('(') {
+            Some(i) => {
+                let size = words[i + 1..].split(')').next().and_then(|e| usize::from_str(e).ok());
+                (words[..i].to_string(), size.unwrap_or(0))
+            },
+            None => (words.split(' ').next().unwrap_or("").to_string(), 0),
+        };
         if name == "tinyint" {
             Ok(SQLType::Tiny(0))
         } else if name == "smallint" {
@@ -197,7 +342,7 @@ impl ::std::str::FromStr for SQLType {
         } else if name == "double" {
             Ok(SQLType::Double(0.0))
         } else if name == "varchar" {
-            Ok(SQLType::VarChar(name, 0))
+            Ok(SQLType::VarChar(String::new(), size))
         } else {
             Err(format!("Invalid name.  {}", name))
         }
@@ -0,0 +1,434 @@
+//! Values of single cells, and converting them to and from Rust types.
+//!
+//! `Value` is what a cell holds at runtime, without the column type `SQLType` also carries.
+//! `ToSql` turns a Rust value into one, and `FromSql` reads one back, checking that it fits.
+//! Both are implemented for the integer and float types, `bool`, `String`, `Vec<u8>`, `Option`
+//! for NULL, and `Date`, `Time`, `DateTime` and `Decimal`.  Implement them for your own types,
+//! such as a newtype around an id, by converting to and from one of those.
+//!
+//! Rows come from the server as text, so `FromSql` also reads numbers, dates and decimals out
+//! of `Text`, and `Value::from_column()` turns a cell into the value its column type says it is.
+
+use ::decimal::Decimal;
+use ::query::escape_string;
+use ::result::Field;
+use ::temporal::{Date, DateTime, Time};
+use ::types::SQLFieldType;
+use ::std::convert::TryFrom;
+use ::std::fmt;
+use ::std::str::{self, FromStr};
+
+/// The value of one cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// NULL.
+    Null,
+    /// A signed integer.
+    Int(i64),
+    /// An unsigned integer, for `UNSIGNED` columns.
+    UInt(u64),
+    /// A `FLOAT`.
+    Float(f32),
+    /// A `DOUBLE`.
+    Double(f64),
+    /// An exact number, for `DECIMAL` columns.
+    Decimal(Decimal),
+    /// Text, for character columns.
+    Text(String),
+    /// Binary data, for `BINARY`, `BLOB` and `BIT` columns.
+    Bytes(Vec<u8>),
+    /// A `DATE`.
+    Date(Date),
+    /// A `TIME`.
+    Time(Time),
+    /// A `DATETIME` or `TIMESTAMP`.
+    DateTime(DateTime),
+}
+
+impl Value {
+    /// Returns true if this is NULL.
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    /// Converts the value to `T`, see `FromSql`.
+    pub fn get<T: FromSql>(&self) -> Result<T, String> {
+        T::from_sql(self)
+    }
+
+    /// The value as a literal that can be placed directly in a query.  Text and dates are
+    /// quoted and escaped, and binary data is written in hex.  NaN and infinite floats are
+    /// refused, as SQL has no literal for them.
+    pub fn to_sql_literal(&self) -> Result<String, String> {
+        match *self {
+            Value::Null => Ok("NULL".to_string()),
+            Value::Float(n) if !n.is_finite()  => Err(format!("{} can't be sent to SQL.", n)),
+            Value::Double(n) if !n.is_finite() => Err(format!("{} can't be sent to SQL.", n)),
+            Value::Text(ref s) => Ok(format!("'{}'", escape_string(s))),
+            Value::Bytes(ref b) => {
+                let hex: String = b.iter().map(|byte| format!("{:02X}", byte)).collect();
+                Ok(format!("X'{}'", hex))
+            },
+            Value::Date(_) | Value::Time(_) | Value::DateTime(_) => Ok(format!("'{}'", self)),
+            _ => Ok(self.to_string()),
+        }
+    }
+
+    /// The value of a cell as read from a text result by `Rows::next_raw()`, given its column.
+    /// Binary columns keep their bytes, and other text is read as UTF-8.  Numbers, decimals
+    /// and dates that don't parse, such as a zero date from a server that allows invalid dates,
+    /// are kept as text.
+    pub fn from_column(field: &Field, cell: Option<&[u8]>) -> Value {
+        let bytes = match cell {
+            Some(bytes) => bytes,
+            None        => return Value::Null,
+        };
+        if field.holds_bytes() {
+            return Value::Bytes(bytes.to_vec());
+        }
+        let text = &*String::from_utf8_lossy(bytes);
+        let parsed = match field.field_type {
+            SQLFieldType::Tiny | SQLFieldType::Short | SQLFieldType::Int24 | SQLFieldType::Long |
+            SQLFieldType::LongLong | SQLFieldType::Year =>
+                if field.is_unsigned() { text.parse().ok().map(Value::UInt) } else { text.parse().ok().map(Value::Int) },
+            SQLFieldType::Float => text.parse().ok().map(Value::Float),
+            SQLFieldType::Double => text.parse().ok().map(Value::Double),
+            SQLFieldType::Decimal | SQLFieldType::NewDecimal => text.parse().ok().map(Value::Decimal),
+            SQLFieldType::Date | SQLFieldType::NewDate => text.parse().ok().map(Value::Date),
+            SQLFieldType::Time | SQLFieldType::Time2 => text.parse().ok().map(Value::Time),
+            SQLFieldType::DateTime | SQLFieldType::DateTime2 | SQLFieldType::Timestamp | SQLFieldType::TimeStamp2 =>
+                text.parse().ok().map(Value::DateTime),
+            SQLFieldType::NULL => Some(Value::Null),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| Value::Text(text.to_string()))
+    }
+
+    /// What kind of value this is, for error messages.
+    fn kind(&self) -> &'static str {
+        match *self {
+            Value::Null        => "NULL",
+            Value::Int(_)      => "an integer",
+            Value::UInt(_)     => "an unsigned integer",
+            Value::Float(_)    => "a float",
+            Value::Double(_)   => "a double",
+            Value::Decimal(_)  => "a decimal",
+            Value::Text(_)     => "text",
+            Value::Bytes(_)    => "binary data",
+            Value::Date(_)     => "a date",
+            Value::Time(_)     => "a time",
+            Value::DateTime(_) => "a datetime",
+        }
+    }
+
+    /// The text of a Text value, or of Bytes that are UTF-8.
+    fn as_text(&self) -> Option<&str> {
+        match *self {
+            Value::Text(ref s)  => Some(s.trim()),
+            Value::Bytes(ref b) => str::from_utf8(b).ok().map(|s| s.trim()),
+            _                   => None,
+        }
+    }
+
+    /// The error for a value that can't be converted to `target`.
+    fn mismatch(&self, target: &str) -> String {
+        match self.as_text() {
+            Some(text) => format!("Can't convert '{}' to {}.", text, target),
+            None       => format!("Can't convert {} to {}.", self.kind(), target),
+        }
+    }
+}
+
+/// Writes the value as the server would show it, with NULL as "NULL" and binary data that
+/// isn't UTF-8 with replacement characters.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null            => f.write_str("NULL"),
+            Value::Int(n)          => write!(f, "{}", n),
+            Value::UInt(n)         => write!(f, "{}", n),
+            Value::Float(n)        => write!(f, "{}", n),
+            Value::Double(n)       => write!(f, "{}", n),
+            Value::Decimal(ref d)  => write!(f, "{}", d),
+            Value::Text(ref s)     => f.write_str(s),
+            Value::Bytes(ref b)    => f.write_str(&String::from_utf8_lossy(b)),
+            Value::Date(ref d)     => write!(f, "{}", d),
+            Value::Time(ref t)     => write!(f, "{}", t),
+            Value::DateTime(ref d) => write!(f, "{}", d),
+        }
+    }
+}
+
+/// Converting a Rust value to a `Value`, to send to the server.
+pub trait ToSql {
+    /// The value to send.
+    fn to_sql(&self) -> Value;
+}
+
+/// Converting a `Value` read from the server to a Rust value.
+pub trait FromSql: Sized {
+    /// The Rust value, or an error if `value` is NULL for a type that can't hold it, or doesn't
+    /// fit.
+    fn from_sql(value: &Value) -> Result<Self, String>;
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl FromSql for Value {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn to_sql(&self) -> Value {
+        (**self).to_sql()
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> Value {
+        self.as_ref().map_or(Value::Null, |v| v.to_sql())
+    }
+}
+
+/// NULL is None, and anything else has to convert to `T`.
+impl<T: FromSql> FromSql for Option<T> {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match *value {
+            Value::Null => Ok(None),
+            _           => T::from_sql(value).map(Some),
+        }
+    }
+}
+
+macro_rules! integer_to_sql {
+    ($variant:ident, $wide:ty: $($t:ty),*) => {$(
+        impl ToSql for $t {
+            fn to_sql(&self) -> Value {
+                Value::$variant(*self as $wide)
+            }
+        }
+    )*}
+}
+
+integer_to_sql!(Int, i64: i8, i16, i32, i64, isize);
+integer_to_sql!(UInt, u64: u8, u16, u32, u64, usize);
+
+/// Sent as a decimal if it doesn't fit in 64 bits.
+impl ToSql for i128 {
+    fn to_sql(&self) -> Value {
+        i64::try_from(*self).map(Value::Int).unwrap_or_else(|_| Value::Decimal(Decimal::from(*self)))
+    }
+}
+
+/// Sent as a decimal if it doesn't fit in 64 bits.
+impl ToSql for u128 {
+    fn to_sql(&self) -> Value {
+        u64::try_from(*self).map(Value::UInt).unwrap_or_else(|_| Value::Decimal(Decimal::from(*self)))
+    }
+}
+
+macro_rules! integer_from_sql {
+    ($($t:ty),*) => {$(
+        /// Integers, decimals without a fraction, and text of an integer, as long as they fit.
+        impl FromSql for $t {
+            fn from_sql(value: &Value) -> Result<Self, String> {
+                let converted = match *value {
+                    Value::Int(n)         => <$t>::try_from(n).ok(),
+                    Value::UInt(n)        => <$t>::try_from(n).ok(),
+                    Value::Decimal(ref d) => d.integer_part().and_then(|n| n.parse().ok()),
+                    _                     => value.as_text().and_then(|n| n.parse().ok()),
+                };
+                converted.ok_or_else(|| value.mismatch(stringify!($t)))
+            }
+        }
+    )*}
+}
+
+integer_from_sql!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl ToSql for f32 {
+    fn to_sql(&self) -> Value {
+        Value::Float(*self)
+    }
+}
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> Value {
+        Value::Double(*self)
+    }
+}
+
+/// Any number, rounded to the closest float, and text of a number.
+impl FromSql for f64 {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        let converted = match *value {
+            Value::Int(n)         => Some(n as f64),
+            Value::UInt(n)        => Some(n as f64),
+            Value::Float(n)       => Some(n as f64),
+            Value::Double(n)      => Some(n),
+            Value::Decimal(ref d) => Some(d.to_f64()),
+            _                     => value.as_text().and_then(|n| n.parse().ok()),
+        };
+        converted.ok_or_else(|| value.mismatch("f64"))
+    }
+}
+
+/// Any number, rounded to the closest float, and text of a number.
+impl FromSql for f32 {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match *value {
+            Value::Float(n) => Ok(n),
+            _               => f64::from_sql(value).map(|n| n as f32).map_err(|_| value.mismatch("f32")),
+        }
+    }
+}
+
+/// Sent as 1 or 0, as `BOOLEAN` columns are `TINYINT(1)`.
+impl ToSql for bool {
+    fn to_sql(&self) -> Value {
+        Value::Int(*self as i64)
+    }
+}
+
+/// Integers, where anything but 0 is true, and the text "true" and "false".
+impl FromSql for bool {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match value.as_text() {
+            Some(text) if text.eq_ignore_ascii_case("true")  => Ok(true),
+            Some(text) if text.eq_ignore_ascii_case("false") => Ok(false),
+            _ => i64::from_sql(value).or_else(|_| u64::from_sql(value).map(|n| n as i64))
+                     .map(|n| n != 0).map_err(|_| value.mismatch("bool")),
+        }
+    }
+}
+
+impl ToSql for str {
+    fn to_sql(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+/// Any value but NULL, as the server would show it.  Binary data has to be UTF-8.
+impl FromSql for String {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match *value {
+            Value::Null         => Err(value.mismatch("String")),
+            Value::Text(ref s)  => Ok(s.clone()),
+            Value::Bytes(ref b) => String::from_utf8(b.clone()).map_err(|_| "Can't convert binary data that isn't UTF-8 to String.".to_string()),
+            _                   => Ok(value.to_string()),
+        }
+    }
+}
+
+impl ToSql for [u8] {
+    fn to_sql(&self) -> Value {
+        Value::Bytes(self.to_vec())
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn to_sql(&self) -> Value {
+        Value::Bytes(self.clone())
+    }
+}
+
+/// Any value but NULL, with text as its UTF-8 bytes.
+impl FromSql for Vec<u8> {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match *value {
+            Value::Null         => Err(value.mismatch("Vec<u8>")),
+            Value::Bytes(ref b) => Ok(b.clone()),
+            _                   => Ok(value.to_string().into_bytes()),
+        }
+    }
+}
+
+macro_rules! parsed_sql {
+    ($t:ident, $name:expr, $($from:pat => $to:expr),*) => {
+        impl ToSql for $t {
+            fn to_sql(&self) -> Value {
+                Value::$t(self.clone())
+            }
+        }
+
+        impl FromSql for $t {
+            fn from_sql(value: &Value) -> Result<Self, String> {
+                let converted = match *value {
+                    Value::$t(ref v) => Some(v.clone()),
+                    $($from => $to,)*
+                    _ => value.as_text().and_then(|text| $t::from_str(text).ok()),
+                };
+                converted.ok_or_else(|| value.mismatch($name))
+            }
+        }
+    }
+}
+
+parsed_sql!(Date, "Date",);
+parsed_sql!(Time, "Time",);
+parsed_sql!(DateTime, "DateTime", Value::Date(d) => Some(d.and_hms(0, 0, 0)));
+parsed_sql!(Decimal, "Decimal",
+            Value::Int(n) => Some(Decimal::from(n)),
+            Value::UInt(n) => Some(Decimal::from(n)),
+            Value::Float(n) => Decimal::from_str(&n.to_string()).ok(),
+            Value::Double(n) => Decimal::from_str(&n.to_string()).ok());
+
+#[test]
+fn test_value() {
+    #[derive(Debug, PartialEq)]
+    struct UserId(u32);
+    impl ToSql for UserId {
+        fn to_sql(&self) -> Value {
+            self.0.to_sql()
+        }
+    }
+    impl FromSql for UserId {
+        fn from_sql(value: &Value) -> Result<Self, String> {
+            u32::from_sql(value).map(UserId)
+        }
+    }
+
+    assert_eq!(Value::Int(300).get::<i16>(), Ok(300));
+    assert!(Value::Int(300).get::<u8>().is_err());
+    assert!(Value::Int(-1).get::<u64>().is_err());
+    assert_eq!(Value::Text(" 42 ".to_string()).get::<i64>(), Ok(42));
+    assert_eq!(Value::Decimal("7.00".parse().unwrap()).get::<u128>(), Ok(7));
+    assert_eq!(Value::Text("2.5".to_string()).get::<f32>(), Ok(2.5));
+    assert_eq!(Value::Text("1".to_string()).get::<bool>(), Ok(true));
+    assert_eq!(Value::Null.get::<Option<String>>(), Ok(None));
+    assert!(Value::Null.get::<String>().is_err());
+    assert_eq!(Value::Double(1.5).get::<String>(), Ok("1.5".to_string()));
+    assert_eq!(Value::Date(Date::new(2024, 1, 2)).get::<DateTime>().unwrap().to_string(), "2024-01-02 00:00:00");
+    assert_eq!(u128::MAX.to_sql(), Value::Decimal(Decimal::from(u128::MAX)));
+    assert_eq!(Some("it's").to_sql().to_sql_literal().unwrap(), "'it''s'");
+    assert_eq!(vec![0u8, 255].to_sql().to_sql_literal().unwrap(), "X'00FF'");
+    assert!(Value::Double(f64::NAN).to_sql_literal().is_err());
+    assert!(Value::Float(f32::INFINITY).to_sql_literal().is_err());
+    assert_eq!(None::<i32>.to_sql(), Value::Null);
+    assert_eq!(UserId(9).to_sql(), Value::UInt(9));
+    assert_eq!(Value::Text("9".to_string()).get::<UserId>(), Ok(UserId(9)));
+    assert_eq!(Value::Text("x".to_string()).get::<UserId>(), Err("Can't convert 'x' to u32.".to_string()));
+
+    let field = |field_type, flags, charset| Field { name: "c".to_string(), table: String::new(), field_type: field_type,
+                                                     length: 0, decimals: 0, flags: flags, charset: charset };
+    assert_eq!(Value::from_column(&field(SQLFieldType::LongLong, 32, 63), Some(&b"18446744073709551615"[..])),
+               Value::UInt(u64::MAX));
+    assert_eq!(Value::from_column(&field(SQLFieldType::NewDecimal, 0, 63), Some(&b"-1.50"[..])), Value::Decimal("-1.50".parse().unwrap()));
+    assert_eq!(Value::from_column(&field(SQLFieldType::Date, 0, 63), Some(&b"2024-02-30"[..])), Value::Date(Date::new(2024, 2, 30)));
+    assert_eq!(Value::from_column(&field(SQLFieldType::Blob, 0, 63), Some(&b"ab"[..])), Value::Bytes(b"ab".to_vec()));
+    assert_eq!(Value::from_column(&field(SQLFieldType::Blob, 0, 63), Some(&[0xff][..])), Value::Bytes(vec![0xff]));
+    assert_eq!(Value::from_column(&field(SQLFieldType::VarString, 0, 45), Some(&b"ab"[..])), Value::Text("ab".to_string()));
+    assert_eq!(Value::from_column(&field(SQLFieldType::VarString, 0, 45), None), Value::Null);
+}